@@ -0,0 +1,343 @@
+use crate::{
+    credentials::{Cache, Chain},
+    http,
+    web_identity::{encode, Credentials},
+};
+use chrono::Utc;
+use futures::{future, Future};
+use rusoto_core::{
+    credential::{AwsCredentials, CredentialsError, ProvideAwsCredentials},
+    request::HttpResponse,
+    signature::SignedRequest,
+    Client, Region, RusotoError,
+};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use std::str::FromStr;
+
+/// an account to diff stacks in, reached by assuming a role
+#[derive(Debug, Clone, PartialEq)]
+pub struct Account {
+    pub role_arn: String,
+    /// regions to diff in, in place of those given on the command line
+    pub regions: Vec<Region>,
+}
+
+impl Account {
+    /// the account id of the role, falling back on the whole ARN
+    pub fn id(&self) -> &str {
+        self.role_arn
+            .split(':')
+            .nth(4)
+            .filter(|id| !id.is_empty())
+            .unwrap_or(&self.role_arn)
+    }
+}
+
+/// parses the ARN of an IAM role, as in `arn:aws:iam::123456789012:role/name`
+pub fn parse_role_arn(s: &str) -> Result<String, String> {
+    let fields = s.splitn(6, ':').collect::<Vec<_>>();
+    match fields.as_slice() {
+        ["arn", partition, "iam", "", account, resource]
+            if !partition.is_empty()
+                && account.len() == 12
+                && account.chars().all(|c| c.is_ascii_digit())
+                && resource
+                    .strip_prefix("role/")
+                    .is_some_and(|name| !name.is_empty()) =>
+        {
+            Ok(s.to_string())
+        }
+        _ => Err(format!(
+            "invalid role ARN `{}`: expected arn:aws:iam::<account-id>:role/<name>",
+            s
+        )),
+    }
+}
+
+/// parses a list of accounts, one role ARN per line optionally followed by
+/// comma-separated regions. blank lines and lines starting with `#` are skipped
+pub fn parse(body: &str) -> Result<Vec<Account>, String> {
+    body.lines()
+        .enumerate()
+        .map(|(index, line)| (index + 1, line.trim()))
+        .filter(|(_, line)| !line.is_empty() && !line.starts_with('#'))
+        .map(|(number, line)| {
+            let mut fields = line.split_whitespace();
+            let role_arn = parse_role_arn(fields.next().unwrap_or_default())
+                .map_err(|err| format!("invalid account on line {}: {}", number, err))?;
+            let regions = fields
+                .flat_map(|field| field.split(','))
+                .filter(|region| !region.is_empty())
+                .map(|region| {
+                    Region::from_str(region).map_err(|_| {
+                        format!(
+                            "invalid account on line {}: unknown region `{}`",
+                            number, region
+                        )
+                    })
+                })
+                .collect::<Result<Vec<_>, _>>()?;
+            Ok(Account { role_arn, regions })
+        })
+        .collect()
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "PascalCase")]
+struct AssumeRoleResponse {
+    assume_role_result: AssumeRoleResult,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "PascalCase")]
+struct AssumeRoleResult {
+    credentials: Credentials,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "PascalCase")]
+struct GetCallerIdentityResponse {
+    get_caller_identity_result: CallerIdentity,
+}
+
+/// who a set of credentials acts as
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+#[serde(rename_all(deserialize = "PascalCase"))]
+pub struct CallerIdentity {
+    pub account: String,
+    pub arn: String,
+}
+
+fn parse_response<T: DeserializeOwned>(body: &[u8]) -> Result<T, CredentialsError> {
+    serde_xml_rs::from_reader::<_, T>(body).map_err(|err| CredentialsError::new(err.to_string()))
+}
+
+fn handle<T>(
+    response: HttpResponse
+) -> Box<dyn Future<Item = T, Error = RusotoError<CredentialsError>> + Send>
+where
+    T: DeserializeOwned + Send + 'static,
+{
+    Box::new(response.buffer().from_err().and_then(|response| {
+        if !response.status.is_success() {
+            return Err(RusotoError::Service(CredentialsError::new(format!(
+                "{} {}",
+                response.status,
+                String::from_utf8_lossy(&response.body).trim()
+            ))));
+        }
+        parse_response(&response.body).map_err(RusotoError::Service)
+    }))
+}
+
+/// a signed request for an STS action through the regional endpoint of `region`
+fn sts_request(
+    region: &Region,
+    params: String,
+) -> SignedRequest {
+    let mut request = SignedRequest::new("POST", "sts", region, "/");
+    request.set_content_type("application/x-www-form-urlencoded".into());
+    request.set_payload(Some(format!("Version=2011-06-15&{}", params)));
+    request
+}
+
+/// the reason an STS request failed, without rusoto's wrapping
+fn reason(err: RusotoError<CredentialsError>) -> String {
+    match err {
+        RusotoError::Service(err) | RusotoError::Credentials(err) => err.message,
+        other => other.to_string(),
+    }
+}
+
+/// asks STS who `credentials` act as, which fails fast when there are no valid credentials
+pub fn caller_identity<P>(
+    credentials: P,
+    region: &Region,
+) -> Box<dyn Future<Item = CallerIdentity, Error = CredentialsError> + Send>
+where
+    P: ProvideAwsCredentials + Send + Sync + 'static,
+    P::Future: Send,
+{
+    let dispatcher = match http::dispatcher() {
+        Ok(dispatcher) => dispatcher,
+        Err(err) => return Box::new(future::err(CredentialsError::new(err.to_string()))),
+    };
+    Box::new(
+        Client::new_with(credentials, dispatcher)
+            .sign_and_dispatch(
+                sts_request(region, "Action=GetCallerIdentity".into()),
+                handle::<GetCallerIdentityResponse>,
+            )
+            .map(|response| response.get_caller_identity_result)
+            .map_err(|err| CredentialsError::new(reason(err))),
+    )
+}
+
+/// credentials of a role assumed with credentials from a chain. unlike the chain's,
+/// assumed credentials are reused until shortly before they expire, so that diffing
+/// many stacks in an account assumes its role once
+#[derive(Debug, Clone)]
+pub struct AssumeRole {
+    role_arn: String,
+    session_name: String,
+    region: Region,
+    source: Chain,
+    cached: Cache,
+}
+
+impl AssumeRole {
+    /// assumes `role_arn` through the regional STS endpoint of `region`
+    pub fn new(
+        role_arn: &str,
+        region: Region,
+        source: Chain,
+    ) -> Self {
+        AssumeRole {
+            role_arn: role_arn.into(),
+            session_name: format!("cliff-{}", Utc::now().timestamp()),
+            region,
+            source,
+            cached: Cache::default(),
+        }
+    }
+}
+
+impl ProvideAwsCredentials for AssumeRole {
+    type Future = Box<dyn Future<Item = AwsCredentials, Error = CredentialsError> + Send>;
+
+    fn credentials(&self) -> Self::Future {
+        if let Some(credentials) = self.cached.fresh() {
+            return Box::new(future::ok(credentials));
+        }
+        let dispatcher = match http::dispatcher() {
+            Ok(dispatcher) => dispatcher,
+            Err(err) => return Box::new(future::err(CredentialsError::new(err.to_string()))),
+        };
+        let request = sts_request(
+            &self.region,
+            format!(
+                "Action=AssumeRole&RoleArn={}&RoleSessionName={}",
+                encode(&self.role_arn),
+                encode(&self.session_name)
+            ),
+        );
+        let role_arn = self.role_arn.clone();
+        let cached = self.cached.clone();
+        Box::new(
+            Client::new_with(self.source.clone(), dispatcher)
+                .sign_and_dispatch(request, handle::<AssumeRoleResponse>)
+                .map_err(move |err| {
+                    CredentialsError::new(format!(
+                        "Failed to assume role {}: {}",
+                        role_arn,
+                        reason(err)
+                    ))
+                })
+                .map(move |response| {
+                    let credentials = AwsCredentials::from(response.assume_role_result.credentials);
+                    cached.store(&credentials);
+                    credentials
+                }),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_reads_roles_and_regions() {
+        assert_eq!(
+            parse(
+                "# production\narn:aws:iam::123456789012:role/cliff\n\n  arn:aws:iam::210987654321:role/cliff us-east-1,eu-west-1\n"
+            ),
+            Ok(vec![
+                Account {
+                    role_arn: "arn:aws:iam::123456789012:role/cliff".into(),
+                    regions: Vec::new(),
+                },
+                Account {
+                    role_arn: "arn:aws:iam::210987654321:role/cliff".into(),
+                    regions: vec![Region::UsEast1, Region::EuWest1],
+                },
+            ])
+        );
+        assert_eq!(
+            parse("arn:aws:iam::123456789012:role/cliff")
+                .ok()
+                .and_then(|accounts| accounts.first().map(|account| account.id().to_string())),
+            Some("123456789012".into())
+        );
+    }
+
+    #[test]
+    fn parse_role_arn_requires_iam_roles() {
+        assert_eq!(
+            parse_role_arn("arn:aws:iam::123456789012:role/cfn/deploy"),
+            Ok("arn:aws:iam::123456789012:role/cfn/deploy".into())
+        );
+        assert!(parse_role_arn("arn:aws-cn:iam::123456789012:role/deploy").is_ok());
+        assert!(parse_role_arn("arn:aws:iam::123456789012:user/deploy").is_err());
+        assert!(parse_role_arn("arn:aws:iam::1234:role/deploy").is_err());
+        assert!(parse_role_arn("arn:aws:iam::123456789012:role/").is_err());
+        assert!(parse_role_arn("deploy").is_err());
+    }
+
+    #[test]
+    fn parse_rejects_invalid_lines() {
+        assert!(parse("123456789012").is_err());
+        assert!(parse("arn:aws:iam::123456789012:role/cliff mars-1").is_err());
+        assert!(parse("arn:aws:iam::1234:role/cliff").is_err());
+    }
+
+    #[test]
+    fn parse_response_reads_caller_identity() {
+        let body = r#"<GetCallerIdentityResponse xmlns="https://sts.amazonaws.com/doc/2011-06-15/">
+  <GetCallerIdentityResult>
+    <Arn>arn:aws:sts::123456789012:assumed-role/deploy/ci</Arn>
+    <UserId>AROAEXAMPLE:ci</UserId>
+    <Account>123456789012</Account>
+  </GetCallerIdentityResult>
+  <ResponseMetadata>
+    <RequestId>1</RequestId>
+  </ResponseMetadata>
+</GetCallerIdentityResponse>"#;
+        assert_eq!(
+            parse_response::<GetCallerIdentityResponse>(body.as_bytes())
+                .map(|response| response.get_caller_identity_result)
+                .ok(),
+            Some(CallerIdentity {
+                account: "123456789012".into(),
+                arn: "arn:aws:sts::123456789012:assumed-role/deploy/ci".into(),
+            })
+        );
+    }
+
+    #[test]
+    fn parse_credentials_reads_sts_response() {
+        let body = r#"<AssumeRoleResponse xmlns="https://sts.amazonaws.com/doc/2011-06-15/">
+  <AssumeRoleResult>
+    <AssumedRoleUser>
+      <Arn>arn:aws:sts::123456789012:assumed-role/cliff/cliff-1</Arn>
+      <AssumedRoleId>AROAEXAMPLE:cliff-1</AssumedRoleId>
+    </AssumedRoleUser>
+    <Credentials>
+      <AccessKeyId>ASIAEXAMPLE</AccessKeyId>
+      <SecretAccessKey>secret</SecretAccessKey>
+      <SessionToken>token</SessionToken>
+      <Expiration>2030-01-01T00:00:00Z</Expiration>
+    </Credentials>
+  </AssumeRoleResult>
+</AssumeRoleResponse>"#;
+        let credentials = parse_response::<AssumeRoleResponse>(body.as_bytes())
+            .map(|response| AwsCredentials::from(response.assume_role_result.credentials));
+        assert_eq!(
+            credentials
+                .as_ref()
+                .ok()
+                .map(|credentials| credentials.aws_access_key_id()),
+            Some("ASIAEXAMPLE")
+        );
+    }
+}
@@ -0,0 +1,204 @@
+use crate::changeset::RenderedChange;
+use serde::Deserialize;
+use std::fmt;
+
+/// the changes of a stack as recorded by a previous run's `--output json`
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+struct Recorded {
+    stack_name: String,
+    region: String,
+    /// `complete`, `failed`, or `other`. a stack whose change set didn't complete has no
+    /// changes to compare against
+    status: String,
+    #[serde(default)]
+    changes: Vec<RenderedChange>,
+}
+
+/// a previous run's JSON output, to compare the changes of a new run against
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Baseline {
+    stacks: Vec<Recorded>,
+}
+
+/// a way a stack's changes differ from its baseline
+#[derive(Debug, Clone, PartialEq)]
+pub enum Delta {
+    /// a change the baseline didn't have
+    New(RenderedChange),
+    /// a change of the baseline that no longer appears
+    Resolved(RenderedChange),
+    /// a change that now requires replacement
+    Replacement(RenderedChange),
+    /// a change whose action or replacement differs from the baseline's
+    Changed {
+        before: RenderedChange,
+        after: RenderedChange,
+    },
+}
+
+fn describe(change: &RenderedChange) -> String {
+    format!(
+        "{}{} of {} ({})",
+        change.action.to_lowercase(),
+        if change.replacement {
+            " with replacement"
+        } else {
+            ""
+        },
+        change.logical_resource_id,
+        change.resource_type
+    )
+}
+
+impl fmt::Display for Delta {
+    fn fmt(
+        &self,
+        f: &mut fmt::Formatter,
+    ) -> fmt::Result {
+        match self {
+            Delta::New(change) => write!(f, "new {}", describe(change)),
+            Delta::Resolved(change) => write!(f, "resolved {}", describe(change)),
+            Delta::Replacement(change) => write!(
+                f,
+                "{} of {} ({}) now requires replacement",
+                change.action.to_lowercase(),
+                change.logical_resource_id,
+                change.resource_type
+            ),
+            Delta::Changed { before, after } => {
+                write!(f, "{} is now {}", describe(before), describe(after))
+            }
+        }
+    }
+}
+
+impl Baseline {
+    /// parses the output of `--output json`, one JSON object per stack per line
+    pub fn parse(body: &str) -> Result<Self, String> {
+        let stacks = body
+            .lines()
+            .enumerate()
+            .filter(|(_, line)| !line.trim().is_empty())
+            .map(|(index, line)| {
+                serde_json::from_str::<Recorded>(line)
+                    .map_err(|err| format!("invalid baseline on line {}: {}", index + 1, err))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(Baseline { stacks })
+    }
+
+    /// the recorded changes of a stack, or None when the baseline didn't diff it or its
+    /// change set didn't complete
+    pub fn changes(
+        &self,
+        stack_name: &str,
+        region: &str,
+    ) -> Option<&[RenderedChange]> {
+        self.stacks
+            .iter()
+            .find(|stack| stack.stack_name == stack_name && stack.region == region)
+            .filter(|stack| stack.status == "complete")
+            .map(|stack| stack.changes.as_slice())
+    }
+}
+
+/// how a stack's changes differ from its baseline's, matched by logical id: new and
+/// differing changes in order, followed by resolved ones
+pub fn compare(
+    baseline: &[RenderedChange],
+    changes: &[RenderedChange],
+) -> Vec<Delta> {
+    let find = |changes: &[RenderedChange], id: &str| {
+        changes
+            .iter()
+            .find(|change| change.logical_resource_id == id)
+            .cloned()
+    };
+    let mut deltas = changes
+        .iter()
+        .filter_map(|change| match find(baseline, &change.logical_resource_id) {
+            None => Some(Delta::New(change.clone())),
+            Some(before) if before.action != change.action => Some(Delta::Changed {
+                before,
+                after: change.clone(),
+            }),
+            Some(before) if change.replacement && !before.replacement => {
+                Some(Delta::Replacement(change.clone()))
+            }
+            Some(before) if before.replacement != change.replacement => Some(Delta::Changed {
+                before,
+                after: change.clone(),
+            }),
+            Some(_) => None,
+        })
+        .collect::<Vec<_>>();
+    deltas.extend(
+        baseline
+            .iter()
+            .filter(|before| find(changes, &before.logical_resource_id).is_none())
+            .cloned()
+            .map(Delta::Resolved),
+    );
+    deltas
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn change(
+        action: &str,
+        logical_resource_id: &str,
+        replacement: bool,
+    ) -> RenderedChange {
+        RenderedChange {
+            action: action.into(),
+            resource_type: "AWS::S3::Bucket".into(),
+            logical_resource_id: logical_resource_id.into(),
+            replacement,
+            ..RenderedChange::default()
+        }
+    }
+
+    #[test]
+    fn parse_reads_json_output() -> Result<(), String> {
+        let baseline = Baseline::parse(
+            "{\"stack_name\":\"test\",\"region\":\"us-east-1\",\"status\":\"complete\",\"changes\":[{\"action\":\"Add\",\"resource_type\":\"AWS::S3::Bucket\",\"logical_resource_id\":\"Bucket\",\"physical_resource_id\":\"\",\"scope\":[],\"replacement\":false,\"properties\":[]}],\"summary\":{\"add\":1,\"modify\":0,\"remove\":0,\"import\":0,\"replace\":0}}\n\n{\"stack_name\":\"other\",\"region\":\"us-east-1\",\"status\":\"failed\",\"reason\":\"no changes\"}\n",
+        )?;
+        assert_eq!(
+            baseline.changes("test", "us-east-1"),
+            Some(&[change("Add", "Bucket", false)][..])
+        );
+        assert_eq!(baseline.changes("other", "us-east-1"), None);
+        assert_eq!(baseline.changes("test", "eu-west-1"), None);
+        assert!(Baseline::parse("add=1 modify=0").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn compare_reports_deltas() {
+        let deltas = compare(
+            &[
+                change("Modify", "Bucket", false),
+                change("Modify", "Logs", false),
+                change("Add", "Queue", false),
+                change("Remove", "Old", false),
+            ],
+            &[
+                change("Modify", "Bucket", true),
+                change("Modify", "Logs", false),
+                change("Modify", "Queue", false),
+                change("Add", "Topic", false),
+            ],
+        );
+        assert_eq!(
+            deltas.iter().map(ToString::to_string).collect::<Vec<_>>(),
+            vec![
+                "modify of Bucket (AWS::S3::Bucket) now requires replacement",
+                "add of Queue (AWS::S3::Bucket) is now modify of Queue (AWS::S3::Bucket)",
+                "new add of Topic (AWS::S3::Bucket)",
+                "resolved remove of Old (AWS::S3::Bucket)",
+            ]
+        );
+    }
+}
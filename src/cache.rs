@@ -0,0 +1,86 @@
+use rusoto_core::Region;
+use std::{
+    fs, io,
+    path::PathBuf,
+    time::{Duration, SystemTime},
+};
+
+/// a local, time-bounded store of previously fetched stack templates
+#[derive(Debug, Clone)]
+pub struct Cache {
+    dir: PathBuf,
+    ttl: Duration,
+}
+
+impl Cache {
+    pub fn new(
+        dir: PathBuf,
+        ttl: Duration,
+    ) -> Self {
+        Cache { dir, ttl }
+    }
+
+    fn path(
+        &self,
+        stack_name: &str,
+        region: &Region,
+    ) -> PathBuf {
+        self.dir
+            .join(format!("{}-{}.template", region.name(), stack_name))
+    }
+
+    /// returns a cached template body if one exists and has not yet expired
+    pub fn get(
+        &self,
+        stack_name: &str,
+        region: &Region,
+    ) -> Option<String> {
+        let path = self.path(stack_name, region);
+        let age = fs::metadata(&path)
+            .and_then(|meta| meta.modified())
+            .ok()
+            .and_then(|modified| SystemTime::now().duration_since(modified).ok())?;
+        if age > self.ttl {
+            log::debug!("cached template {} expired", path.display());
+            return None;
+        }
+        fs::read_to_string(path).ok()
+    }
+
+    pub fn put(
+        &self,
+        stack_name: &str,
+        region: &Region,
+        template_body: &str,
+    ) -> io::Result<()> {
+        fs::create_dir_all(&self.dir)?;
+        fs::write(self.path(stack_name, region), template_body)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::error::Error as StdError;
+
+    #[test]
+    fn cache_serves_fresh_entries() -> Result<(), Box<dyn StdError>> {
+        let dir = tempfile::tempdir()?;
+        let cache = Cache::new(dir.path().join("nested"), Duration::from_secs(60));
+        assert_eq!(cache.get("test", &Region::UsEast1), None);
+        cache.put("test", &Region::UsEast1, "body")?;
+        assert_eq!(cache.get("test", &Region::UsEast1), Some("body".into()));
+        assert_eq!(cache.get("test", &Region::UsWest2), None);
+        Ok(())
+    }
+
+    #[test]
+    fn cache_ignores_expired_entries() -> Result<(), Box<dyn StdError>> {
+        let dir = tempfile::tempdir()?;
+        let cache = Cache::new(dir.path().into(), Duration::from_secs(0));
+        cache.put("test", &Region::UsEast1, "body")?;
+        std::thread::sleep(Duration::from_millis(10));
+        assert_eq!(cache.get("test", &Region::UsEast1), None);
+        Ok(())
+    }
+}
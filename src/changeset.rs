@@ -0,0 +1,903 @@
+use crate::{policy::StatementChange, properties::PropertyChange};
+use rusoto_cloudformation::{Change, DescribeChangeSetOutput};
+use serde::{Deserialize, Serialize};
+use std::{cmp::Ordering, collections::BTreeMap, fmt, str::FromStr};
+
+/// the final state of a described change set
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(tag = "status", content = "reason", rename_all = "snake_case")]
+pub enum Status {
+    Complete,
+    /// a failed change set with CloudFormation's stated reason
+    Failed(String),
+    /// any other status CloudFormation reported
+    Other(String),
+}
+
+/// a single resource change within a change set
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RenderedChange {
+    pub action: String,
+    pub resource_type: String,
+    pub logical_resource_id: String,
+    pub physical_resource_id: String,
+    pub scope: Vec<String>,
+    pub replacement: bool,
+    /// property value changes, when requested
+    pub properties: Vec<PropertyChange>,
+    /// policy statement changes of IAM resources, when requested
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub policies: Vec<StatementChange>,
+}
+
+impl From<Change> for RenderedChange {
+    /// changes of kinds other than `Resource` carry no resource change, and are named by
+    /// their kind in place of a resource type
+    fn from(change: Change) -> Self {
+        let c = change.resource_change.unwrap_or_default();
+        RenderedChange {
+            action: c.action.unwrap_or_default(),
+            resource_type: c.resource_type.or(change.type_).unwrap_or_default(),
+            logical_resource_id: c.logical_resource_id.unwrap_or_default(),
+            physical_resource_id: c.physical_resource_id.unwrap_or_default(),
+            scope: c.scope.unwrap_or_default(),
+            replacement: c.replacement.unwrap_or_default() == "True",
+            properties: Vec::new(),
+            policies: Vec::new(),
+        }
+    }
+}
+
+/// counts of changes by action
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct Summary {
+    pub add: usize,
+    pub modify: usize,
+    pub remove: usize,
+    pub import: usize,
+    /// changes that require replacement, regardless of action
+    pub replace: usize,
+    /// the highest severity of any change
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub severity: Option<Severity>,
+}
+
+impl<'a> From<&'a [RenderedChange]> for Summary {
+    fn from(changes: &'a [RenderedChange]) -> Self {
+        changes
+            .iter()
+            .fold(Summary::default(), |mut summary, change| {
+                summary.count(change);
+                summary
+            })
+    }
+}
+
+impl Summary {
+    fn count(
+        &mut self,
+        change: &RenderedChange,
+    ) {
+        match change.action.as_str() {
+            "Add" => self.add += 1,
+            "Modify" => self.modify += 1,
+            "Remove" => self.remove += 1,
+            "Import" => self.import += 1,
+            _ => (),
+        }
+        if change.replacement {
+            self.replace += 1;
+        }
+        self.severity = self.severity.max(Some(Severity::of(change)));
+    }
+
+    /// true when there is at least one change
+    pub fn changed(&self) -> bool {
+        self.add + self.modify + self.remove + self.import + self.replace > 0
+    }
+}
+
+/// counts of changes to resources of one type
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct TypeSummary {
+    pub resource_type: String,
+    #[serde(flatten)]
+    pub summary: Summary,
+}
+
+/// counts of changes by resource type, ordered by type
+pub fn by_type(changes: &[RenderedChange]) -> Vec<TypeSummary> {
+    let mut types = BTreeMap::<&str, Summary>::new();
+    for change in changes {
+        types
+            .entry(&change.resource_type)
+            .or_default()
+            .count(change);
+    }
+    types
+        .into_iter()
+        .map(|(resource_type, summary)| TypeSummary {
+            resource_type: resource_type.into(),
+            summary,
+        })
+        .collect()
+}
+
+/// resource types whose removal or replacement discards stored data
+const STATEFUL_TYPES: &[&str] = &[
+    "AWS::DynamoDB::Table",
+    "AWS::EC2::Volume",
+    "AWS::EFS::FileSystem",
+    "AWS::ElastiCache::CacheCluster",
+    "AWS::ElastiCache::ReplicationGroup",
+    "AWS::Elasticsearch::Domain",
+    "AWS::Kinesis::Stream",
+    "AWS::Logs::LogGroup",
+    "AWS::RDS::DBCluster",
+    "AWS::RDS::DBInstance",
+    "AWS::S3::Bucket",
+    "AWS::SQS::Queue",
+];
+
+/// a condition that a change may meet, used to gate on a change set's contents
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Condition {
+    Add,
+    Modify,
+    Remove,
+    Import,
+    Replace,
+    /// removal or replacement of a resource that stores data
+    DataLoss,
+}
+
+impl Condition {
+    /// true when a change meets this condition
+    pub fn matches(
+        &self,
+        change: &RenderedChange,
+    ) -> bool {
+        match self {
+            Condition::Add => change.action == "Add",
+            Condition::Modify => change.action == "Modify",
+            Condition::Remove => change.action == "Remove",
+            Condition::Import => change.action == "Import",
+            Condition::Replace => change.replacement,
+            Condition::DataLoss => {
+                (change.action == "Remove" || change.replacement)
+                    && STATEFUL_TYPES.contains(&change.resource_type.as_str())
+            }
+        }
+    }
+}
+
+impl FromStr for Condition {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim() {
+            "add" => Ok(Condition::Add),
+            "modify" => Ok(Condition::Modify),
+            "remove" => Ok(Condition::Remove),
+            "import" => Ok(Condition::Import),
+            "replace" => Ok(Condition::Replace),
+            "data-loss" => Ok(Condition::DataLoss),
+            other => Err(format!(
+                "invalid condition `{}`. expected one of add, modify, remove, import, replace, or data-loss",
+                other
+            )),
+        }
+    }
+}
+
+impl fmt::Display for Condition {
+    fn fmt(
+        &self,
+        f: &mut fmt::Formatter,
+    ) -> fmt::Result {
+        write!(
+            f,
+            "{}",
+            match self {
+                Condition::Add => "add",
+                Condition::Modify => "modify",
+                Condition::Remove => "remove",
+                Condition::Import => "import",
+                Condition::Replace => "replace",
+                Condition::DataLoss => "data-loss",
+            }
+        )
+    }
+}
+
+/// how risky a change is, ordered from least to most
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Severity {
+    /// additions and imports
+    Info,
+    /// modifications in place
+    Low,
+    /// removals and replacements
+    High,
+    /// removals and replacements of resources that store data
+    Critical,
+}
+
+impl Severity {
+    /// the severity of a change
+    pub fn of(change: &RenderedChange) -> Self {
+        if Condition::DataLoss.matches(change) {
+            Severity::Critical
+        } else if change.replacement || change.action == "Remove" {
+            Severity::High
+        } else if change.action == "Modify" {
+            Severity::Low
+        } else {
+            Severity::Info
+        }
+    }
+}
+
+impl FromStr for Severity {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim() {
+            "info" => Ok(Severity::Info),
+            "low" => Ok(Severity::Low),
+            "high" => Ok(Severity::High),
+            "critical" => Ok(Severity::Critical),
+            other => Err(format!(
+                "invalid severity `{}`. expected one of info, low, high, or critical",
+                other
+            )),
+        }
+    }
+}
+
+impl fmt::Display for Severity {
+    fn fmt(
+        &self,
+        f: &mut fmt::Formatter,
+    ) -> fmt::Result {
+        write!(
+            f,
+            "{}",
+            match self {
+                Severity::Info => "info",
+                Severity::Low => "low",
+                Severity::High => "high",
+                Severity::Critical => "critical",
+            }
+        )
+    }
+}
+
+/// orderings of a change set's changes
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum SortBy {
+    #[default]
+    Action,
+    /// resource type
+    Type,
+    /// logical resource id
+    Id,
+    /// most destructive first: data loss, then removals, replacements, modifications,
+    /// imports, and additions
+    Risk,
+}
+
+impl SortBy {
+    fn risk(change: &RenderedChange) -> u8 {
+        if Condition::DataLoss.matches(change) {
+            return 0;
+        }
+        match change.action.as_str() {
+            "Remove" => 1,
+            _ if change.replacement => 2,
+            "Modify" => 3,
+            "Import" => 4,
+            "Add" => 5,
+            _ => 6,
+        }
+    }
+
+    fn compare(
+        &self,
+        a: &RenderedChange,
+        b: &RenderedChange,
+    ) -> Ordering {
+        match self {
+            SortBy::Action => a.action.cmp(&b.action),
+            SortBy::Type => a.resource_type.cmp(&b.resource_type),
+            SortBy::Id => Ordering::Equal,
+            SortBy::Risk => SortBy::risk(a).cmp(&SortBy::risk(b)),
+        }
+        .then_with(|| a.logical_resource_id.cmp(&b.logical_resource_id))
+        .then_with(|| a.physical_resource_id.cmp(&b.physical_resource_id))
+    }
+}
+
+impl FromStr for SortBy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim() {
+            "action" => Ok(SortBy::Action),
+            "type" => Ok(SortBy::Type),
+            "id" => Ok(SortBy::Id),
+            "risk" => Ok(SortBy::Risk),
+            other => Err(format!(
+                "invalid sort `{}`. expected one of action, type, id, or risk",
+                other
+            )),
+        }
+    }
+}
+
+/// sorts changes, breaking ties by logical then physical id
+pub fn sort(
+    changes: &mut [RenderedChange],
+    by: SortBy,
+) {
+    changes.sort_by(|a, b| by.compare(a, b));
+}
+
+/// the prefixes of reasons CloudFormation gives for failing change sets without changes
+const NO_CHANGES_REASONS: &[&str] = &[
+    "The submitted information didn't contain changes",
+    "No updates are to be performed",
+];
+
+/// matches `*` against any run of characters and `?` against any single character
+fn glob(
+    pattern: &str,
+    text: &str,
+) -> bool {
+    let (pattern, text) = (pattern.as_bytes(), text.as_bytes());
+    let (mut p, mut t) = (0, 0);
+    let mut star = None;
+    while t < text.len() {
+        match pattern.get(p) {
+            Some(b'*') => {
+                star = Some((p, t));
+                p += 1;
+            }
+            Some(&c) if c == b'?' || c == text[t] => {
+                p += 1;
+                t += 1;
+            }
+            _ => match star {
+                Some((star_p, star_t)) => {
+                    p = star_p + 1;
+                    t = star_t + 1;
+                    star = Some((star_p, star_t + 1));
+                }
+                _ => return false,
+            },
+        }
+    }
+    pattern[p..].iter().all(|&c| c == b'*')
+}
+
+/// selects changes by logical id from comma-separated globs like `Api*,*Table`.
+/// globs prefixed with `!` exclude matching ids
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ResourceFilter {
+    include: Vec<String>,
+    exclude: Vec<String>,
+}
+
+impl FromStr for ResourceFilter {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut filter = ResourceFilter::default();
+        for pattern in s.split(',').map(str::trim) {
+            let (patterns, glob) = match pattern.strip_prefix('!') {
+                Some(exclude) => (&mut filter.exclude, exclude),
+                None => (&mut filter.include, pattern),
+            };
+            if glob.is_empty() {
+                return Err(format!("invalid resource filter {:?}", s));
+            }
+            patterns.push(glob.into());
+        }
+        Ok(filter)
+    }
+}
+
+impl ResourceFilter {
+    /// true when a logical id matches an included glob, or there are only exclusions,
+    /// and matches no excluded glob
+    pub fn matches(
+        &self,
+        logical_resource_id: &str,
+    ) -> bool {
+        (self.include.is_empty()
+            || self
+                .include
+                .iter()
+                .any(|pattern| glob(pattern, logical_resource_id)))
+            && !self
+                .exclude
+                .iter()
+                .any(|pattern| glob(pattern, logical_resource_id))
+    }
+}
+
+/// resource types a change set may change, as globs like `AWS::IAM::*`
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct TypeGuard {
+    allow: Vec<String>,
+    deny: Vec<String>,
+}
+
+/// why a type guard rejects a change
+#[derive(Debug, Clone, PartialEq)]
+pub enum TypeViolation {
+    /// the change's resource type matches this denied glob
+    Denied(String),
+    /// the change's resource type matches none of the allowed globs
+    NotAllowed,
+}
+
+impl TypeGuard {
+    /// a guard rejecting changes to types matching any of `deny`, and, when `allow` isn't
+    /// empty, changes to types matching none of `allow`
+    pub fn new(
+        allow: Vec<String>,
+        deny: Vec<String>,
+    ) -> Self {
+        TypeGuard { allow, deny }
+    }
+
+    /// why a change isn't permitted, if it isn't
+    pub fn check(
+        &self,
+        change: &RenderedChange,
+    ) -> Option<TypeViolation> {
+        if let Some(pattern) = self
+            .deny
+            .iter()
+            .find(|pattern| glob(pattern, &change.resource_type))
+        {
+            return Some(TypeViolation::Denied(pattern.clone()));
+        }
+        if !self.allow.is_empty()
+            && !self
+                .allow
+                .iter()
+                .any(|pattern| glob(pattern, &change.resource_type))
+        {
+            return Some(TypeViolation::NotAllowed);
+        }
+        None
+    }
+}
+
+fn is_zero(n: &usize) -> bool {
+    *n == 0
+}
+
+/// the structured result of a described change set
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct DiffResult {
+    #[serde(flatten)]
+    pub status: Status,
+    /// resource changes, sorted by action then logical and physical id unless sorted otherwise
+    pub changes: Vec<RenderedChange>,
+    pub summary: Summary,
+    /// the number of changes left out by a resource filter
+    #[serde(skip_serializing_if = "is_zero")]
+    pub hidden: usize,
+}
+
+impl DiffResult {
+    /// keeps only changes to resources the filter matches, summarizing those that remain
+    pub fn filter(
+        &mut self,
+        filter: &ResourceFilter,
+    ) {
+        let total = self.changes.len();
+        self.changes
+            .retain(|change| filter.matches(&change.logical_resource_id));
+        self.hidden += total - self.changes.len();
+        self.summary = Summary::from(self.changes.as_slice());
+    }
+}
+
+/// computes a diff result from a described change set
+pub fn diff_changeset(changeset: DescribeChangeSetOutput) -> DiffResult {
+    let reason = changeset.status_reason.unwrap_or_default();
+    let status = match changeset.status.as_deref().unwrap_or_default() {
+        complete if complete.ends_with("_COMPLETE") => Status::Complete,
+        // CloudFormation fails change sets without changes, though nothing went wrong
+        "FAILED" if NO_CHANGES_REASONS.iter().any(|no| reason.starts_with(no)) => Status::Complete,
+        "FAILED" => Status::Failed(reason),
+        other => Status::Other(other.into()),
+    };
+    let mut changes = match status {
+        Status::Complete => changeset
+            .changes
+            .unwrap_or_default()
+            .into_iter()
+            .map(RenderedChange::from)
+            .collect::<Vec<_>>(),
+        _ => Vec::new(),
+    };
+    sort(&mut changes, SortBy::default());
+    let summary = Summary::from(changes.as_slice());
+    DiffResult {
+        status,
+        changes,
+        summary,
+        hidden: 0,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rusoto_cloudformation::ResourceChange;
+
+    fn change(
+        action: &str,
+        logical_resource_id: &str,
+        replacement: &str,
+    ) -> Change {
+        Change {
+            type_: Some("Resource".into()),
+            resource_change: Some(ResourceChange {
+                action: Some(action.into()),
+                logical_resource_id: Some(logical_resource_id.into()),
+                physical_resource_id: Some(format!("{}-physical", logical_resource_id)),
+                resource_type: Some("AWS::DynamoDB::Table".into()),
+                replacement: Some(replacement.into()),
+                scope: Some(vec!["Properties".into()]),
+                ..ResourceChange::default()
+            }),
+        }
+    }
+
+    #[test]
+    fn diff_changeset_sorts_and_summarizes() {
+        let result = diff_changeset(DescribeChangeSetOutput {
+            status: Some("CREATE_COMPLETE".into()),
+            changes: Some(vec![
+                change("Remove", "Old", "False"),
+                change("Modify", "Table", "True"),
+                change("Add", "New", "False"),
+            ]),
+            ..DescribeChangeSetOutput::default()
+        });
+        assert_eq!(result.status, Status::Complete);
+        assert_eq!(
+            result
+                .changes
+                .iter()
+                .map(|c| c.logical_resource_id.as_str())
+                .collect::<Vec<_>>(),
+            vec!["New", "Table", "Old"]
+        );
+        assert_eq!(
+            result.changes[1],
+            RenderedChange {
+                action: "Modify".into(),
+                resource_type: "AWS::DynamoDB::Table".into(),
+                logical_resource_id: "Table".into(),
+                physical_resource_id: "Table-physical".into(),
+                scope: vec!["Properties".into()],
+                replacement: true,
+                properties: Vec::new(),
+                policies: Vec::new(),
+            }
+        );
+        assert_eq!(
+            result.summary,
+            Summary {
+                add: 1,
+                modify: 1,
+                remove: 1,
+                import: 0,
+                replace: 1,
+                severity: Some(Severity::Critical),
+            }
+        );
+    }
+
+    #[test]
+    fn by_type_counts_changes_per_type() {
+        let rendered = |action: &str, resource_type: &str, replacement: bool| RenderedChange {
+            action: action.into(),
+            resource_type: resource_type.into(),
+            replacement,
+            ..RenderedChange::default()
+        };
+        let types = by_type(&[
+            rendered("Modify", "AWS::Lambda::Function", false),
+            rendered("Modify", "AWS::S3::Bucket", true),
+            rendered("Add", "AWS::Lambda::Function", false),
+        ]);
+        assert_eq!(
+            types
+                .iter()
+                .map(|t| (
+                    t.resource_type.as_str(),
+                    t.summary.add,
+                    t.summary.modify,
+                    t.summary.replace
+                ))
+                .collect::<Vec<_>>(),
+            vec![
+                ("AWS::Lambda::Function", 1, 1, 0),
+                ("AWS::S3::Bucket", 0, 1, 1)
+            ]
+        );
+        assert!(by_type(&[]).is_empty());
+    }
+
+    #[test]
+    fn severity_ranks_changes() {
+        let rendered = |action: &str, resource_type: &str, replacement: bool| RenderedChange {
+            action: action.into(),
+            resource_type: resource_type.into(),
+            replacement,
+            ..RenderedChange::default()
+        };
+        assert_eq!(
+            [
+                rendered("Add", "AWS::SNS::Topic", false),
+                rendered("Import", "AWS::S3::Bucket", false),
+                rendered("Modify", "AWS::SNS::Topic", false),
+                rendered("Modify", "AWS::SNS::Topic", true),
+                rendered("Remove", "AWS::SNS::Topic", false),
+                rendered("Modify", "AWS::S3::Bucket", true),
+                rendered("Remove", "AWS::S3::Bucket", false),
+            ]
+            .iter()
+            .map(Severity::of)
+            .collect::<Vec<_>>(),
+            vec![
+                Severity::Info,
+                Severity::Info,
+                Severity::Low,
+                Severity::High,
+                Severity::High,
+                Severity::Critical,
+                Severity::Critical,
+            ]
+        );
+        assert!(Severity::High > Severity::Low);
+        assert_eq!("critical".parse(), Ok(Severity::Critical));
+        assert!("severe".parse::<Severity>().is_err());
+    }
+
+    #[test]
+    fn diff_changeset_sorts_deterministically() {
+        let ids = |changes: Vec<Change>| {
+            diff_changeset(DescribeChangeSetOutput {
+                status: Some("CREATE_COMPLETE".into()),
+                changes: Some(changes),
+                ..DescribeChangeSetOutput::default()
+            })
+            .changes
+            .into_iter()
+            .map(|c| c.logical_resource_id)
+            .collect::<Vec<_>>()
+        };
+        let expected = vec!["A", "B", "C", "D"];
+        assert_eq!(
+            ids(vec![
+                change("Modify", "D", "False"),
+                change("Add", "B", "False"),
+                change("Modify", "C", "False"),
+                change("Add", "A", "False"),
+            ]),
+            expected
+        );
+        assert_eq!(
+            ids(vec![
+                change("Add", "A", "False"),
+                change("Modify", "C", "False"),
+                change("Add", "B", "False"),
+                change("Modify", "D", "False"),
+            ]),
+            expected
+        );
+    }
+
+    #[test]
+    fn glob_matches_wildcards() {
+        assert!(glob("Api*", "ApiGateway"));
+        assert!(glob("*Table", "UsersTable"));
+        assert!(glob("*", ""));
+        assert!(glob("Q?eue", "Queue"));
+        assert!(glob("A*b*c", "AxxbYbc"));
+        assert!(!glob("Api*", "MyApi"));
+        assert!(!glob("*Table", "TableRole"));
+    }
+
+    #[test]
+    fn type_guard_checks_resource_types() {
+        let change = |resource_type: &str| RenderedChange {
+            resource_type: resource_type.into(),
+            ..RenderedChange::default()
+        };
+        let guard = TypeGuard::new(
+            vec!["AWS::Lambda::*".into(), "AWS::IAM::*".into()],
+            vec!["AWS::IAM::User".into()],
+        );
+        assert_eq!(guard.check(&change("AWS::Lambda::Function")), None);
+        assert_eq!(guard.check(&change("AWS::IAM::Role")), None);
+        assert_eq!(
+            guard.check(&change("AWS::IAM::User")),
+            Some(TypeViolation::Denied("AWS::IAM::User".into()))
+        );
+        assert_eq!(
+            guard.check(&change("AWS::KMS::Key")),
+            Some(TypeViolation::NotAllowed)
+        );
+        assert_eq!(TypeGuard::default().check(&change("AWS::KMS::Key")), None);
+    }
+
+    #[test]
+    fn filter_keeps_matching_changes() -> Result<(), String> {
+        let mut result = diff_changeset(DescribeChangeSetOutput {
+            status: Some("CREATE_COMPLETE".into()),
+            changes: Some(vec![
+                change("Add", "ApiGateway", "False"),
+                change("Modify", "UsersTable", "True"),
+                change("Remove", "LogsTable", "False"),
+                change("Add", "Queue", "False"),
+            ]),
+            ..DescribeChangeSetOutput::default()
+        });
+        result.filter(&"Api*,*Table,!Logs*".parse()?);
+        assert_eq!(
+            result
+                .changes
+                .iter()
+                .map(|c| c.logical_resource_id.as_str())
+                .collect::<Vec<_>>(),
+            vec!["ApiGateway", "UsersTable"]
+        );
+        assert_eq!(result.hidden, 2);
+        assert_eq!(
+            result.summary,
+            Summary {
+                add: 1,
+                modify: 1,
+                replace: 1,
+                severity: Some(Severity::Critical),
+                ..Summary::default()
+            }
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn filter_parses_exclusions_alone() -> Result<(), String> {
+        let filter = "!Logs*".parse::<ResourceFilter>()?;
+        assert!(filter.matches("Queue"));
+        assert!(!filter.matches("LogsGroup"));
+        assert!("Api*,".parse::<ResourceFilter>().is_err());
+        assert!("!".parse::<ResourceFilter>().is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn condition_matches_changes() {
+        let table = RenderedChange::from(change("Modify", "Table", "True"));
+        let topic = RenderedChange {
+            resource_type: "AWS::SNS::Topic".into(),
+            ..RenderedChange::from(change("Remove", "Topic", "False"))
+        };
+        assert!(Condition::Modify.matches(&table));
+        assert!(Condition::Replace.matches(&table));
+        assert!(Condition::DataLoss.matches(&table));
+        assert!(Condition::Remove.matches(&topic));
+        assert!(!Condition::DataLoss.matches(&topic));
+        assert!(!Condition::Add.matches(&topic));
+    }
+
+    #[test]
+    fn condition_parses_from_str() {
+        assert_eq!("data-loss".parse(), Ok(Condition::DataLoss));
+        assert_eq!(
+            "replace".parse::<Condition>().map(|c| c.to_string()),
+            Ok("replace".to_string())
+        );
+        assert!("destroy".parse::<Condition>().is_err());
+    }
+
+    #[test]
+    fn sort_orders_riskiest_changes_first() {
+        let mut changes = vec![
+            change("Add", "New", "False"),
+            change("Modify", "Config", "False"),
+            change("Modify", "Cache", "True"),
+            change("Remove", "Old", "False"),
+        ]
+        .into_iter()
+        .map(RenderedChange::from)
+        .collect::<Vec<_>>();
+        changes[0].resource_type = "AWS::SNS::Topic".into();
+        changes[2].resource_type = "AWS::Lambda::Function".into();
+        changes[3].resource_type = "AWS::IAM::Role".into();
+        let ids = |changes: &[RenderedChange]| {
+            changes
+                .iter()
+                .map(|c| c.logical_resource_id.clone())
+                .collect::<Vec<_>>()
+        };
+        sort(&mut changes, SortBy::Risk);
+        assert_eq!(ids(&changes), vec!["Old", "Cache", "Config", "New"]);
+        sort(&mut changes, SortBy::Type);
+        assert_eq!(ids(&changes), vec!["Config", "Old", "Cache", "New"]);
+        sort(&mut changes, SortBy::Id);
+        assert_eq!(ids(&changes), vec!["Cache", "Config", "New", "Old"]);
+    }
+
+    #[test]
+    fn sort_by_parses_from_str() {
+        assert_eq!("risk".parse(), Ok(SortBy::Risk));
+        assert!("danger".parse::<SortBy>().is_err());
+    }
+
+    #[test]
+    fn diff_changeset_keeps_non_resource_changes() {
+        let result = diff_changeset(DescribeChangeSetOutput {
+            status: Some("CREATE_COMPLETE".into()),
+            changes: Some(vec![
+                change("Modify", "Table", "False"),
+                Change {
+                    type_: Some("HookInvocation".into()),
+                    resource_change: None,
+                },
+            ]),
+            ..DescribeChangeSetOutput::default()
+        });
+        assert_eq!(result.changes.len(), 2);
+        assert!(result
+            .changes
+            .iter()
+            .any(|change| change.resource_type == "HookInvocation"));
+        assert_eq!(result.summary.modify, 1);
+    }
+
+    #[test]
+    fn diff_changeset_reports_failures() {
+        let result = diff_changeset(DescribeChangeSetOutput {
+            status: Some("FAILED".into()),
+            status_reason: Some("no changes".into()),
+            ..DescribeChangeSetOutput::default()
+        });
+        assert_eq!(result.status, Status::Failed("no changes".into()));
+        assert!(result.changes.is_empty());
+        assert_eq!(result.summary, Summary::default());
+    }
+
+    #[test]
+    fn diff_changeset_completes_change_sets_without_changes() {
+        let result = diff_changeset(DescribeChangeSetOutput {
+            status: Some("FAILED".into()),
+            status_reason: Some("The submitted information didn't contain changes. Submit different information to create a change set.".into()),
+            ..DescribeChangeSetOutput::default()
+        });
+        assert_eq!(result.status, Status::Complete);
+        assert!(result.changes.is_empty());
+        assert!(!result.summary.changed());
+    }
+
+    #[test]
+    fn diff_changeset_reports_other_statuses() {
+        let result = diff_changeset(DescribeChangeSetOutput {
+            status: Some("DELETE_FAILED".into()),
+            ..DescribeChangeSetOutput::default()
+        });
+        assert_eq!(result.status, Status::Other("DELETE_FAILED".into()));
+    }
+}
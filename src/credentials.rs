@@ -0,0 +1,321 @@
+use crate::{sso, web_identity};
+use chrono::Utc;
+use futures::{future, Future};
+use rusoto_core::credential::{
+    AwsCredentials, ContainerProvider, CredentialsError, EnvironmentProvider,
+    InstanceMetadataProvider, ProfileProvider, ProvideAwsCredentials,
+};
+use std::{
+    fmt,
+    str::FromStr,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+const TIMEOUT: Duration = Duration::from_millis(200);
+
+/// credentials shared by the clones of a provider until shortly before they expire
+#[derive(Debug, Clone, Default)]
+pub(crate) struct Cache(Arc<Mutex<Option<AwsCredentials>>>);
+
+impl Cache {
+    /// the cached credentials, unless they expire within a minute
+    pub(crate) fn fresh(&self) -> Option<AwsCredentials> {
+        self.0
+            .lock()
+            .ok()?
+            .as_ref()
+            .filter(|credentials| {
+                credentials
+                    .expires_at()
+                    .is_none_or(|at| at > Utc::now() + chrono::Duration::minutes(1))
+            })
+            .cloned()
+    }
+
+    pub(crate) fn store(
+        &self,
+        credentials: &AwsCredentials,
+    ) {
+        if let Ok(mut cached) = self.0.lock() {
+            *cached = Some(credentials.clone());
+        }
+    }
+}
+
+/// a single source of AWS credentials
+#[derive(Debug, Clone, PartialEq)]
+pub enum Source {
+    Environment,
+    /// a role assumed with the web identity token named by `AWS_WEB_IDENTITY_TOKEN_FILE`
+    WebIdentity,
+    /// a profile in ~/.aws/credentials, the default profile when no name is provided.
+    /// named profiles configured for AWS SSO resolve credentials from `aws sso login`
+    Profile(Option<String>),
+    Container,
+    Instance,
+}
+
+impl Source {
+    /// true for sources that reach out over the network for temporary credentials,
+    /// which are worth caching between requests. SSO profiles are among them, since
+    /// their role credentials are fetched from the SSO portal
+    fn cached(&self) -> bool {
+        match self {
+            Source::WebIdentity | Source::Container | Source::Instance => true,
+            Source::Profile(Some(name)) => sso::configured(name),
+            _ => false,
+        }
+    }
+
+    fn credentials(
+        &self
+    ) -> Box<dyn Future<Item = AwsCredentials, Error = CredentialsError> + Send> {
+        match self {
+            Source::Environment => Box::new(EnvironmentProvider::default().credentials()),
+            Source::WebIdentity => web_identity::credentials(),
+            Source::Profile(Some(name)) if sso::configured(name) => sso::credentials(name),
+            Source::Profile(name) => match ProfileProvider::new() {
+                Ok(mut provider) => {
+                    if let Some(name) = name {
+                        provider.set_profile(name.as_str());
+                    }
+                    Box::new(provider.credentials())
+                }
+                Err(err) => Box::new(future::err(err)),
+            },
+            Source::Container => {
+                let mut provider = ContainerProvider::new();
+                provider.set_timeout(TIMEOUT);
+                Box::new(provider.credentials())
+            }
+            Source::Instance => {
+                let mut provider = InstanceMetadataProvider::new();
+                provider.set_timeout(TIMEOUT);
+                Box::new(provider.credentials())
+            }
+        }
+    }
+}
+
+impl FromStr for Source {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim() {
+            "env" => Ok(Source::Environment),
+            "web-identity" => Ok(Source::WebIdentity),
+            "profile" => Ok(Source::Profile(None)),
+            "container" => Ok(Source::Container),
+            "instance" => Ok(Source::Instance),
+            other => match other.strip_prefix("profile:") {
+                Some(name) if !name.is_empty() => Ok(Source::Profile(Some(name.into()))),
+                _ => Err(format!(
+                    "invalid credential source `{}`. expected one of env, web-identity, profile, profile:<name>, container, or instance",
+                    other
+                )),
+            },
+        }
+    }
+}
+
+impl fmt::Display for Source {
+    fn fmt(
+        &self,
+        f: &mut fmt::Formatter,
+    ) -> fmt::Result {
+        match self {
+            Source::Environment => write!(f, "env"),
+            Source::WebIdentity => write!(f, "web-identity"),
+            Source::Profile(None) => write!(f, "profile"),
+            Source::Profile(Some(name)) => write!(f, "profile:{}", name),
+            Source::Container => write!(f, "container"),
+            Source::Instance => write!(f, "instance"),
+        }
+    }
+}
+
+/// an ordered list of credential sources where the first to yield credentials wins
+///
+/// temporary credentials from network-backed sources are cached, and shared by the
+/// chain's clones, until shortly before they expire
+#[derive(Debug, Clone)]
+pub struct Chain {
+    sources: Vec<Source>,
+    cache: Cache,
+}
+
+impl Chain {
+    /// an empty list of sources falls back on the same order as rusoto's `ChainProvider`,
+    /// trying web identity credentials after the environment
+    pub fn new(sources: Vec<Source>) -> Self {
+        if sources.is_empty() {
+            Chain::default()
+        } else {
+            Chain {
+                sources,
+                cache: Cache::default(),
+            }
+        }
+    }
+
+    /// uses the named profile wherever the chain would use the default profile
+    pub fn with_profile(
+        self,
+        name: &str,
+    ) -> Self {
+        Chain {
+            sources: self
+                .sources
+                .into_iter()
+                .map(|source| match source {
+                    Source::Profile(None) => Source::Profile(Some(name.into())),
+                    other => other,
+                })
+                .collect(),
+            cache: Cache::default(),
+        }
+    }
+}
+
+impl Default for Chain {
+    fn default() -> Self {
+        Chain {
+            sources: vec![
+                Source::Environment,
+                Source::WebIdentity,
+                Source::Profile(None),
+                Source::Container,
+                Source::Instance,
+            ],
+            cache: Cache::default(),
+        }
+    }
+}
+
+impl ProvideAwsCredentials for Chain {
+    type Future = Box<dyn Future<Item = AwsCredentials, Error = CredentialsError> + Send>;
+
+    fn credentials(&self) -> Self::Future {
+        if let Some(credentials) = self.cache.fresh() {
+            return Box::new(future::ok(credentials));
+        }
+        let tried = self
+            .sources
+            .iter()
+            .map(ToString::to_string)
+            .collect::<Vec<_>>()
+            .join(", ");
+        let initial: Self::Future = Box::new(future::err(CredentialsError::new("")));
+        let chain = self.sources.iter().cloned().fold(initial, |chain, source| {
+            let cache = self.cache.clone();
+            Box::new(chain.or_else(move |prev| {
+                let cached = source.cached();
+                source
+                    .credentials()
+                    .map(move |credentials| {
+                        if cached {
+                            cache.store(&credentials);
+                        }
+                        credentials
+                    })
+                    .map_err(move |err| {
+                        CredentialsError::new(format!(
+                            "{}\n  {}: {}",
+                            prev.message, source, err.message
+                        ))
+                    })
+            }))
+        });
+        Box::new(chain.map_err(move |errors| {
+            CredentialsError::new(format!(
+                "Couldn't find AWS credentials in any of the sources: {}{}",
+                tried, errors.message
+            ))
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn source_parses_from_str() {
+        assert_eq!("env".parse(), Ok(Source::Environment));
+        assert_eq!("web-identity".parse(), Ok(Source::WebIdentity));
+        assert_eq!("profile".parse(), Ok(Source::Profile(None)));
+        assert_eq!(
+            "profile:foo".parse(),
+            Ok(Source::Profile(Some("foo".into())))
+        );
+        assert_eq!("container".parse(), Ok(Source::Container));
+        assert_eq!("instance".parse(), Ok(Source::Instance));
+        assert!("profile:".parse::<Source>().is_err());
+        assert!("bogus".parse::<Source>().is_err());
+    }
+
+    #[test]
+    fn source_display_roundtrips() {
+        for spec in &[
+            "env",
+            "web-identity",
+            "profile",
+            "profile:foo",
+            "container",
+            "instance",
+        ] {
+            assert_eq!(
+                spec.parse::<Source>().map(|s| s.to_string()),
+                Ok(spec.to_string())
+            );
+        }
+    }
+
+    #[test]
+    fn with_profile_replaces_default_profile() {
+        assert_eq!(
+            Chain::default().with_profile("foo").sources,
+            vec![
+                Source::Environment,
+                Source::WebIdentity,
+                Source::Profile(Some("foo".into())),
+                Source::Container,
+                Source::Instance,
+            ]
+        );
+    }
+
+    #[test]
+    fn cache_keeps_credentials_until_shortly_before_they_expire() {
+        let credentials = |expires_in: Option<i64>| {
+            AwsCredentials::new(
+                "key",
+                "secret",
+                None,
+                expires_in.map(|seconds| Utc::now() + chrono::Duration::seconds(seconds)),
+            )
+        };
+        let cache = Cache::default();
+        assert!(cache.fresh().is_none());
+        cache.store(&credentials(Some(3600)));
+        assert!(cache.clone().fresh().is_some());
+        cache.store(&credentials(Some(30)));
+        assert!(cache.fresh().is_none());
+        cache.store(&credentials(None));
+        assert!(cache.fresh().is_some());
+    }
+
+    #[test]
+    fn cached_sources_are_network_backed() {
+        assert!(Source::WebIdentity.cached());
+        assert!(Source::Instance.cached());
+        assert!(!Source::Environment.cached());
+        assert!(!Source::Profile(None).cached());
+    }
+
+    #[test]
+    fn empty_chain_defaults() {
+        assert_eq!(Chain::new(Vec::new()).sources, Chain::default().sources);
+    }
+}
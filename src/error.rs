@@ -1,8 +1,11 @@
+use crate::DEFAULT_CAPABILITIES;
 use rusoto_cloudformation::{
     CreateChangeSetError, DeleteChangeSetError, DescribeChangeSetError, DescribeStacksError,
-    GetTemplateError,
+    ExecuteChangeSetError, GetTemplateError, ValidateTemplateError,
 };
 use rusoto_core::{request::BufferedHttpResponse, RusotoError};
+use rusoto_s3::PutObjectError;
+use rusoto_ssm::GetParameterError;
 use serde::Deserialize;
 use std::{error::Error as StdError, fmt};
 
@@ -27,53 +30,140 @@ pub enum Error {
     DescribeChangeset(RusotoError<DescribeChangeSetError>),
     DescribeStack(RusotoError<DescribeStacksError>),
     Delete(RusotoError<DeleteChangeSetError>),
+    Validate(RusotoError<ValidateTemplateError>),
+    Execute(RusotoError<ExecuteChangeSetError>),
+    /// a template requires capabilities the change set wasn't created with
+    InsufficientCapabilities(String),
+    Ssm(String, RusotoError<GetParameterError>),
+    /// a `Stack.Output` reference that couldn't be resolved, and why
+    StackOutput(String, String),
+    Upload(RusotoError<PutObjectError>),
     Differ(String),
+    Config(String),
+    Template(String),
+    TemplateTooLarge(usize),
+    /// a template declaring more resources than a stack may hold
+    TooManyResources(usize),
     Validation(String),
     Throttling(String),
+    AccessDenied(String),
+    ExpiredToken(String),
+    /// a stack and its status while an operation is in progress
+    StackNotReady(String, String),
+    Tls(String),
+    /// a transient failure to reach an endpoint, like a connection reset or timeout
+    Network(String),
+    Notify(String),
+    /// a poll's timer failed, as when the runtime is shutting down
+    Timer(String),
 }
 
-impl From<RusotoError<GetTemplateError>> for Error {
-    fn from(err: RusotoError<GetTemplateError>) -> Self {
-        match &err {
-            // deal with the fact that Rusoto doesn't suface structured errors well here
-            RusotoError::Unknown(BufferedHttpResponse { ref body, .. }) => {
-                if let Ok(ErrorResponse { error }) =
-                    serde_xml_rs::from_reader::<_, ErrorResponse>(body.as_ref())
-                {
-                    match error.code.as_str() {
-                        "Throttling" => return Error::Throttling(error.message),
-                        "ValidationError" => return Error::Validation(error.message),
-                        code => log::debug!("unmatched error code {}", code),
-                    }
+/// IAM actions cliff needs to diff a stack
+pub const REQUIRED_PERMISSIONS: &[&str] = &[
+    "cloudformation:DescribeStacks",
+    "cloudformation:GetTemplate",
+    "cloudformation:CreateChangeSet",
+    "cloudformation:DescribeChangeSet",
+    "cloudformation:DeleteChangeSet",
+];
+
+/// capabilities named in an insufficient capabilities error message, like
+/// `Requires capabilities : [CAPABILITY_AUTO_EXPAND]`, other than those every change
+/// set is created with, which needn't be asked for
+pub fn required_capabilities(message: &str) -> Vec<String> {
+    message
+        .split(|c: char| !(c.is_ascii_uppercase() || c == '_'))
+        .filter(|word| word.starts_with("CAPABILITY_") && !DEFAULT_CAPABILITIES.contains(word))
+        .map(String::from)
+        .collect()
+}
+
+/// deal with the fact that Rusoto doesn't suface structured errors well
+fn classify<E>(err: &RusotoError<E>) -> Option<Error> {
+    if let RusotoError::HttpDispatch(dispatch) = err {
+        let message = dispatch.to_string();
+        let lower = message.to_lowercase();
+        if ["certificate", "tls", "handshake"]
+            .iter()
+            .any(|term| lower.contains(term))
+        {
+            return Some(Error::Tls(message));
+        }
+        return Some(Error::Network(message));
+    }
+    if let RusotoError::Unknown(BufferedHttpResponse { ref body, .. }) = err {
+        if let Ok(ErrorResponse { error }) =
+            serde_xml_rs::from_reader::<_, ErrorResponse>(body.as_ref())
+        {
+            match error.code.as_str() {
+                "Throttling" => return Some(Error::Throttling(error.message)),
+                "InsufficientCapabilities" | "InsufficientCapabilitiesException" => {
+                    return Some(Error::InsufficientCapabilities(error.message))
+                }
+                "ValidationError" if error.message.starts_with("Requires capabilities") => {
+                    return Some(Error::InsufficientCapabilities(error.message))
+                }
+                "ValidationError" => return Some(Error::Validation(error.message)),
+                "ExpiredToken" | "ExpiredTokenException" => {
+                    return Some(Error::ExpiredToken(error.message))
+                }
+                "AccessDenied" | "AccessDeniedException" => {
+                    return Some(Error::AccessDenied(error.message))
                 }
-                Error::Get(err)
+                code => log::debug!("unmatched error code {}", code),
             }
-            _ => Error::Get(err),
         }
     }
+    None
+}
+
+impl From<RusotoError<GetTemplateError>> for Error {
+    fn from(err: RusotoError<GetTemplateError>) -> Self {
+        classify(&err).unwrap_or_else(|| Error::Get(err))
+    }
 }
 
 impl From<RusotoError<CreateChangeSetError>> for Error {
     fn from(err: RusotoError<CreateChangeSetError>) -> Self {
-        match &err {
-            // deal with the fact that Rusoto doesn't suface structured errors well here
-            RusotoError::Unknown(BufferedHttpResponse { ref body, .. }) => {
-                if let Ok(ErrorResponse { error }) =
-                    serde_xml_rs::from_reader::<_, ErrorResponse>(body.as_ref())
-                {
-                    match error.code.as_str() {
-                        "ValidationError" => return Error::Validation(error.message),
-                        "Throttling" => return Error::Throttling(error.message),
-                        code => log::debug!("unmatched error code {}", code),
-                    }
-                }
-                Error::Create(err)
+        match err {
+            RusotoError::Service(CreateChangeSetError::InsufficientCapabilities(message)) => {
+                Error::InsufficientCapabilities(message)
             }
-            _ => Error::Create(err),
+            err => classify(&err).unwrap_or_else(|| Error::Create(err)),
         }
     }
 }
 
+impl From<RusotoError<DescribeChangeSetError>> for Error {
+    fn from(err: RusotoError<DescribeChangeSetError>) -> Self {
+        classify(&err).unwrap_or_else(|| Error::DescribeChangeset(err))
+    }
+}
+
+impl From<RusotoError<DescribeStacksError>> for Error {
+    fn from(err: RusotoError<DescribeStacksError>) -> Self {
+        classify(&err).unwrap_or_else(|| Error::DescribeStack(err))
+    }
+}
+
+impl From<RusotoError<DeleteChangeSetError>> for Error {
+    fn from(err: RusotoError<DeleteChangeSetError>) -> Self {
+        classify(&err).unwrap_or_else(|| Error::Delete(err))
+    }
+}
+
+impl From<RusotoError<ValidateTemplateError>> for Error {
+    fn from(err: RusotoError<ValidateTemplateError>) -> Self {
+        classify(&err).unwrap_or_else(|| Error::Validate(err))
+    }
+}
+
+impl From<RusotoError<ExecuteChangeSetError>> for Error {
+    fn from(err: RusotoError<ExecuteChangeSetError>) -> Self {
+        classify(&err).unwrap_or_else(|| Error::Execute(err))
+    }
+}
+
 impl StdError for Error {}
 
 impl fmt::Display for Error {
@@ -85,14 +175,60 @@ impl fmt::Display for Error {
             f,
             "{}",
             match self {
-                Error::Get(e) => e.to_string(),
-                Error::Create(e) => e.to_string(),
-                Error::DescribeChangeset(e) => e.to_string(),
-                Error::DescribeStack(e) => e.to_string(),
-                Error::Delete(e) => e.to_string(),
+                Error::Get(e) => format!("Failed to get template: {}", e),
+                Error::Create(e) => format!("Failed to create change set: {}", e),
+                Error::DescribeChangeset(e) => format!("Failed to describe change set: {}", e),
+                Error::DescribeStack(e) => format!("Failed to describe stack: {}", e),
+                Error::Delete(e) => format!("Failed to delete change set: {}", e),
+                Error::Validate(e) => format!("Failed to validate template: {}", e),
+                Error::Execute(e) => format!("Failed to execute change set: {}", e),
+                Error::InsufficientCapabilities(message) => {
+                    match required_capabilities(message).as_slice() {
+                        [] => format!("Insufficient capabilities: {}", message),
+                        capabilities => format!(
+                            "Insufficient capabilities: {}\nadd --capabilities {}",
+                            message,
+                            capabilities.join(",")
+                        ),
+                    }
+                }
+                Error::Upload(e) => format!("Failed to upload template: {}", e),
+                Error::Ssm(name, e) => format!("Failed to resolve SSM parameter {}: {}", name, e),
+                Error::StackOutput(reference, reason) => {
+                    format!("Failed to resolve stack output {}: {}", reference, reason)
+                }
                 Error::Differ(tool) => format!("Invalid differ tool {}", tool),
+                Error::Config(message) => message.to_string(),
+                Error::Template(message) => message.to_string(),
+                Error::TemplateTooLarge(size) => format!(
+                    "Template is {} bytes which exceeds CloudFormation's inline limit of {} bytes. Provide an --artifacts-bucket to upload it to S3 instead",
+                    size,
+                    crate::MAX_TEMPLATE_BODY_BYTES
+                ),
+                Error::TooManyResources(count) => format!(
+                    "Template declares {} resources, more than the {} CloudFormation allows in a stack. Split it into nested stacks, or drop --strict to diff it anyway",
+                    count,
+                    crate::MAX_STACK_RESOURCES
+                ),
                 Error::Validation(message) => format!("Error: {}", message),
                 Error::Throttling(message) => message.to_string(),
+                Error::ExpiredToken(message) => format!("Expired credentials: {}", message),
+                Error::Network(message) => format!("Network error: {}", message),
+                Error::Notify(message) => format!("Failed to send notification: {}", message),
+                Error::Timer(message) => format!("Timer error: {}", message),
+                Error::Tls(message) => format!(
+                    "TLS error: {}\nIf your network intercepts TLS, set AWS_CA_BUNDLE to a PEM file of trusted certificates",
+                    message
+                ),
+                Error::StackNotReady(stack, status) => format!(
+                    "Stack {} is {}. Wait for the operation to finish or use --wait-for-stack-ready",
+                    stack, status
+                ),
+                Error::AccessDenied(message) => format!(
+                    "Access denied: {}\nCliff requires the following IAM permissions: {}",
+                    message,
+                    REQUIRED_PERMISSIONS.join(", ")
+                ),
             }
         )
     }
@@ -102,6 +238,7 @@ impl fmt::Display for Error {
 mod tests {
     use super::*;
     use bytes::Bytes;
+    use rusoto_core::request::HttpDispatchError;
 
     #[test]
     fn error_response_deserializes() {
@@ -139,6 +276,36 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn error_from_create_changset_error_insufficient_capabilities() -> Result<(), Box<dyn StdError>>
+    {
+        let rusoto_error = CreateChangeSetError::from_response(BufferedHttpResponse {
+            status: Default::default(),
+            body: Bytes::from("<ErrorResponse><Error><Code>InsufficientCapabilitiesException</Code><Message>Requires capabilities : [CAPABILITY_AUTO_EXPAND]</Message></Error></ErrorResponse>"),
+            headers: Default::default(),
+        });
+        let err = Error::from(rusoto_error);
+        assert_eq!(
+            err,
+            Error::InsufficientCapabilities(
+                "Requires capabilities : [CAPABILITY_AUTO_EXPAND]".into()
+            )
+        );
+        assert!(err
+            .to_string()
+            .ends_with("add --capabilities CAPABILITY_AUTO_EXPAND"));
+        let rusoto_error: RusotoError<CreateChangeSetError> =
+            RusotoError::Unknown(BufferedHttpResponse {
+                status: Default::default(),
+                body: Bytes::from("<ErrorResponse><Error><Code>InsufficientCapabilities</Code><Message>Requires capabilities : [CAPABILITY_AUTO_EXPAND, CAPABILITY_IAM]</Message></Error></ErrorResponse>"),
+                headers: Default::default(),
+            });
+        assert!(Error::from(rusoto_error)
+            .to_string()
+            .ends_with("add --capabilities CAPABILITY_AUTO_EXPAND"));
+        Ok(())
+    }
+
     #[test]
     fn error_from_create_changset_error_throttling() -> Result<(), Box<dyn StdError>> {
         let rusoto_error: RusotoError<CreateChangeSetError> =
@@ -152,6 +319,99 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn error_from_create_changset_error_access_denied() -> Result<(), Box<dyn StdError>> {
+        let rusoto_error: RusotoError<CreateChangeSetError> =
+            RusotoError::Unknown(BufferedHttpResponse {
+                status: Default::default(),
+                body: Bytes::from("<ErrorResponse><Error><Code>AccessDenied</Code><Message>test</Message></Error></ErrorResponse>"),
+                headers: Default::default(),
+            });
+        let err = Error::from(rusoto_error);
+        assert_eq!(err, Error::AccessDenied("test".into()));
+        assert!(err.to_string().contains("cloudformation:CreateChangeSet"));
+        Ok(())
+    }
+
+    #[test]
+    fn error_from_describe_stacks_error_access_denied() -> Result<(), Box<dyn StdError>> {
+        let rusoto_error: RusotoError<DescribeStacksError> =
+            RusotoError::Unknown(BufferedHttpResponse {
+                status: Default::default(),
+                body: Bytes::from("<ErrorResponse><Error><Code>AccessDenied</Code><Message>test</Message></Error></ErrorResponse>"),
+                headers: Default::default(),
+            });
+        assert_eq!(
+            Error::from(rusoto_error),
+            Error::AccessDenied("test".into())
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn error_from_get_template_error_expired_token() -> Result<(), Box<dyn StdError>> {
+        let rusoto_error: RusotoError<GetTemplateError> =
+            RusotoError::Unknown(BufferedHttpResponse {
+                status: Default::default(),
+                body: Bytes::from("<ErrorResponse><Error><Code>ExpiredToken</Code><Message>test</Message></Error></ErrorResponse>"),
+                headers: Default::default(),
+            });
+        assert_eq!(
+            Error::from(rusoto_error),
+            Error::ExpiredToken("test".into())
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn error_from_get_template_error_tls() -> Result<(), Box<dyn StdError>> {
+        let rusoto_error: RusotoError<GetTemplateError> = RusotoError::HttpDispatch(
+            HttpDispatchError::new("invalid certificate: UnknownIssuer".into()),
+        );
+        let err = Error::from(rusoto_error);
+        assert_eq!(err, Error::Tls("invalid certificate: UnknownIssuer".into()));
+        assert!(err.to_string().contains("AWS_CA_BUNDLE"));
+        Ok(())
+    }
+
+    #[test]
+    fn error_from_delete_changeset_error_network() -> Result<(), Box<dyn StdError>> {
+        let rusoto_error: RusotoError<DeleteChangeSetError> =
+            RusotoError::HttpDispatch(HttpDispatchError::new("connection reset by peer".into()));
+        assert_eq!(
+            Error::from(rusoto_error),
+            Error::Network("connection reset by peer".into())
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn error_from_validate_template_error_validation() -> Result<(), Box<dyn StdError>> {
+        let rusoto_error: RusotoError<ValidateTemplateError> =
+            RusotoError::Unknown(BufferedHttpResponse {
+                status: Default::default(),
+                body: Bytes::from("<ErrorResponse><Error><Code>ValidationError</Code><Message>test</Message></Error></ErrorResponse>"),
+                headers: Default::default(),
+            });
+        assert_eq!(Error::from(rusoto_error), Error::Validation("test".into()));
+        Ok(())
+    }
+
+    #[test]
+    fn error_display_names_the_failed_call() {
+        assert_eq!(
+            Error::DescribeChangeset(RusotoError::Service(
+                DescribeChangeSetError::ChangeSetNotFound("test".into())
+            ))
+            .to_string(),
+            "Failed to describe change set: test"
+        );
+        assert_eq!(
+            Error::DescribeStack(RusotoError::Validation("test".into())).to_string(),
+            "Failed to describe stack: test"
+        );
+    }
+
     #[test]
     fn error_from_get_template_error() -> Result<(), Box<dyn StdError>> {
         let rusoto_error: RusotoError<GetTemplateError> =
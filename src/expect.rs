@@ -0,0 +1,158 @@
+use crate::changeset::{Condition, RenderedChange};
+use std::{collections::BTreeMap, fmt};
+
+/// the changes a change set is expected to contain: the logical ids of resources
+/// allowed to change, and how each may change
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Expectation {
+    allowed: BTreeMap<String, Vec<Condition>>,
+}
+
+/// a way a change set departs from an expectation
+#[derive(Debug, Clone, PartialEq)]
+pub enum Violation {
+    /// a change to a resource that isn't expected to change, or not in this way
+    Unexpected(RenderedChange),
+    /// an expected resource that doesn't change
+    Missing(String),
+}
+
+impl fmt::Display for Violation {
+    fn fmt(
+        &self,
+        f: &mut fmt::Formatter,
+    ) -> fmt::Result {
+        match self {
+            Violation::Unexpected(change) => write!(
+                f,
+                "unexpected {}{} of {}",
+                change.action.to_lowercase(),
+                if change.replacement {
+                    " with replacement"
+                } else {
+                    ""
+                },
+                change.logical_resource_id
+            ),
+            Violation::Missing(id) => write!(f, "expected change to {} is missing", id),
+        }
+    }
+}
+
+impl Expectation {
+    /// parses a YAML or JSON mapping of logical ids to lists of allowed changes: add,
+    /// modify, remove, import, and replace
+    pub fn parse(body: &str) -> Result<Self, String> {
+        let entries = serde_yaml::from_str::<BTreeMap<String, Vec<String>>>(body)
+            .map_err(|err| format!("invalid expectation: {}", err))?;
+        let mut allowed = BTreeMap::new();
+        for (id, actions) in entries {
+            let actions = actions
+                .iter()
+                .map(|action| action.parse::<Condition>())
+                .collect::<Result<Vec<_>, _>>()
+                .map_err(|err| format!("invalid expectation for {}: {}", id, err))?;
+            allowed.insert(id, actions);
+        }
+        Ok(Expectation { allowed })
+    }
+
+    fn allows(
+        &self,
+        change: &RenderedChange,
+    ) -> bool {
+        let allowed = match self.allowed.get(&change.logical_resource_id) {
+            Some(allowed) => allowed,
+            _ => return false,
+        };
+        let action = change.action.to_lowercase().parse::<Condition>();
+        action.is_ok_and(|action| allowed.contains(&action))
+            && (!change.replacement || allowed.contains(&Condition::Replace))
+    }
+
+    /// changes the expectation doesn't allow, followed by expected changes that are missing
+    pub fn check(
+        &self,
+        changes: &[RenderedChange],
+    ) -> Vec<Violation> {
+        let mut violations = changes
+            .iter()
+            .filter(|change| !self.allows(change))
+            .cloned()
+            .map(Violation::Unexpected)
+            .collect::<Vec<_>>();
+        violations.extend(
+            self.allowed
+                .keys()
+                .filter(|id| {
+                    !changes
+                        .iter()
+                        .any(|change| change.logical_resource_id == **id)
+                })
+                .cloned()
+                .map(Violation::Missing),
+        );
+        violations
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn change(
+        action: &str,
+        logical_resource_id: &str,
+        replacement: bool,
+    ) -> RenderedChange {
+        RenderedChange {
+            action: action.into(),
+            logical_resource_id: logical_resource_id.into(),
+            replacement,
+            ..RenderedChange::default()
+        }
+    }
+
+    #[test]
+    fn check_reports_unexpected_and_missing_changes() -> Result<(), String> {
+        let expectation = Expectation::parse(
+            r#"
+Table: [modify]
+Function: [modify, replace]
+Queue: [add]
+"#,
+        )?;
+        assert_eq!(
+            expectation.check(&[
+                change("Modify", "Table", true),
+                change("Modify", "Function", true),
+                change("Remove", "Topic", false),
+            ]),
+            vec![
+                Violation::Unexpected(change("Modify", "Table", true)),
+                Violation::Unexpected(change("Remove", "Topic", false)),
+                Violation::Missing("Queue".into()),
+            ]
+        );
+        assert_eq!(
+            Violation::Unexpected(change("Modify", "Table", true)).to_string(),
+            "unexpected modify with replacement of Table"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn check_passes_expected_changes() -> Result<(), String> {
+        let expectation = Expectation::parse(r#"{"Queue": ["add"]}"#)?;
+        assert!(expectation
+            .check(&[change("Add", "Queue", false)])
+            .is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn parse_rejects_unknown_actions() {
+        assert!(Expectation::parse("Table: [destroy]").is_err());
+        assert!(Expectation::parse("[Table]").is_err());
+    }
+}
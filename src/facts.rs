@@ -0,0 +1,222 @@
+use crate::{changeset::Status, Diff};
+use rusoto_cloudformation::Change;
+use serde::Serialize;
+
+/// the version of the facts schema, bumped whenever a field changes meaning or goes away
+const VERSION: u32 = 1;
+
+/// the policy-relevant facts of every stack diffed in a run, shaped for policy engines
+/// like cfn-guard
+#[derive(Debug, PartialEq, Serialize)]
+pub struct Report<'a> {
+    version: u32,
+    stacks: Vec<Facts<'a>>,
+}
+
+/// the policy-relevant facts of a stack's changes
+#[derive(Debug, PartialEq, Serialize)]
+pub struct Facts<'a> {
+    stack_name: &'a str,
+    region: &'a str,
+    #[serde(flatten)]
+    status: &'a Status,
+    changes: Vec<Fact<'a>>,
+}
+
+/// the facts of a single resource change
+#[derive(Debug, PartialEq, Serialize)]
+pub struct Fact<'a> {
+    logical_resource_id: &'a str,
+    resource_type: &'a str,
+    action: &'a str,
+    replacement: bool,
+    /// names of the properties, or other attributes like `Tags`, the change touches
+    properties: Vec<String>,
+}
+
+/// the properties and attributes a change set's change of a resource touches, in the
+/// order CloudFormation lists them
+fn properties(
+    changes: &[Change],
+    logical_resource_id: &str,
+) -> Vec<String> {
+    let mut names = Vec::<String>::new();
+    let details = changes
+        .iter()
+        .filter_map(|change| change.resource_change.as_ref())
+        .filter(|change| change.logical_resource_id.as_deref() == Some(logical_resource_id))
+        .flat_map(|change| change.details.iter().flatten());
+    for target in details.filter_map(|detail| detail.target.as_ref()) {
+        let name = match (target.attribute.as_deref(), &target.name) {
+            (Some("Properties"), Some(name)) | (None, Some(name)) => name.as_str(),
+            (Some(attribute), _) => attribute,
+            _ => continue,
+        };
+        if !names.iter().any(|known| known == name) {
+            names.push(name.into());
+        }
+    }
+    names
+}
+
+/// the facts of every diff's changes. only the changes cliff shows are included
+pub fn report(diffs: &[Diff]) -> Report<'_> {
+    Report {
+        version: VERSION,
+        stacks: diffs.iter().map(facts).collect(),
+    }
+}
+
+/// the facts of a diff's changes
+fn facts(diff: &Diff) -> Facts<'_> {
+    let changes = diff.changeset.changes.as_deref().unwrap_or_default();
+    Facts {
+        stack_name: &diff.stack_name,
+        region: diff.region.name(),
+        status: &diff.result.status,
+        changes: diff
+            .result
+            .changes
+            .iter()
+            .map(|change| Fact {
+                logical_resource_id: &change.logical_resource_id,
+                resource_type: &change.resource_type,
+                action: &change.action,
+                replacement: change.replacement,
+                properties: properties(changes, &change.logical_resource_id),
+            })
+            .collect(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::changeset::{DiffResult, RenderedChange};
+    use rusoto_cloudformation::{
+        DescribeChangeSetOutput, ResourceChange, ResourceChangeDetail, ResourceTargetDefinition,
+    };
+    use std::path::PathBuf;
+
+    fn detail(
+        attribute: &str,
+        name: Option<&str>,
+    ) -> ResourceChangeDetail {
+        ResourceChangeDetail {
+            target: Some(ResourceTargetDefinition {
+                attribute: Some(attribute.into()),
+                name: name.map(Into::into),
+                ..ResourceTargetDefinition::default()
+            }),
+            ..ResourceChangeDetail::default()
+        }
+    }
+
+    fn change(
+        logical_resource_id: &str,
+        details: Vec<ResourceChangeDetail>,
+    ) -> Change {
+        Change {
+            type_: Some("Resource".into()),
+            resource_change: Some(ResourceChange {
+                logical_resource_id: Some(logical_resource_id.into()),
+                details: Some(details),
+                ..ResourceChange::default()
+            }),
+        }
+    }
+
+    #[test]
+    fn properties_names_touched_properties_once() {
+        let changes = [
+            change(
+                "Bucket",
+                vec![
+                    detail("Properties", Some("BucketName")),
+                    detail("Tags", None),
+                    detail("Properties", Some("BucketName")),
+                    detail("Properties", Some("VersioningConfiguration")),
+                ],
+            ),
+            change("Queue", vec![detail("Properties", Some("DelaySeconds"))]),
+        ];
+        assert_eq!(
+            properties(&changes, "Bucket"),
+            vec!["BucketName", "Tags", "VersioningConfiguration"]
+        );
+        assert!(properties(&changes, "Topic").is_empty());
+    }
+
+    #[test]
+    fn facts_follow_the_documented_schema() -> Result<(), serde_json::Error> {
+        let diff = Diff {
+            stack_name: "test".into(),
+            region: rusoto_core::Region::UsEast1,
+            filename: PathBuf::from("template.yml"),
+            template_body: String::new(),
+            template: None,
+            changeset: DescribeChangeSetOutput {
+                changes: Some(vec![change(
+                    "Bucket",
+                    vec![detail("Properties", Some("BucketName"))],
+                )]),
+                ..DescribeChangeSetOutput::default()
+            },
+            parameters: Vec::new(),
+            timings: Default::default(),
+            identity: None,
+            template_error: None,
+            notes: Vec::new(),
+            result: DiffResult {
+                status: Status::Complete,
+                changes: vec![
+                    RenderedChange {
+                        action: "Modify".into(),
+                        resource_type: "AWS::S3::Bucket".into(),
+                        logical_resource_id: "Bucket".into(),
+                        replacement: true,
+                        ..RenderedChange::default()
+                    },
+                    RenderedChange {
+                        action: "Add".into(),
+                        resource_type: "AWS::SNS::Topic".into(),
+                        logical_resource_id: "Topic".into(),
+                        ..RenderedChange::default()
+                    },
+                ],
+                summary: Default::default(),
+                hidden: 0,
+            },
+        };
+        assert_eq!(
+            serde_json::to_value(report(&[diff]))?,
+            serde_json::json!({
+                "version": 1,
+                "stacks": [
+                    {
+                        "stack_name": "test",
+                        "region": "us-east-1",
+                        "status": "complete",
+                        "changes": [
+                            {
+                                "logical_resource_id": "Bucket",
+                                "resource_type": "AWS::S3::Bucket",
+                                "action": "Modify",
+                                "replacement": true,
+                                "properties": ["BucketName"]
+                            },
+                            {
+                                "logical_resource_id": "Topic",
+                                "resource_type": "AWS::SNS::Topic",
+                                "action": "Add",
+                                "replacement": false,
+                                "properties": []
+                            }
+                        ]
+                    }
+                ]
+            })
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,113 @@
+use crate::error::Error;
+use hyper::client::HttpConnector;
+use hyper_proxy::{Intercept, Proxy, ProxyConnector};
+use hyper_rustls::HttpsConnector;
+use rusoto_core::request::HttpClient;
+use rustls::{internal::pemfile, ClientConfig};
+use std::{env, fs::File, io::BufReader};
+
+/// number of DNS worker threads, matching rusoto's default dispatcher
+const DNS_THREADS: usize = 4;
+
+/// the connector cliff dispatches AWS requests with
+pub type Connector = HttpsConnector<ProxyConnector<HttpConnector>>;
+
+fn var(names: &[&str]) -> Option<String> {
+    names
+        .iter()
+        .filter_map(|name| env::var(name).ok())
+        .find(|value| !value.trim().is_empty())
+}
+
+/// true when `host` matches an entry of a comma-separated `NO_PROXY` list
+fn no_proxy(
+    host: &str,
+    list: &str,
+) -> bool {
+    list.split(',')
+        .map(|entry| entry.trim().trim_start_matches('.'))
+        .filter(|entry| !entry.is_empty())
+        .any(|entry| entry == "*" || host == entry || host.ends_with(&format!(".{}", entry)))
+}
+
+fn proxy(url: &str) -> Result<Proxy, Error> {
+    let uri = url
+        .parse()
+        .map_err(|err| Error::Config(format!("invalid HTTPS_PROXY `{}`: {}", url, err)))?;
+    let bypass = var(&["NO_PROXY", "no_proxy"]).unwrap_or_default();
+    Ok(Proxy::new(
+        Intercept::Custom(
+            (move |scheme: Option<&str>, host: Option<&str>, _: Option<u16>| {
+                scheme == Some("https") && !no_proxy(host.unwrap_or_default(), &bypass)
+            })
+            .into(),
+        ),
+        uri,
+    ))
+}
+
+fn tls_config(ca_bundle: Option<String>) -> Result<ClientConfig, Error> {
+    let mut config = ClientConfig::new();
+    config
+        .root_store
+        .add_server_trust_anchors(&webpki_roots::TLS_SERVER_ROOTS);
+    config.ct_logs = Some(&ct_logs::LOGS);
+    if let Some(path) = ca_bundle {
+        let invalid = |reason: String| {
+            Error::Config(format!("Failed to load CA bundle {}: {}", path, reason))
+        };
+        let file = File::open(&path).map_err(|err| invalid(err.to_string()))?;
+        let certs =
+            pemfile::certs(&mut BufReader::new(file)).map_err(|_| invalid("invalid PEM".into()))?;
+        if certs.is_empty() {
+            return Err(invalid("no certificates found".into()));
+        }
+        for cert in certs {
+            config
+                .root_store
+                .add(&cert)
+                .map_err(|err| invalid(format!("{:?}", err)))?;
+        }
+    }
+    Ok(config)
+}
+
+/// creates a connector honoring `HTTPS_PROXY`, `NO_PROXY`, and `AWS_CA_BUNDLE`
+pub(crate) fn connector() -> Result<Connector, Error> {
+    let mut http = HttpConnector::new(DNS_THREADS);
+    http.enforce_http(false);
+    let mut proxies = ProxyConnector::unsecured(http);
+    if let Some(url) = var(&["HTTPS_PROXY", "https_proxy"]) {
+        log::debug!("proxying requests through {}", url);
+        proxies.add_proxy(proxy(&url)?);
+    }
+    let config = tls_config(var(&["AWS_CA_BUNDLE"]))?;
+    Ok(HttpsConnector::from((proxies, config)))
+}
+
+/// creates a request dispatcher honoring `HTTPS_PROXY`, `NO_PROXY`, and `AWS_CA_BUNDLE`
+pub fn dispatcher() -> Result<HttpClient<Connector>, Error> {
+    Ok(HttpClient::from_connector(connector()?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn no_proxy_matches_hosts_and_domains() {
+        let list = "localhost, .internal.example.com,10.0.0.1";
+        assert!(no_proxy("localhost", list));
+        assert!(no_proxy("ci.internal.example.com", list));
+        assert!(no_proxy("10.0.0.1", list));
+        assert!(!no_proxy("cloudformation.us-east-1.amazonaws.com", list));
+        assert!(no_proxy("anything", "*"));
+    }
+
+    #[test]
+    fn tls_config_rejects_missing_ca_bundles() {
+        assert!(tls_config(None).is_ok());
+        assert!(tls_config(Some("tests/data/missing.pem".into())).is_err());
+        assert!(tls_config(Some("tests/data/template-before.yml".into())).is_err());
+    }
+}
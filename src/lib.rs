@@ -0,0 +1,2789 @@
+//! An AWS CloudFormation stack diff tool
+//!
+//! The core of cliff is a [`Differ`](struct.Differ.html) which diffs a local template
+//! against a deployed stack by creating, describing, and then deleting a change set.
+#![allow(clippy::result_large_err)]
+use futures::{
+    future::{self, Loop},
+    stream, Future, Stream,
+};
+use futures_backoff::Strategy;
+use lazy_static::lazy_static;
+use rusoto_cloudformation::{
+    CloudFormation, CloudFormationClient, CreateChangeSetError, CreateChangeSetInput,
+    CreateChangeSetOutput, DeleteChangeSetInput, DescribeChangeSetError, DescribeChangeSetInput,
+    DescribeChangeSetOutput, DescribeStacksInput, ExecuteChangeSetInput, GetTemplateInput,
+    GetTemplateOutput, Parameter, RollbackConfiguration, RollbackTrigger, Stack,
+    ValidateTemplateInput, ValidateTemplateOutput,
+};
+use rusoto_core::{Region, RusotoError};
+use rusoto_s3::{PutObjectRequest, S3Client, S3};
+use rusoto_ssm::{GetParameterRequest, Ssm, SsmClient};
+use std::{
+    collections::{hash_map::DefaultHasher, HashMap},
+    hash::{Hash, Hasher},
+    io::{self, Write},
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+use tokio::timer::Delay;
+
+pub mod accounts;
+pub mod baseline;
+pub mod cache;
+pub mod changeset;
+pub mod credentials;
+pub mod error;
+pub mod expect;
+pub mod facts;
+pub mod http;
+pub mod notify;
+pub mod parameters;
+pub mod policy;
+mod profile;
+pub mod properties;
+pub mod raw;
+pub mod redact;
+pub mod region;
+pub mod render;
+pub mod sarif;
+mod sso;
+pub mod template;
+pub mod timings;
+pub mod watch;
+mod web_identity;
+use crate::{
+    accounts::CallerIdentity,
+    cache::Cache,
+    changeset::{diff_changeset, sort, DiffResult, ResourceFilter, SortBy, Status},
+    error::Error,
+    parameters::{parameter_changes, ParameterChange},
+    properties::annotate,
+    redact::{Redactor, REDACTED},
+    template::{diff_template, parse, DiffOptions, Stage, TemplateFormat},
+    timings::{timed, Phase, Shared, Timings},
+};
+
+/// name of the ephemeral change set created for each diff
+pub const CHANGESET_NAME: &str = "cliff";
+const SSM_PREFIX: &str = "ssm:";
+/// prefix of parameter values resolved from another stack's outputs, like
+/// `stack:network.VpcId`
+const STACK_PREFIX: &str = "stack:";
+/// initial interval between change set polls, doubled after each poll
+const POLL_INTERVAL: Duration = Duration::from_millis(250);
+/// default cap on the interval between change set polls
+pub const MAX_POLL_INTERVAL: Duration = Duration::from_secs(5);
+/// interval between checks on a stack with an operation in progress
+const STACK_READY_POLL: Duration = Duration::from_secs(5);
+/// largest template body CloudFormation accepts inline
+pub const MAX_TEMPLATE_BODY_BYTES: usize = 51_200;
+/// largest template CloudFormation accepts from S3
+pub const MAX_TEMPLATE_URL_BYTES: usize = 1_048_576;
+/// most resources CloudFormation allows in a single stack
+pub const MAX_STACK_RESOURCES: usize = 500;
+/// longest client request token CloudFormation accepts
+pub const MAX_CLIENT_TOKEN_LEN: usize = 128;
+/// templates declaring at least this many resources are warned about as nearing
+/// `MAX_STACK_RESOURCES`
+const STACK_RESOURCES_WARNING: usize = MAX_STACK_RESOURCES * 9 / 10;
+
+lazy_static! {
+    static ref RETRIES: Strategy = Strategy::exponential(Duration::from_millis(100))
+        .with_max_retries(15)
+        .with_max_delay(Duration::from_secs(10))
+        .with_jitter(true);
+}
+
+/// the outcome of diffing a local template against a deployed stack
+#[derive(Debug, Clone)]
+pub struct Diff {
+    /// name of the stack diffed against
+    pub stack_name: String,
+    /// region of the stack diffed against
+    pub region: Region,
+    /// the local template diffed
+    pub filename: PathBuf,
+    /// the body of the local template diffed, with secrets redacted
+    pub template_body: String,
+    /// textual diff of the current and local templates, if the differ could be run
+    pub template: Option<String>,
+    /// the change set CloudFormation computed for the local template
+    pub changeset: DescribeChangeSetOutput,
+    /// the structured result of the change set
+    pub result: DiffResult,
+    /// parameters added, modified, or removed relative to the deployed stack
+    pub parameters: Vec<ParameterChange>,
+    /// how long each phase of the diff took
+    pub timings: Timings,
+    /// who the diff was made as, when credentials were checked with STS
+    pub identity: Option<CallerIdentity>,
+    /// why there's no textual template diff, when the differ failed
+    pub template_error: Option<String>,
+    /// notes for whoever runs the diff, like a stack that was never deployed or a
+    /// retained change set
+    pub notes: Vec<String>,
+}
+
+/// notes collected over the course of a diff
+type Notes = Arc<Mutex<Vec<String>>>;
+
+/// adds a note to those collected
+fn note(
+    notes: &Notes,
+    note: String,
+) {
+    if let Ok(mut notes) = notes.lock() {
+        notes.push(note);
+    }
+}
+
+/// capabilities change sets are always created with
+pub(crate) const DEFAULT_CAPABILITIES: &[&str] = &["CAPABILITY_IAM", "CAPABILITY_NAMED_IAM"];
+
+/// settings of the change set created for a diff, beyond its template and parameters
+#[derive(Debug, Default, Clone, PartialEq)]
+struct ChangeSetOptions {
+    description: Option<String>,
+    notification_arns: Vec<String>,
+    /// service role CloudFormation makes the change set's changes with
+    role_arn: Option<String>,
+    /// ARNs of CloudWatch alarms that roll back the change set's stack operation
+    rollback_alarm_arns: Vec<String>,
+    /// how long CloudFormation monitors the rollback alarms after the operation
+    rollback_monitoring_minutes: Option<i64>,
+    /// capabilities acknowledged in addition to `DEFAULT_CAPABILITIES`
+    capabilities: Vec<String>,
+    /// identifies the change set's creation across retries, so CloudFormation doesn't
+    /// create it twice
+    client_token: Option<String>,
+    /// creates rather than updates the stack, for stacks that were never deployed
+    create: bool,
+}
+
+/// a random version 4 UUID, prefixed to satisfy CloudFormation's requirement that
+/// client tokens start with a letter
+fn client_token() -> String {
+    let mut bytes = rand::random::<[u8; 16]>();
+    bytes[6] = (bytes[6] & 0x0f) | 0x40;
+    bytes[8] = (bytes[8] & 0x3f) | 0x80;
+    let hex = bytes
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect::<String>();
+    format!(
+        "cliff-{}-{}-{}-{}-{}",
+        &hex[..8],
+        &hex[8..12],
+        &hex[12..16],
+        &hex[16..20],
+        &hex[20..]
+    )
+}
+
+/// a client token for a stack in a region, derived from one provided for many, so that
+/// no two differs share one. the provided token leads, cut short to fit
+/// `MAX_CLIENT_TOKEN_LEN` when it's long
+fn derived_client_token(
+    token: &str,
+    stack_name: &str,
+    region: &Region,
+) -> String {
+    let mut hasher = DefaultHasher::new();
+    (token, stack_name, region.name()).hash(&mut hasher);
+    let suffix = format!("-{:016x}", hasher.finish());
+    let prefix = token
+        .chars()
+        .take(MAX_CLIENT_TOKEN_LEN - suffix.len())
+        .collect::<String>();
+    format!("{}{}", prefix, suffix)
+}
+
+impl ChangeSetOptions {
+    fn capabilities(&self) -> Vec<String> {
+        let mut capabilities = DEFAULT_CAPABILITIES
+            .iter()
+            .map(|capability| capability.to_string())
+            .collect::<Vec<_>>();
+        for capability in &self.capabilities {
+            if !capabilities.contains(capability) {
+                capabilities.push(capability.clone());
+            }
+        }
+        capabilities
+    }
+
+    /// the rollback configuration, when rollback alarms or monitoring are set. left
+    /// unset, CloudFormation keeps the configuration the stack already has
+    fn rollback_configuration(&self) -> Option<RollbackConfiguration> {
+        if self.rollback_alarm_arns.is_empty() && self.rollback_monitoring_minutes.is_none() {
+            return None;
+        }
+        Some(RollbackConfiguration {
+            monitoring_time_in_minutes: self.rollback_monitoring_minutes,
+            rollback_triggers: Some(
+                self.rollback_alarm_arns
+                    .iter()
+                    .map(|arn| RollbackTrigger {
+                        arn: arn.clone(),
+                        type_: "AWS::CloudWatch::Alarm".into(),
+                    })
+                    .collect(),
+            )
+            .filter(|triggers: &Vec<_>| !triggers.is_empty()),
+        })
+    }
+}
+
+/// diffs a local template against a deployed CloudFormation stack
+///
+/// Differs are created with a [`DifferBuilder`](struct.DifferBuilder.html)
+#[derive(Clone)]
+pub struct Differ {
+    client: CloudFormationClient,
+    ssm_client: SsmClient,
+    s3_client: S3Client,
+    region: Region,
+    stack_name: String,
+    filename: PathBuf,
+    template_body: String,
+    parameters: Vec<(String, String)>,
+    artifacts_bucket: Option<String>,
+    cache: Option<Cache>,
+    refresh: bool,
+    progress: bool,
+    include_property_values: bool,
+    include_policy_changes: bool,
+    include_parameter_values: bool,
+    sort_by: SortBy,
+    only_resources: Option<ResourceFilter>,
+    stack_ready_timeout: Option<Duration>,
+    max_poll_interval: Duration,
+    change_set_name: String,
+    keep_change_set: bool,
+    resume: bool,
+    retain_on_error: bool,
+    diff_options: DiffOptions,
+    template_format: Option<TemplateFormat>,
+    template_stage: Stage,
+    change_set: ChangeSetOptions,
+    /// a local template and its body to diff against in place of the deployed template
+    before: Option<(PathBuf, String)>,
+    identity: Option<CallerIdentity>,
+    retry_deadline: Option<Duration>,
+    /// notes about the local template found while building the differ
+    notes: Vec<String>,
+}
+
+/// configures and builds a [`Differ`](struct.Differ.html)
+#[derive(Default, Clone)]
+pub struct DifferBuilder {
+    client: Option<CloudFormationClient>,
+    ssm_client: Option<SsmClient>,
+    s3_client: Option<S3Client>,
+    region: Option<Region>,
+    stack_name: Option<String>,
+    filename: Option<PathBuf>,
+    parameters: Vec<(String, String)>,
+    artifacts_bucket: Option<String>,
+    cache: Option<Cache>,
+    refresh: bool,
+    progress: bool,
+    include_property_values: bool,
+    include_policy_changes: bool,
+    include_parameter_values: bool,
+    sort_by: SortBy,
+    only_resources: Option<ResourceFilter>,
+    stack_ready_timeout: Option<Duration>,
+    max_poll_interval: Option<Duration>,
+    change_set_name: Option<String>,
+    keep_change_set: bool,
+    resume: bool,
+    retain_on_error: bool,
+    preserve_line_endings: bool,
+    diff_options: DiffOptions,
+    template_format: Option<TemplateFormat>,
+    template_stage: Stage,
+    change_set: ChangeSetOptions,
+    before: Option<PathBuf>,
+    body: Option<String>,
+    strict: bool,
+    max_template_bytes: Option<usize>,
+    identity: Option<CallerIdentity>,
+    retry_deadline: Option<Duration>,
+}
+
+impl DifferBuilder {
+    /// CloudFormation client used for stack operations. defaults to a client for `region`
+    pub fn client(
+        mut self,
+        client: CloudFormationClient,
+    ) -> Self {
+        self.client = Some(client);
+        self
+    }
+
+    /// SSM client used to resolve `ssm:` parameters. defaults to a client for `region`
+    pub fn ssm_client(
+        mut self,
+        ssm_client: SsmClient,
+    ) -> Self {
+        self.ssm_client = Some(ssm_client);
+        self
+    }
+
+    /// S3 client used to upload large templates. defaults to a client for `region`
+    pub fn s3_client(
+        mut self,
+        s3_client: S3Client,
+    ) -> Self {
+        self.s3_client = Some(s3_client);
+        self
+    }
+
+    /// region of the stack. defaults to `Region::default()`
+    pub fn region(
+        mut self,
+        region: Region,
+    ) -> Self {
+        self.region = Some(region);
+        self
+    }
+
+    /// name of the stack to diff against (required)
+    pub fn stack_name<S: Into<String>>(
+        mut self,
+        stack_name: S,
+    ) -> Self {
+        self.stack_name = Some(stack_name.into());
+        self
+    }
+
+    /// path to the local template (required)
+    pub fn template<P: Into<PathBuf>>(
+        mut self,
+        filename: P,
+    ) -> Self {
+        self.filename = Some(filename.into());
+        self
+    }
+
+    /// body of the local template, in place of reading it from `template`. `template`
+    /// still names the template in diffs, and its extension selects the template's format
+    pub fn template_body<S: Into<String>>(
+        mut self,
+        body: S,
+    ) -> Self {
+        self.body = Some(body.into());
+        self
+    }
+
+    /// template parameters, merged with the stack's current parameters
+    pub fn parameters(
+        mut self,
+        parameters: Vec<(String, String)>,
+    ) -> Self {
+        self.parameters = parameters;
+        self
+    }
+
+    /// S3 bucket to upload templates exceeding `MAX_TEMPLATE_BODY_BYTES` to
+    pub fn artifacts_bucket<S: Into<String>>(
+        mut self,
+        bucket: S,
+    ) -> Self {
+        self.artifacts_bucket = Some(bucket.into());
+        self
+    }
+
+    /// cache for current stack templates
+    pub fn cache(
+        mut self,
+        cache: Cache,
+    ) -> Self {
+        self.cache = Some(cache);
+        self
+    }
+
+    /// always fetch a fresh copy of the current stack template, bypassing the cache
+    pub fn refresh(
+        mut self,
+        refresh: bool,
+    ) -> Self {
+        self.refresh = refresh;
+        self
+    }
+
+    /// report change set progress on stderr
+    pub fn progress(
+        mut self,
+        progress: bool,
+    ) -> Self {
+        self.progress = progress;
+        self
+    }
+
+    /// annotate modified resources with their before and after property values
+    pub fn include_property_values(
+        mut self,
+        include_property_values: bool,
+    ) -> Self {
+        self.include_property_values = include_property_values;
+        self
+    }
+
+    /// annotate changed IAM resources with the statements added to, removed from, or
+    /// modified within their policy documents
+    pub fn include_policy_changes(
+        mut self,
+        include_policy_changes: bool,
+    ) -> Self {
+        self.include_policy_changes = include_policy_changes;
+        self
+    }
+
+    /// include the before and after values of changed parameters. NoEcho values are
+    /// redacted either way
+    pub fn include_parameter_values(
+        mut self,
+        include_parameter_values: bool,
+    ) -> Self {
+        self.include_parameter_values = include_parameter_values;
+        self
+    }
+
+    /// order of the diff's changes. defaults to `SortBy::Action`
+    pub fn sort_by(
+        mut self,
+        sort_by: SortBy,
+    ) -> Self {
+        self.sort_by = sort_by;
+        self
+    }
+
+    /// only report changes to resources whose logical ids match the filter
+    pub fn only_resources(
+        mut self,
+        only_resources: ResourceFilter,
+    ) -> Self {
+        self.only_resources = Some(only_resources);
+        self
+    }
+
+    /// wait up to `timeout` for a stack with an operation in progress to stabilize
+    /// before diffing. by default, diffing a stack that isn't ready fails immediately
+    pub fn wait_for_stack_ready(
+        mut self,
+        timeout: Duration,
+    ) -> Self {
+        self.stack_ready_timeout = Some(timeout);
+        self
+    }
+
+    /// cap on the interval between change set polls, which starts short and backs off.
+    /// defaults to `MAX_POLL_INTERVAL`
+    pub fn max_poll_interval(
+        mut self,
+        max_poll_interval: Duration,
+    ) -> Self {
+        self.max_poll_interval = Some(max_poll_interval);
+        self
+    }
+
+    /// name of the change set to create. defaults to `CHANGESET_NAME`
+    pub fn change_set_name<S: Into<String>>(
+        mut self,
+        change_set_name: S,
+    ) -> Self {
+        self.change_set_name = Some(change_set_name.into());
+        self
+    }
+
+    /// leave the change set in place after diffing so that it may be executed later.
+    /// a kept change set of the same name from a previous run is replaced
+    pub fn keep_change_set(
+        mut self,
+        keep_change_set: bool,
+    ) -> Self {
+        self.keep_change_set = keep_change_set;
+        self
+    }
+
+    /// describe an existing change set named `change_set_name` rather than creating it,
+    /// resuming a run that stopped after creating it. the change set is created as usual
+    /// when it doesn't exist, and is deleted after diffing unless kept
+    pub fn resume(
+        mut self,
+        resume: bool,
+    ) -> Self {
+        self.resume = resume;
+        self
+    }
+
+    /// leave the change set in place when it doesn't complete cleanly, so that it may be
+    /// inspected. change sets that complete are still deleted
+    pub fn retain_on_error(
+        mut self,
+        retain_on_error: bool,
+    ) -> Self {
+        self.retain_on_error = retain_on_error;
+        self
+    }
+
+    /// who the differ's credentials act as, recorded in its diffs
+    pub fn identity(
+        mut self,
+        identity: CallerIdentity,
+    ) -> Self {
+        self.identity = Some(identity);
+        self
+    }
+
+    /// stop retrying failed requests once `budget` has elapsed from the start of each
+    /// diff, bounding how long throttling backoff can extend it. retries are otherwise
+    /// limited only by count
+    pub fn retry_deadline(
+        mut self,
+        budget: Duration,
+    ) -> Self {
+        self.retry_deadline = Some(budget);
+        self
+    }
+
+    /// fail to build when the template declares more resources than a stack may hold,
+    /// rather than warning
+    pub fn strict(
+        mut self,
+        strict: bool,
+    ) -> Self {
+        self.strict = strict;
+        self
+    }
+
+    /// fail to build when the template is larger than `max` bytes. templates are always
+    /// held to the limit CloudFormation accepts inline or, with an artifacts bucket, from S3
+    pub fn max_template_bytes(
+        mut self,
+        max: usize,
+    ) -> Self {
+        self.max_template_bytes = Some(max);
+        self
+    }
+
+    /// submit and diff the local template's line endings as is. by default CRLF line
+    /// endings are converted to LF, matching the templates CloudFormation stores
+    pub fn preserve_line_endings(
+        mut self,
+        preserve_line_endings: bool,
+    ) -> Self {
+        self.preserve_line_endings = preserve_line_endings;
+        self
+    }
+
+    /// lines of context around each change in the template diff. defaults to
+    /// `template::DEFAULT_CONTEXT_LINES`, or whatever `CLIFF_DIFFER` prints by default
+    pub fn context_lines(
+        mut self,
+        context_lines: usize,
+    ) -> Self {
+        self.diff_options.context_lines = Some(context_lines);
+        self
+    }
+
+    /// diff the local template against the deployed template, flipping added and
+    /// removed lines
+    pub fn reverse(
+        mut self,
+        reverse: bool,
+    ) -> Self {
+        self.diff_options.reverse = reverse;
+        self
+    }
+
+    /// ignore lines of the template diff that differ only in whitespace, passing `-w`
+    /// to `CLIFF_DIFFER`
+    pub fn ignore_whitespace(
+        mut self,
+        ignore_whitespace: bool,
+    ) -> Self {
+        self.diff_options.ignore_whitespace = ignore_whitespace;
+        self
+    }
+
+    /// most bytes of differ output captured for the template diff, truncating larger
+    /// diffs with a notice. 0 captures everything. defaults to
+    /// `template::DEFAULT_MAX_DIFF_BYTES`
+    pub fn max_diff_bytes(
+        mut self,
+        max_diff_bytes: usize,
+    ) -> Self {
+        self.diff_options.max_bytes = max_diff_bytes;
+        self
+    }
+
+    /// how long the differ may run before it's killed and the diff fails. None lets it
+    /// run forever. defaults to `template::DEFAULT_DIFF_TIMEOUT`
+    pub fn diff_timeout(
+        mut self,
+        timeout: Option<Duration>,
+    ) -> Self {
+        self.diff_options.timeout = timeout;
+        self
+    }
+
+    /// format of the local template, for suffixing the temporary copies handed to the
+    /// differ. defaults to the format of the template's extension
+    pub fn template_format(
+        mut self,
+        template_format: TemplateFormat,
+    ) -> Self {
+        self.template_format = Some(template_format);
+        self
+    }
+
+    /// stage of the deployed template to diff against. defaults to `Stage::Original`.
+    /// only original templates are cached
+    pub fn template_stage(
+        mut self,
+        template_stage: Stage,
+    ) -> Self {
+        self.template_stage = template_stage;
+        self
+    }
+
+    /// description of the change set to create
+    pub fn description<S: Into<String>>(
+        mut self,
+        description: S,
+    ) -> Self {
+        self.change_set.description = Some(description.into());
+        self
+    }
+
+    /// ARNs of SNS topics the change set's stack operations notify
+    pub fn notification_arns(
+        mut self,
+        notification_arns: Vec<String>,
+    ) -> Self {
+        self.change_set.notification_arns = notification_arns;
+        self
+    }
+
+    /// ARN of the IAM role CloudFormation assumes to make the change set's changes,
+    /// rather than using the caller's credentials
+    pub fn stack_role_arn<S: Into<String>>(
+        mut self,
+        role_arn: S,
+    ) -> Self {
+        self.change_set.role_arn = Some(role_arn.into());
+        self
+    }
+
+    /// ARNs of CloudWatch alarms that roll back the change set's stack operation when
+    /// they go into alarm, so the change set matches how the stack is deployed
+    pub fn rollback_alarm_arns(
+        mut self,
+        rollback_alarm_arns: Vec<String>,
+    ) -> Self {
+        self.change_set.rollback_alarm_arns = rollback_alarm_arns;
+        self
+    }
+
+    /// minutes CloudFormation monitors rollback alarms for after deploying the change set
+    pub fn rollback_monitoring_minutes(
+        mut self,
+        minutes: u32,
+    ) -> Self {
+        self.change_set.rollback_monitoring_minutes = Some(i64::from(minutes));
+        self
+    }
+
+    /// capabilities to acknowledge, like `CAPABILITY_AUTO_EXPAND`, in addition to
+    /// `CAPABILITY_IAM` and `CAPABILITY_NAMED_IAM` which are always acknowledged
+    pub fn capabilities(
+        mut self,
+        capabilities: Vec<String>,
+    ) -> Self {
+        self.change_set.capabilities = capabilities;
+        self
+    }
+
+    /// token identifying the change set's creation. each differ derives its own token
+    /// from it, its stack, and its region, which it reuses when creating the change set
+    /// is retried. defaults to a random token generated for each differ
+    pub fn client_request_token<S: Into<String>>(
+        mut self,
+        token: S,
+    ) -> Self {
+        self.change_set.client_token = Some(token.into());
+        self
+    }
+
+    /// diff the local template's text against another local template rather than the
+    /// deployed template. a change set is still created against the deployed stack
+    pub fn before_template<P: Into<PathBuf>>(
+        mut self,
+        filename: P,
+    ) -> Self {
+        self.before = Some(filename.into());
+        self
+    }
+
+    pub fn build(self) -> Result<Differ, Error> {
+        let stack_name = self
+            .stack_name
+            .ok_or_else(|| Error::Config("a stack name is required".into()))?;
+        let filename = self
+            .filename
+            .ok_or_else(|| Error::Config("a template is required".into()))?;
+        let preserve_line_endings = self.preserve_line_endings;
+        let normalize = |body: String| {
+            if preserve_line_endings {
+                body
+            } else {
+                template::normalize_line_endings(&body)
+            }
+        };
+        let read = |filename: &Path| {
+            template::template_body(filename)
+                .map_err(|err| {
+                    Error::Template(format!(
+                        "Failed to read template {}: {}",
+                        filename.display(),
+                        err
+                    ))
+                })
+                .map(normalize)
+        };
+        let template_body = match self.body {
+            Some(body) => normalize(body),
+            _ => read(&filename)?,
+        };
+        let before = match self.before {
+            Some(before) => {
+                let body = read(&before)?;
+                Some((before, body))
+            }
+            _ => None,
+        };
+        template_size(
+            template_body.len(),
+            self.artifacts_bucket.is_some(),
+            self.max_template_bytes,
+        )?;
+        let mut notes = Vec::new();
+        if let Ok(template) = parse(&template_body) {
+            let count = template::resource_count(&template);
+            if let Some(warning) = resource_limit_warning(count, self.strict)? {
+                notes.push(format!("warning: {} {}", stack_name, warning));
+            }
+        }
+        let region = self.region.unwrap_or_default();
+        let mut change_set = self.change_set;
+        change_set.client_token = Some(match &change_set.client_token {
+            Some(token) => derived_client_token(token, &stack_name, &region),
+            _ => client_token(),
+        });
+        Ok(Differ {
+            client: self
+                .client
+                .unwrap_or_else(|| CloudFormationClient::new(region.clone())),
+            ssm_client: self
+                .ssm_client
+                .unwrap_or_else(|| SsmClient::new(region.clone())),
+            s3_client: self
+                .s3_client
+                .unwrap_or_else(|| S3Client::new(region.clone())),
+            region,
+            stack_name,
+            filename,
+            template_body,
+            parameters: self.parameters,
+            artifacts_bucket: self.artifacts_bucket,
+            cache: self.cache,
+            refresh: self.refresh,
+            progress: self.progress,
+            include_property_values: self.include_property_values,
+            include_policy_changes: self.include_policy_changes,
+            include_parameter_values: self.include_parameter_values,
+            sort_by: self.sort_by,
+            only_resources: self.only_resources,
+            stack_ready_timeout: self.stack_ready_timeout,
+            max_poll_interval: self.max_poll_interval.unwrap_or(MAX_POLL_INTERVAL),
+            change_set_name: self
+                .change_set_name
+                .unwrap_or_else(|| CHANGESET_NAME.into()),
+            keep_change_set: self.keep_change_set,
+            resume: self.resume,
+            retain_on_error: self.retain_on_error,
+            diff_options: self.diff_options,
+            template_format: self.template_format,
+            template_stage: self.template_stage,
+            change_set,
+            before,
+            identity: self.identity,
+            retry_deadline: self.retry_deadline,
+            notes,
+        })
+    }
+}
+
+impl Differ {
+    pub fn builder() -> DifferBuilder {
+        DifferBuilder::default()
+    }
+
+    /// name of the stack diffed against
+    pub fn stack_name(&self) -> &str {
+        &self.stack_name
+    }
+
+    /// region of the stack diffed against
+    pub fn region(&self) -> &Region {
+        &self.region
+    }
+
+    /// the local template diffed
+    pub fn filename(&self) -> &Path {
+        &self.filename
+    }
+
+    /// notes about the local template, like nearing the stack resource limit, found
+    /// while building the differ. diffs carry these along with their own
+    pub fn notes(&self) -> &[String] {
+        &self.notes
+    }
+
+    /// when retries stop for an operation starting now
+    fn deadline(&self) -> Option<Instant> {
+        deadline(self.retry_deadline)
+    }
+
+    /// deletes the change set a diff creates, as when a diff is interrupted before it
+    /// could clean up after itself
+    pub fn delete_change_set(&self) -> impl Future<Item = (), Error = Error> + Send {
+        let deadline = self.deadline();
+        delete_changset(
+            self.client.clone(),
+            self.stack_name.clone(),
+            self.change_set_name.clone(),
+            deadline,
+        )
+    }
+
+    /// compares the deployed stack's parameters with those submitted for the local
+    /// template, without fetching templates or creating a change set. values are
+    /// included, with those of NoEcho and SecureString parameters redacted
+    pub fn diff_parameters(
+        &self
+    ) -> impl Future<Item = Vec<ParameterChange>, Error = Error> + Send {
+        let template_body = self.template_body.clone();
+        let filename = self.filename.clone();
+        let deadline = self.deadline();
+        current_parameters(self.client.clone(), self.stack_name.clone(), deadline)
+            .join(resolve_parameters(
+                self.client.clone(),
+                self.ssm_client.clone(),
+                self.parameters.clone(),
+                deadline,
+            ))
+            .and_then(move |(current, (provided, sensitive))| {
+                let local = parse(&template_body).map_err(|err| {
+                    Error::Template(format!("failed to parse {}: {}", filename.display(), err))
+                })?;
+                let submitted = merge(current.clone(), provided.clone());
+                let mut changes = parameter_changes(&current, &submitted, &provided, &local);
+                Redactor::new(&[&template_body], &submitted)
+                    .sensitive(&sensitive, &submitted)
+                    .apply_parameters(&mut changes);
+                Ok(changes)
+            })
+    }
+
+    /// creates, describes, and then deletes a change set for the local template
+    pub fn diff(&self) -> impl Future<Item = Diff, Error = Error> + Send {
+        let deadline = self.deadline();
+        let Differ {
+            client,
+            ssm_client,
+            s3_client,
+            region,
+            stack_name,
+            filename,
+            template_body,
+            parameters,
+            artifacts_bucket,
+            cache,
+            refresh,
+            progress,
+            include_property_values,
+            include_policy_changes,
+            include_parameter_values,
+            sort_by,
+            only_resources,
+            stack_ready_timeout,
+            max_poll_interval,
+            change_set_name,
+            keep_change_set,
+            resume,
+            retain_on_error,
+            diff_options,
+            template_format,
+            template_stage,
+            change_set,
+            before,
+            identity,
+            notes,
+            ..
+        } = self.clone();
+        let notes: Notes = Arc::new(Mutex::new(notes));
+        let (notes2, notes3, notes4, notes5) =
+            (notes.clone(), notes.clone(), notes.clone(), notes.clone());
+        let (change_set_name2, change_set_name3, change_set_name4) = (
+            change_set_name.clone(),
+            change_set_name.clone(),
+            change_set_name.clone(),
+        );
+        let (cf, cf2, cf3) = (client.clone(), client.clone(), client.clone());
+        let (stack_name2, stack_name3, stack_name4, stack_name5) = (
+            stack_name.clone(),
+            stack_name.clone(),
+            stack_name.clone(),
+            stack_name.clone(),
+        );
+        let timings = Shared::default();
+        let (timings2, timings3, timings4, timings5) = (
+            timings.clone(),
+            timings.clone(),
+            timings.clone(),
+            timings.clone(),
+        );
+        let ready = stack_ready(
+            client.clone(),
+            stack_name.clone(),
+            stack_ready_timeout,
+            deadline,
+        );
+
+        let source = template_source(
+            s3_client,
+            region.clone(),
+            artifacts_bucket,
+            stack_name.clone(),
+            template_body.clone(),
+        );
+
+        let local_body = template_body.clone();
+        let diff_region = region.clone();
+        let status_client = client.clone();
+        let label = match &before {
+            Some((filename, _)) => filename.display().to_string(),
+            _ => stack_name.clone(),
+        };
+        let timings6 = timings.clone();
+        let stack_name6 = stack_name.clone();
+        // a stack that was never deployed has no current template to speak of
+        let template = move |review: bool| {
+            let current = match before {
+                Some((_, body)) => future::Either::A(future::ok(body)),
+                _ if review => {
+                    note(
+                        &notes2,
+                        format!(
+                            "stack {} has never been deployed. previewing its creation",
+                            stack_name6
+                        ),
+                    );
+                    future::Either::A(future::ok(String::new()))
+                }
+                _ => future::Either::B(timed(timings6, Phase::GetTemplate, move || {
+                    cached_template(
+                        client,
+                        stack_name6,
+                        template_stage,
+                        region,
+                        cache,
+                        refresh,
+                        deadline,
+                    )
+                })),
+            };
+            let fetched = matches!(current, future::Either::B(_));
+            current.and_then(move |current| {
+                if fetched && current.trim().is_empty() {
+                    return future::Either::A(
+                        stack_status(status_client, stack_name5.clone(), deadline).map(
+                            move |status| {
+                                note(
+                                    &notes2,
+                                    format!(
+                                        "no template available for stack {} in state {}",
+                                        stack_name5, status
+                                    ),
+                                );
+                                (false, current)
+                            },
+                        ),
+                    );
+                }
+                future::Either::B(future::ok((true, current)))
+            })
+        };
+
+        let changeset = move |review: bool| {
+            let prev_parameters = if review {
+                future::Either::A(future::ok(Vec::new()))
+            } else {
+                future::Either::B(current_parameters(cf.clone(), stack_name.clone(), deadline))
+            };
+            let change_set = ChangeSetOptions {
+                create: review,
+                ..change_set
+            };
+            let resumed = if resume {
+                future::Either::A(existing_change_set(
+                    cf.clone(),
+                    stack_name.clone(),
+                    change_set_name.clone(),
+                    deadline,
+                ))
+            } else {
+                future::Either::B(future::ok(None))
+            };
+            prev_parameters
+                .join4(
+                    resolve_parameters(cf.clone(), ssm_client, parameters, deadline),
+                    source,
+                    resumed,
+                )
+                .and_then(
+                    move |(prev_parameters, (parameters, sensitive), source, resumed)| {
+                        let submitted = merge(prev_parameters.clone(), parameters.clone());
+                        if let Some(resumed) = resumed {
+                            note(
+                                &notes3,
+                                format!(
+                                    "resuming change set {} of stack {}",
+                                    change_set_name, stack_name
+                                ),
+                            );
+                            let stale = stale_parameters(&resumed, &submitted);
+                            if !stale.is_empty() {
+                                note(
+                                    &notes3,
+                                    format!(
+                                        "warning: change set {} of stack {} was created with other values of {}. its changes may not reflect the parameters submitted now",
+                                        change_set_name,
+                                        stack_name,
+                                        stale.join(", ")
+                                    ),
+                                );
+                            }
+                            return future::Either::A(future::ok((
+                                prev_parameters,
+                                parameters,
+                                submitted,
+                                sensitive,
+                            )));
+                        }
+                        let replace = if keep_change_set {
+                            future::Either::A(
+                                delete_changset(
+                                    cf.clone(),
+                                    stack_name.clone(),
+                                    change_set_name4,
+                                    deadline,
+                                )
+                                .then(|_| Ok(())),
+                            )
+                        } else {
+                            future::Either::B(future::ok(()))
+                        };
+                        future::Either::B(replace.and_then(move |_| {
+                            timed(timings2, Phase::CreateChangeSet, move || {
+                                create_changeset(
+                                    cf,
+                                    stack_name,
+                                    change_set_name,
+                                    source,
+                                    submitted.clone(),
+                                    change_set,
+                                    deadline,
+                                )
+                                .map(move |_| (prev_parameters, parameters, submitted, sensitive))
+                            })
+                        }))
+                    },
+                )
+        };
+
+        ready
+            .and_then(move |status| {
+                let review = status == REVIEW_IN_PROGRESS;
+                template(review).and_then(move |template| {
+                    changeset(review).map(|parameters| (template, parameters))
+                })
+            })
+            .and_then(
+                move |(
+                    (available, current),
+                    (prev_parameters, provided, parameters, sensitive),
+                )| {
+                    timed(timings3, Phase::DescribeChangeSet, move || {
+                        describe_changeset(
+                            cf2,
+                            stack_name2,
+                            change_set_name2,
+                            Poll {
+                                show_progress: progress,
+                                timings: timings4,
+                                interval: POLL_INTERVAL.min(max_poll_interval),
+                                max_interval: max_poll_interval,
+                            },
+                            deadline,
+                        )
+                    })
+                    .map(move |changeset| {
+                        let redactor = Redactor::new(&[&current, &template_body], &parameters)
+                            .sensitive(&sensitive, &parameters);
+                        // secrets are redacted before templates are written out for the differ
+                        let (template, template_error) = if available {
+                            match diff_template(
+                                &label,
+                                &filename,
+                                template_format,
+                                &redactor.redact(&local_body),
+                                redactor.redact(&current),
+                                &diff_options,
+                            ) {
+                                Ok(diff) => (Some(diff), None),
+                                Err(err) => (None, Some(err.to_string())),
+                            }
+                        } else {
+                            (None, None)
+                        };
+                        let mut result = diff_changeset(changeset.clone());
+                        sort(&mut result.changes, sort_by);
+                        if let Some(only_resources) = &only_resources {
+                            result.filter(only_resources);
+                        }
+                        let local = parse(&template_body);
+                        if include_property_values || include_policy_changes {
+                            match (parse(&current), &local) {
+                                (Ok(before), Ok(after)) => {
+                                    if include_property_values {
+                                        annotate(&mut result, &before, after);
+                                    }
+                                    if include_policy_changes {
+                                        policy::annotate(&mut result, &before, after);
+                                    }
+                                }
+                                _ => log::debug!("failed to parse templates to annotate changes"),
+                            }
+                        }
+                        let mut parameter_changes = match &local {
+                            Ok(local) => {
+                                parameter_changes(&prev_parameters, &parameters, &provided, local)
+                            }
+                            _ => Vec::new(),
+                        };
+                        if !include_parameter_values {
+                            for change in &mut parameter_changes {
+                                change.before = None;
+                                change.after = None;
+                            }
+                        }
+                        if cosmetic(template.as_deref(), &result) {
+                            note(
+                                &notes4,
+                                format!(
+                                    "note: the templates of stack {} differ, but CloudFormation found no resource changes. the differences may be cosmetic, like comments or formatting",
+                                    stack_name4
+                                ),
+                            );
+                        }
+                        let mut diff = Diff {
+                            stack_name: stack_name4,
+                            region: diff_region,
+                            filename,
+                            template_body: template_body.clone(),
+                            template,
+                            result,
+                            parameters: parameter_changes,
+                            changeset,
+                            timings: Timings::default(),
+                            identity: None,
+                            template_error,
+                            notes: Vec::new(),
+                        };
+                        redactor.apply(&mut diff);
+                        diff
+                    })
+                },
+            )
+            .and_then(move |diff| {
+                let retain = retain_on_error && diff.result.status != Status::Complete;
+                if retain {
+                    note(
+                        &notes5,
+                        format!(
+                            "retained change set {}",
+                            diff.changeset
+                                .change_set_id
+                                .as_deref()
+                                .unwrap_or(&change_set_name3)
+                        ),
+                    );
+                }
+                let delete = if keep_change_set || retain {
+                    future::Either::A(future::ok(()))
+                } else {
+                    future::Either::B(timed(timings5, Phase::DeleteChangeSet, move || {
+                        delete_changset(cf3, stack_name3, change_set_name3, deadline)
+                    }))
+                };
+                delete.map(move |_| Diff {
+                    timings: timings.lock().map(|t| t.clone()).unwrap_or_default(),
+                    identity,
+                    notes: notes.lock().map(|n| n.clone()).unwrap_or_default(),
+                    ..diff
+                })
+            })
+    }
+}
+
+/// true when templates differ textually but their change set changes nothing
+fn cosmetic(
+    template: Option<&str>,
+    result: &DiffResult,
+) -> bool {
+    template.is_some_and(|template| !template.trim().is_empty())
+        && result.status == Status::Complete
+        && result.changes.is_empty()
+        && result.hidden == 0
+}
+
+/// when retries stop for an operation starting now, once `budget` has elapsed. without
+/// a budget, retries are limited only by count
+pub fn deadline(budget: Option<Duration>) -> Option<Instant> {
+    budget.map(|budget| Instant::now() + budget)
+}
+
+/// true when there's time left to retry before `deadline`, if any
+fn before_deadline(
+    deadline: Option<Instant>,
+    now: Instant,
+) -> bool {
+    deadline.is_none_or(|deadline| now < deadline)
+}
+
+/// true unless the retry deadline has passed
+fn within_retry_deadline(deadline: Option<Instant>) -> bool {
+    let within = before_deadline(deadline, Instant::now());
+    if !within {
+        log::debug!("retry deadline passed. not retrying");
+    }
+    within
+}
+
+/// errors worth retrying for any operation, while `deadline` allows
+///
+/// credentials are resolved anew for each request, so retrying after an expired token
+/// picks up refreshed session credentials
+fn retryable(
+    err: &Error,
+    deadline: Option<Instant>,
+) -> bool {
+    matches!(
+        err,
+        Error::Throttling(_) | Error::ExpiredToken(_) | Error::Network(_)
+    ) && within_retry_deadline(deadline)
+}
+
+/// diffs many stacks, running at most `concurrency` diffs at a time
+///
+/// the result of each diff is yielded in the same order as the provided differs. a
+/// failed diff doesn't stop the others
+pub fn diff_all(
+    differs: Vec<Differ>,
+    concurrency: usize,
+) -> impl Future<Item = Vec<Result<Diff, Error>>, Error = Error> + Send {
+    stream::iter_ok(differs)
+        .map(|differ| differ.diff().then(Ok))
+        .buffered(concurrency.max(1))
+        .collect()
+}
+
+/// diffs many stacks like `diff_all`, but stops at the first failed diff
+///
+/// results are yielded in order up to and including the failed diff. diffs after it
+/// are never started, or are dropped when already underway
+pub fn diff_all_fail_fast(
+    differs: Vec<Differ>,
+    concurrency: usize,
+) -> impl Future<Item = Vec<Result<Diff, Error>>, Error = Error> + Send {
+    stream::iter_ok(differs)
+        .map(|differ| differ.diff().then(Ok))
+        .buffered(concurrency.max(1))
+        .map_err(|err| vec![Err(err)])
+        .fold(Vec::new(), |mut results, result| {
+            let failed = result.is_err();
+            results.push(result);
+            if failed {
+                Err(results)
+            } else {
+                Ok(results)
+            }
+        })
+        .or_else(Ok)
+}
+
+/// status of a stack created by a change set that was never executed
+const REVIEW_IN_PROGRESS: &str = "REVIEW_IN_PROGRESS";
+
+/// true for stack statuses of an operation still underway
+fn in_progress(status: &str) -> bool {
+    status.ends_with("_IN_PROGRESS") && status != REVIEW_IN_PROGRESS
+}
+
+/// the current status of a stack, e.g. `UPDATE_COMPLETE`
+fn stack_status(
+    cf: CloudFormationClient,
+    stack_name: String,
+    deadline: Option<Instant>,
+) -> impl Future<Item = String, Error = Error> {
+    let stack = stack_name.clone();
+    RETRIES
+        .retry_if(
+            move || {
+                cf.describe_stacks(DescribeStacksInput {
+                    stack_name: Some(stack_name.clone()),
+                    ..DescribeStacksInput::default()
+                })
+                .map_err(Error::from)
+            },
+            move |err: &Error| {
+                log::debug!("describe stack error for stack {}: {}", stack, err);
+                retryable(err, deadline)
+            },
+        )
+        .map(|output| {
+            output
+                .stacks
+                .unwrap_or_default()
+                .into_iter()
+                .next()
+                .map(|stack| stack.stack_status)
+                .unwrap_or_default()
+        })
+}
+
+/// ensures a stack has no operation in progress, polling until `timeout` when provided,
+/// yielding the stack's status
+fn stack_ready(
+    cf: CloudFormationClient,
+    stack_name: String,
+    timeout: Option<Duration>,
+    deadline: Option<Instant>,
+) -> impl Future<Item = String, Error = Error> {
+    let ready_by = timeout.map(|timeout| Instant::now() + timeout);
+    future::loop_fn((), move |_| {
+        let name = stack_name.clone();
+        stack_status(cf.clone(), stack_name.clone(), deadline).and_then(move |status| {
+            if !in_progress(&status) {
+                return future::Either::B(future::ok(Loop::Break(status)));
+            }
+            match ready_by {
+                Some(ready_by) if Instant::now() < ready_by => {
+                    log::debug!("waiting on stack {} in status {}", name, status);
+                    future::Either::A(delay(STACK_READY_POLL).map(Loop::Continue))
+                }
+                _ => future::Either::B(future::err(Error::StackNotReady(name, status))),
+            }
+        })
+    })
+}
+
+/// lists the names of all live stacks whose names start with `prefix`
+pub fn stacks(
+    cf: CloudFormationClient,
+    prefix: String,
+    deadline: Option<Instant>,
+) -> impl Future<Item = Vec<String>, Error = Error> {
+    future::loop_fn(
+        (None, Vec::new()),
+        move |(next_token, mut names): (Option<String>, Vec<String>)| {
+            let cf = cf.clone();
+            let prefix = prefix.clone();
+            RETRIES
+                .retry_if(
+                    move || {
+                        cf.describe_stacks(DescribeStacksInput {
+                            next_token: next_token.clone(),
+                            ..DescribeStacksInput::default()
+                        })
+                        .map_err(Error::from)
+                    },
+                    move |err: &Error| {
+                        log::debug!("list stacks error {}", err);
+                        retryable(err, deadline)
+                    },
+                )
+                .map(move |output| {
+                    names.extend(
+                        output
+                            .stacks
+                            .unwrap_or_default()
+                            .into_iter()
+                            .map(|stack| stack.stack_name)
+                            .filter(|name| name.starts_with(&prefix)),
+                    );
+                    match output.next_token {
+                        Some(token) => Loop::Continue((Some(token), names)),
+                        _ => Loop::Break(names),
+                    }
+                })
+        },
+    )
+}
+
+/// checks a template's resource count against the most a stack may hold, yielding a
+/// warning as it nears or exceeds the limit. exceeding it fails when `strict`
+fn resource_limit_warning(
+    count: usize,
+    strict: bool,
+) -> Result<Option<String>, Error> {
+    if count > MAX_STACK_RESOURCES && strict {
+        return Err(Error::TooManyResources(count));
+    }
+    Ok(match count {
+        count if count > MAX_STACK_RESOURCES => Some(format!(
+            "template declares {} resources, more than the {} CloudFormation allows in a stack. the change set may be created, but executing it will fail",
+            count, MAX_STACK_RESOURCES
+        )),
+        count if count >= STACK_RESOURCES_WARNING => Some(format!(
+            "template declares {} resources, nearing the {} CloudFormation allows in a stack",
+            count, MAX_STACK_RESOURCES
+        )),
+        _ => None,
+    })
+}
+
+/// validates a template body with CloudFormation
+pub fn validate_template(
+    cf: CloudFormationClient,
+    template_body: String,
+    deadline: Option<Instant>,
+) -> impl Future<Item = ValidateTemplateOutput, Error = Error> {
+    RETRIES.retry_if(
+        move || {
+            cf.validate_template(ValidateTemplateInput {
+                template_body: Some(template_body.clone()),
+                ..ValidateTemplateInput::default()
+            })
+            .map_err(Error::from)
+        },
+        move |err: &Error| {
+            log::debug!("validate template error {}", err);
+            retryable(err, deadline)
+        },
+    )
+}
+
+/// true when CloudFormation's validation error says a stack doesn't exist, as when
+/// describing a change set of a stack that was never created
+fn stack_missing(message: &str) -> bool {
+    message.starts_with("Stack") && message.ends_with("does not exist")
+}
+
+/// describes a change set once, retrying errors worth retrying
+fn describe_change_set(
+    cf: CloudFormationClient,
+    stack_name: String,
+    change_set_name: String,
+    deadline: Option<Instant>,
+) -> impl Future<Item = DescribeChangeSetOutput, Error = Error> {
+    let stack = stack_name.clone();
+    RETRIES.retry_if(
+        move || {
+            cf.describe_change_set(DescribeChangeSetInput {
+                change_set_name: change_set_name.clone(),
+                stack_name: Some(stack_name.clone()),
+                ..DescribeChangeSetInput::default()
+            })
+            .map_err(Error::from)
+        },
+        move |err: &Error| {
+            log::debug!("describe changeset error for stack {}: {}", stack, err);
+            retryable(err, deadline)
+        },
+    )
+}
+
+/// a change set, when it exists
+fn existing_change_set(
+    cf: CloudFormationClient,
+    stack_name: String,
+    change_set_name: String,
+    deadline: Option<Instant>,
+) -> impl Future<Item = Option<DescribeChangeSetOutput>, Error = Error> {
+    describe_change_set(cf, stack_name, change_set_name, deadline).then(|result| match result {
+        Ok(changeset) => Ok(Some(changeset)),
+        Err(Error::DescribeChangeset(RusotoError::Service(
+            DescribeChangeSetError::ChangeSetNotFound(_),
+        ))) => Ok(None),
+        Err(Error::Validation(message)) if stack_missing(&message) => Ok(None),
+        Err(err) => Err(err),
+    })
+}
+
+/// keys of parameters whose values in a change set differ from those submitted, or
+/// that only one of them has. values CloudFormation masks can't be compared
+fn stale_parameters(
+    changeset: &DescribeChangeSetOutput,
+    submitted: &[(String, String)],
+) -> Vec<String> {
+    let created = changeset
+        .parameters
+        .iter()
+        .flatten()
+        .filter_map(|parameter| {
+            Some((
+                parameter.parameter_key.as_deref()?,
+                parameter.parameter_value.as_deref().unwrap_or_default(),
+            ))
+        })
+        .collect::<HashMap<_, _>>();
+    let mut stale = submitted
+        .iter()
+        .filter(|(key, value)| {
+            created
+                .get(key.as_str())
+                .is_none_or(|created| *created != REDACTED && created != value)
+        })
+        .map(|(key, _)| key.clone())
+        .collect::<Vec<_>>();
+    stale.extend(
+        created
+            .keys()
+            .filter(|key| !submitted.iter().any(|(submitted, _)| submitted == *key))
+            .map(|key| key.to_string()),
+    );
+    stale.sort();
+    stale
+}
+
+/// when a change set was created, as an ISO 8601 timestamp
+pub fn change_set_creation_time(
+    cf: CloudFormationClient,
+    stack_name: String,
+    change_set_name: String,
+    deadline: Option<Instant>,
+) -> impl Future<Item = Option<String>, Error = Error> {
+    describe_change_set(cf, stack_name, change_set_name, deadline)
+        .map(|output| output.creation_time)
+}
+
+/// executes a change set, typically one kept for later with `keep_change_set`
+pub fn execute_changeset(
+    cf: CloudFormationClient,
+    stack_name: String,
+    change_set_name: String,
+    deadline: Option<Instant>,
+) -> impl Future<Item = (), Error = Error> {
+    RETRIES
+        .retry_if(
+            move || {
+                cf.execute_change_set(ExecuteChangeSetInput {
+                    change_set_name: change_set_name.clone(),
+                    stack_name: Some(stack_name.clone()),
+                    ..ExecuteChangeSetInput::default()
+                })
+                .map_err(Error::from)
+            },
+            move |err: &Error| {
+                log::debug!("execute changeset error {}", err);
+                retryable(err, deadline)
+            },
+        )
+        .map(drop)
+}
+
+/// where CloudFormation should read a template from
+#[derive(Debug, Clone, PartialEq)]
+enum TemplateSource {
+    Body(String),
+    Url(String),
+}
+
+/// checks a template's size against the limit CloudFormation accepts inline or, when it
+/// may be uploaded to S3, from S3, and against a lower `max` of the caller's
+fn template_size(
+    size: usize,
+    uploadable: bool,
+    max: Option<usize>,
+) -> Result<(), Error> {
+    let limit = if uploadable {
+        MAX_TEMPLATE_URL_BYTES
+    } else {
+        MAX_TEMPLATE_BODY_BYTES
+    };
+    match max.filter(|max| *max < limit) {
+        Some(max) if size > max => Err(Error::Validation(format!(
+            "Template is {} bytes which exceeds the --max-template-bytes limit of {} bytes",
+            size, max
+        ))),
+        _ if size <= limit => Ok(()),
+        _ if !uploadable => Err(Error::TemplateTooLarge(size)),
+        _ => Err(Error::Validation(format!(
+            "Template is {} bytes which exceeds CloudFormation's limit of {} bytes for templates in S3",
+            size, MAX_TEMPLATE_URL_BYTES
+        ))),
+    }
+}
+
+/// uploads templates too large to submit inline to S3, yielding the source to submit
+fn template_source(
+    s3: S3Client,
+    region: Region,
+    bucket: Option<String>,
+    stack_name: String,
+    template_body: String,
+) -> impl Future<Item = TemplateSource, Error = Error> {
+    let bucket = match bucket {
+        Some(bucket) if template_body.len() > MAX_TEMPLATE_BODY_BYTES => bucket,
+        _ => return future::Either::A(future::ok(TemplateSource::Body(template_body))),
+    };
+    let mut hasher = DefaultHasher::new();
+    template_body.hash(&mut hasher);
+    let key = format!("cliff/{}/{:x}.template", stack_name, hasher.finish());
+    let url = format!(
+        "https://{}.s3.{}.amazonaws.com/{}",
+        bucket,
+        region.name(),
+        key
+    );
+    log::debug!("uploading template to {}", url);
+    future::Either::B(
+        s3.put_object(PutObjectRequest {
+            bucket,
+            key,
+            body: Some(template_body.into_bytes().into()),
+            ..PutObjectRequest::default()
+        })
+        .map_err(Error::Upload)
+        .map(|_| TemplateSource::Url(url)),
+    )
+}
+
+/// the decrypted value of an SSM parameter, and whether it's a SecureString
+fn ssm_parameter(
+    ssm: SsmClient,
+    name: String,
+) -> impl Future<Item = (String, bool), Error = Error> {
+    ssm.get_parameter(GetParameterRequest {
+        name: name.clone(),
+        with_decryption: Some(true),
+    })
+    .map_err(move |err| Error::Ssm(name, err))
+    .map(|result| {
+        let parameter = result.parameter.unwrap_or_default();
+        (
+            parameter.value.unwrap_or_default(),
+            parameter.type_.as_deref() == Some("SecureString"),
+        )
+    })
+}
+
+/// splits a `Stack.Output` reference into its stack name and output key. stack names
+/// can't contain dots, so the first separates them
+fn output_reference(reference: &str) -> Option<(&str, &str)> {
+    match reference.split_once('.') {
+        Some((stack, output)) if !stack.is_empty() && !output.is_empty() => Some((stack, output)),
+        _ => None,
+    }
+}
+
+/// the value of an output of the first of `stacks`
+fn output_value(
+    stacks: Vec<Stack>,
+    output_key: &str,
+) -> Option<String> {
+    stacks
+        .into_iter()
+        .next()?
+        .outputs
+        .unwrap_or_default()
+        .into_iter()
+        .find(|output| output.output_key.as_deref() == Some(output_key))
+        .and_then(|output| output.output_value)
+}
+
+/// the value of another stack's output, referenced as `Stack.Output`
+fn stack_output(
+    cf: CloudFormationClient,
+    reference: String,
+    deadline: Option<Instant>,
+) -> impl Future<Item = String, Error = Error> {
+    let (stack_name, output_key) = match output_reference(&reference) {
+        Some((stack, output)) => (stack.to_string(), output.to_string()),
+        _ => {
+            return future::Either::A(future::err(Error::StackOutput(
+                reference,
+                "expected a reference like Stack.Output".into(),
+            )))
+        }
+    };
+    let stack = stack_name.clone();
+    future::Either::B(
+        RETRIES
+            .retry_if(
+                move || {
+                    cf.describe_stacks(DescribeStacksInput {
+                        stack_name: Some(stack_name.clone()),
+                        ..DescribeStacksInput::default()
+                    })
+                    .map_err(Error::from)
+                },
+                move |err: &Error| {
+                    log::debug!("describe stack outputs error for stack {}: {}", stack, err);
+                    retryable(err, deadline)
+                },
+            )
+            .then(move |result| match result {
+                Ok(output) => match output_value(output.stacks.unwrap_or_default(), &output_key) {
+                    Some(value) => Ok(value),
+                    _ => Err(Error::StackOutput(reference, "no such output".into())),
+                },
+                Err(Error::Validation(message)) => Err(Error::StackOutput(reference, message)),
+                Err(err) => Err(Error::StackOutput(reference, err.to_string())),
+            }),
+    )
+}
+
+/// template parameters, with the keys of those whose values are secret
+type ResolvedParameters = (Vec<(String, String)>, Vec<String>);
+
+/// replaces any `ssm:` prefixed parameter values with their values in SSM Parameter Store,
+/// noting which were resolved from SecureStrings, and any `stack:` prefixed values with
+/// the outputs of other stacks
+fn resolve_parameters(
+    cf: CloudFormationClient,
+    ssm: SsmClient,
+    parameters: Vec<(String, String)>,
+    deadline: Option<Instant>,
+) -> impl Future<Item = ResolvedParameters, Error = Error> {
+    future::join_all(parameters.into_iter().map(move |(key, value)| {
+        if let Some(name) = value.strip_prefix(SSM_PREFIX) {
+            return future::Either::A(
+                ssm_parameter(ssm.clone(), name.into())
+                    .map(move |(resolved, secure)| (key, resolved, secure)),
+            );
+        }
+        match value.strip_prefix(STACK_PREFIX) {
+            Some(reference) => future::Either::B(future::Either::A(
+                stack_output(cf.clone(), reference.into(), deadline)
+                    .map(move |resolved| (key, resolved, false)),
+            )),
+            _ => future::Either::B(future::Either::B(future::ok((key, value, false)))),
+        }
+    }))
+    .map(|resolved| {
+        let sensitive = resolved
+            .iter()
+            .filter(|(_, _, secure)| *secure)
+            .map(|(key, _, _)| key.clone())
+            .collect();
+        let parameters = resolved
+            .into_iter()
+            .map(|(key, value, _)| (key, value))
+            .collect();
+        (parameters, sensitive)
+    })
+}
+
+fn current_parameters(
+    cf: CloudFormationClient,
+    stack_name: String,
+    deadline: Option<Instant>,
+) -> impl Future<Item = Vec<(String, String)>, Error = Error> {
+    let stack = stack_name.clone();
+    RETRIES.retry_if(
+        move || {
+            cf.describe_stacks(DescribeStacksInput {
+                stack_name: Some(stack_name.clone()),
+                ..DescribeStacksInput::default()
+            })
+            .map_err(Error::from)
+            .map(|result| {
+                result
+                    .stacks
+                    .unwrap_or_default()
+                    .first()
+                    .map(|stack| {
+                        stack
+                            .clone()
+                            .parameters
+                            .unwrap_or_default()
+                            .into_iter()
+                            .map(|param| {
+                                (
+                                    param.parameter_key.unwrap_or_default(),
+                                    param
+                                        .resolved_value
+                                        .or(param.parameter_value)
+                                        .unwrap_or_default(),
+                                )
+                            })
+                            .collect()
+                    })
+                    .unwrap_or_default()
+            })
+        },
+        move |err: &Error| {
+            log::debug!("get describe stacks error for stack {}: {}", stack, err);
+            retryable(err, deadline)
+        },
+    )
+}
+
+/// fetches both the original and processed templates of a deployed stack, so that
+/// the effects of macros and transforms may be diffed
+pub fn stage_templates(
+    cf: CloudFormationClient,
+    stack_name: String,
+    deadline: Option<Instant>,
+) -> impl Future<Item = (String, String), Error = Error> {
+    current_template(cf.clone(), stack_name.clone(), Stage::Original, deadline)
+        .join(current_template(cf, stack_name, Stage::Processed, deadline))
+        .map(|(original, processed)| {
+            (
+                original.template_body.unwrap_or_default(),
+                processed.template_body.unwrap_or_default(),
+            )
+        })
+}
+
+/// a request for the provided stage of a stack's template
+fn get_template_input(
+    stack_name: &str,
+    stage: Stage,
+) -> GetTemplateInput {
+    GetTemplateInput {
+        stack_name: Some(stack_name.into()),
+        template_stage: Some(stage.to_string()),
+        ..GetTemplateInput::default()
+    }
+}
+
+fn current_template(
+    cf: CloudFormationClient,
+    stack_name: String,
+    stage: Stage,
+    deadline: Option<Instant>,
+) -> impl Future<Item = GetTemplateOutput, Error = Error> {
+    let stack = stack_name.clone();
+    RETRIES.retry_if(
+        move || {
+            cf.get_template(get_template_input(&stack_name, stage))
+                .map_err(Error::from)
+        },
+        move |err: &Error| {
+            log::debug!("get template error for stack {}: {}", stack, err);
+            retryable(err, deadline)
+        },
+    )
+}
+
+/// serves the current template body from cache when available, caching fresh fetches.
+/// the cache is keyed by stack and region alone, so processed templates aren't cached
+fn cached_template(
+    cf: CloudFormationClient,
+    stack_name: String,
+    stage: Stage,
+    region: Region,
+    cache: Option<Cache>,
+    refresh: bool,
+    deadline: Option<Instant>,
+) -> impl Future<Item = String, Error = Error> {
+    let cache = cache.filter(|_| stage == Stage::Original);
+    if let Some(body) = cache
+        .as_ref()
+        .filter(|_| !refresh)
+        .and_then(|cache| cache.get(&stack_name, &region))
+    {
+        log::debug!("using cached template for {}", stack_name);
+        return future::Either::A(future::ok(body));
+    }
+    future::Either::B(
+        current_template(cf, stack_name.clone(), stage, deadline).map(move |current| {
+            let body = current.template_body.unwrap_or_default();
+            if let Some(cache) = cache.filter(|_| !body.trim().is_empty()) {
+                if let Err(err) = cache.put(&stack_name, &region, &body) {
+                    log::debug!("failed to cache template {}", err);
+                }
+            }
+            body
+        }),
+    )
+}
+
+fn create_changeset(
+    cf: CloudFormationClient,
+    stack_name: String,
+    change_set_name: String,
+    source: TemplateSource,
+    parameters: Vec<(String, String)>,
+    options: ChangeSetOptions,
+    deadline: Option<Instant>,
+) -> impl Future<Item = CreateChangeSetOutput, Error = Error> {
+    let stack = stack_name.clone();
+    let capabilities = options.capabilities();
+    let rollback_configuration = options.rollback_configuration();
+    let ChangeSetOptions {
+        description,
+        notification_arns,
+        role_arn,
+        client_token,
+        create,
+        ..
+    } = options;
+    let change_set_type = Some("CREATE".to_string()).filter(|_| create);
+    log::debug!("creating change set with client token {:?}", client_token);
+    let notification_arns = Some(notification_arns).filter(|arns| !arns.is_empty());
+    let (template_body, template_url) = match source {
+        TemplateSource::Body(body) => (Some(body), None),
+        TemplateSource::Url(url) => (None, Some(url)),
+    };
+    RETRIES.retry_if(
+        move || {
+            cf.create_change_set(CreateChangeSetInput {
+                change_set_name: change_set_name.clone(),
+                stack_name: stack_name.clone(),
+                template_body: template_body.clone(),
+                template_url: template_url.clone(),
+                description: description.clone(),
+                notification_ar_ns: notification_arns.clone(),
+                role_arn: role_arn.clone(),
+                rollback_configuration: rollback_configuration.clone(),
+                capabilities: Some(capabilities.clone()),
+                client_token: client_token.clone(),
+                change_set_type: change_set_type.clone(),
+                parameters: Some(
+                    parameters
+                        .clone()
+                        .into_iter()
+                        .map(|(k, v)| Parameter {
+                            parameter_key: Some(k),
+                            parameter_value: Some(v),
+                            ..Parameter::default()
+                        })
+                        .collect(),
+                ),
+                ..CreateChangeSetInput::default()
+            })
+            .map_err(Error::from)
+        },
+        move |err: &Error| {
+            log::debug!("create changeset error for stack {}: {}", stack, err);
+            retryable(err, deadline)
+                || (matches!(
+                    err,
+                    Error::Create(RusotoError::Service(CreateChangeSetError::LimitExceeded(_)))
+                ) && within_retry_deadline(deadline))
+        },
+    )
+}
+
+/// reports change set progress on a single, rewritten stderr line
+fn progress(status: Option<&str>) {
+    match status {
+        Some(status) => eprint!("\r\x1b[2Kwaiting for change set... (status: {})", status),
+        _ => eprint!("\r\x1b[2K"),
+    }
+    let _ = io::stderr().flush();
+}
+
+/// how a change set is polled until CloudFormation is done computing it
+struct Poll {
+    show_progress: bool,
+    timings: Shared,
+    interval: Duration,
+    max_interval: Duration,
+}
+
+fn describe_changeset(
+    cf: CloudFormationClient,
+    stack_name: String,
+    change_set_name: String,
+    poll: Poll,
+    deadline: Option<Instant>,
+) -> Box<dyn Future<Item = DescribeChangeSetOutput, Error = Error> + Send> {
+    Box::new(
+        describe_change_set(
+            cf.clone(),
+            stack_name.clone(),
+            change_set_name.clone(),
+            deadline,
+        )
+        .and_then(move |response| {
+            if let Ok(mut timings) = poll.timings.lock() {
+                timings.describe_polls += 1;
+            }
+            if response
+                .status
+                .iter()
+                .any(|v| v.ends_with("_PROGRESS") || v.ends_with("_PENDING"))
+            {
+                if poll.show_progress {
+                    progress(response.status.as_deref());
+                }
+                let interval = poll.interval;
+                future::Either::A(delay(interval).and_then(move |_| {
+                    describe_changeset(
+                        cf,
+                        stack_name,
+                        change_set_name,
+                        Poll {
+                            interval: next_interval(interval, poll.max_interval),
+                            ..poll
+                        },
+                        deadline,
+                    )
+                }))
+            } else {
+                if poll.show_progress {
+                    progress(None);
+                }
+                future::Either::B(future::ok(response))
+            }
+        }),
+    )
+}
+
+/// resolves after `interval` without blocking the thread, so other diffs driven by the
+/// same runtime keep polling in the meantime
+fn delay(interval: Duration) -> impl Future<Item = (), Error = Error> {
+    Delay::new(Instant::now() + interval).map_err(|err| Error::Timer(err.to_string()))
+}
+
+/// doubles a poll interval, up to `max`
+fn next_interval(
+    interval: Duration,
+    max: Duration,
+) -> Duration {
+    (interval * 2).min(max)
+}
+
+fn delete_changset(
+    cf: CloudFormationClient,
+    stack_name: String,
+    change_set_name: String,
+    deadline: Option<Instant>,
+) -> impl Future<Item = (), Error = Error> {
+    let stack = stack_name.clone();
+    RETRIES
+        .retry_if(
+            move || {
+                cf.delete_change_set(DeleteChangeSetInput {
+                    change_set_name: change_set_name.clone(),
+                    stack_name: Some(stack_name.clone()),
+                })
+                .map_err(Error::from)
+            },
+            move |err: &Error| {
+                log::debug!("delete changeset error for stack {}: {}", stack, err);
+                retryable(err, deadline)
+            },
+        )
+        .map(drop)
+}
+
+fn merge(
+    prev: Vec<(String, String)>,
+    provided: Vec<(String, String)>,
+) -> Vec<(String, String)> {
+    let lookup = provided.into_iter().collect::<HashMap<String, String>>();
+    prev.into_iter()
+        .map(|(k, v)| {
+            let value = lookup.get(&k).cloned().unwrap_or(v);
+            (k, value)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::error::Error as StdError;
+
+    #[test]
+    fn retryable_retries_throttling_and_expired_tokens() {
+        assert!(retryable(&Error::Throttling("test".into()), None));
+        assert!(retryable(&Error::ExpiredToken("test".into()), None));
+        assert!(retryable(&Error::Network("test".into()), None));
+        assert!(!retryable(&Error::Validation("test".into()), None));
+    }
+
+    #[test]
+    fn before_deadline_allows_retries_until_the_deadline() {
+        let now = Instant::now();
+        assert!(before_deadline(None, now));
+        assert!(before_deadline(Some(now + Duration::from_secs(1)), now));
+        assert!(!before_deadline(Some(now), now));
+    }
+
+    #[test]
+    fn deadline_starts_when_requested() {
+        assert_eq!(deadline(None), None);
+        let before = Instant::now();
+        assert!(deadline(Some(Duration::from_secs(30)))
+            .is_some_and(|deadline| deadline >= before + Duration::from_secs(30)));
+    }
+
+    #[test]
+    fn cosmetic_matches_text_changes_without_resource_changes() {
+        let result = diff_changeset(DescribeChangeSetOutput {
+            status: Some("CREATE_COMPLETE".into()),
+            ..DescribeChangeSetOutput::default()
+        });
+        assert!(cosmetic(Some("-# old\n+# new\n"), &result));
+        assert!(!cosmetic(Some(""), &result));
+        assert!(!cosmetic(None, &result));
+        let failed = diff_changeset(DescribeChangeSetOutput {
+            status: Some("FAILED".into()),
+            ..DescribeChangeSetOutput::default()
+        });
+        assert!(!cosmetic(Some("-# old\n+# new\n"), &failed));
+    }
+
+    #[test]
+    fn in_progress_matches_operations_underway() {
+        assert!(in_progress("UPDATE_IN_PROGRESS"));
+        assert!(in_progress("UPDATE_COMPLETE_CLEANUP_IN_PROGRESS"));
+        assert!(!in_progress("UPDATE_COMPLETE"));
+        assert!(!in_progress("REVIEW_IN_PROGRESS"));
+    }
+
+    #[test]
+    fn get_template_input_passes_stage_through() {
+        assert_eq!(
+            get_template_input("svc", Stage::Processed).template_stage,
+            Some("Processed".into())
+        );
+        assert_eq!(
+            get_template_input("svc", Stage::default()),
+            GetTemplateInput {
+                stack_name: Some("svc".into()),
+                template_stage: Some("Original".into()),
+                ..GetTemplateInput::default()
+            }
+        );
+    }
+
+    #[test]
+    fn next_interval_backs_off_to_cap() {
+        let max = Duration::from_secs(1);
+        assert_eq!(
+            next_interval(Duration::from_millis(250), max),
+            Duration::from_millis(500)
+        );
+        assert_eq!(next_interval(Duration::from_millis(750), max), max);
+        assert_eq!(next_interval(max, max), max);
+    }
+
+    #[test]
+    fn merge_merges_parameters() {
+        assert_eq!(
+            merge(
+                vec![("foo".into(), "bar".into()), ("baz".into(), "boom".into())],
+                vec![("baz".into(), "zoom".into())]
+            ),
+            vec![("foo".into(), "bar".into()), ("baz".into(), "zoom".into())]
+        )
+    }
+
+    #[test]
+    fn resolve_parameters_leaves_plain_values_unchanged() -> Result<(), Box<dyn StdError>> {
+        let parameters = vec![("foo".into(), "bar".into()), ("baz".into(), "".into())];
+        assert_eq!(
+            resolve_parameters(
+                CloudFormationClient::new(Region::default()),
+                SsmClient::new(Region::default()),
+                parameters.clone(),
+                None,
+            )
+            .wait()?,
+            (parameters, Vec::new())
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn output_reference_splits_stack_and_output() {
+        assert_eq!(
+            output_reference("network-prod.VpcId"),
+            Some(("network-prod", "VpcId"))
+        );
+        assert_eq!(output_reference("network"), None);
+        assert_eq!(output_reference(".VpcId"), None);
+        assert_eq!(output_reference("network."), None);
+    }
+
+    #[test]
+    fn output_value_finds_named_output() {
+        let stacks = vec![Stack {
+            outputs: Some(vec![rusoto_cloudformation::Output {
+                output_key: Some("VpcId".into()),
+                output_value: Some("vpc-123".into()),
+                ..Default::default()
+            }]),
+            ..Default::default()
+        }];
+        assert_eq!(
+            output_value(stacks.clone(), "VpcId"),
+            Some("vpc-123".into())
+        );
+        assert_eq!(output_value(stacks, "SubnetId"), None);
+        assert_eq!(output_value(Vec::new(), "VpcId"), None);
+    }
+
+    #[test]
+    fn template_source_inlines_small_templates() -> Result<(), Box<dyn StdError>> {
+        assert_eq!(
+            template_source(
+                S3Client::new(Region::default()),
+                Region::default(),
+                Some("bucket".into()),
+                "test".into(),
+                "body".into()
+            )
+            .wait()?,
+            TemplateSource::Body("body".into())
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn resource_limit_warning_warns_near_and_over_the_limit() {
+        assert_eq!(resource_limit_warning(10, true), Ok(None));
+        assert!(resource_limit_warning(STACK_RESOURCES_WARNING, true)
+            .ok()
+            .flatten()
+            .is_some_and(|warning| warning.contains("nearing")));
+        assert!(resource_limit_warning(MAX_STACK_RESOURCES + 1, false)
+            .ok()
+            .flatten()
+            .is_some_and(|warning| warning.contains("more than the 500")));
+        assert_eq!(
+            resource_limit_warning(MAX_STACK_RESOURCES + 1, true),
+            Err(Error::TooManyResources(MAX_STACK_RESOURCES + 1))
+        );
+    }
+
+    #[test]
+    fn template_size_checks_limits_at_boundaries() {
+        assert_eq!(template_size(MAX_TEMPLATE_BODY_BYTES, false, None), Ok(()));
+        assert_eq!(
+            template_size(MAX_TEMPLATE_BODY_BYTES + 1, false, None),
+            Err(Error::TemplateTooLarge(MAX_TEMPLATE_BODY_BYTES + 1))
+        );
+        assert_eq!(template_size(MAX_TEMPLATE_URL_BYTES, true, None), Ok(()));
+        assert_eq!(
+            template_size(MAX_TEMPLATE_URL_BYTES + 1, true, None),
+            Err(Error::Validation(
+                "Template is 1048577 bytes which exceeds CloudFormation's limit of 1048576 bytes for templates in S3"
+                    .into()
+            ))
+        );
+        assert_eq!(template_size(1_000, false, Some(1_000)), Ok(()));
+        assert_eq!(
+            template_size(1_001, true, Some(1_000)),
+            Err(Error::Validation(
+                "Template is 1001 bytes which exceeds the --max-template-bytes limit of 1000 bytes"
+                    .into()
+            ))
+        );
+        assert_eq!(
+            template_size(
+                MAX_TEMPLATE_BODY_BYTES + 1,
+                false,
+                Some(MAX_TEMPLATE_URL_BYTES)
+            ),
+            Err(Error::TemplateTooLarge(MAX_TEMPLATE_BODY_BYTES + 1))
+        );
+    }
+
+    #[test]
+    fn builder_rejects_large_templates_without_bucket() -> Result<(), Box<dyn StdError>> {
+        let mut template = tempfile::NamedTempFile::new()?;
+        template.write_all(&vec![b'#'; MAX_TEMPLATE_BODY_BYTES + 1])?;
+        assert_eq!(
+            Differ::builder()
+                .stack_name("test")
+                .template(template.path())
+                .build()
+                .err(),
+            Some(Error::TemplateTooLarge(MAX_TEMPLATE_BODY_BYTES + 1))
+        );
+        assert!(Differ::builder()
+            .stack_name("test")
+            .template(template.path())
+            .artifacts_bucket("bucket")
+            .build()
+            .is_ok());
+        Ok(())
+    }
+
+    #[test]
+    fn builder_requires_stack_name_and_template() {
+        assert_eq!(
+            Differ::builder()
+                .template("tests/data/template-after.yml")
+                .build()
+                .err(),
+            Some(Error::Config("a stack name is required".into()))
+        );
+        assert_eq!(
+            Differ::builder().stack_name("test").build().err(),
+            Some(Error::Config("a template is required".into()))
+        );
+        assert!(Differ::builder()
+            .stack_name("test")
+            .template("tests/data/template-after.yml")
+            .build()
+            .is_ok());
+    }
+
+    /// records the body of each request, failing the first with a connection reset
+    #[derive(Clone, Default)]
+    struct FlakyDispatcher {
+        requests: std::sync::Arc<std::sync::Mutex<Vec<String>>>,
+    }
+
+    impl rusoto_core::DispatchSignedRequest for FlakyDispatcher {
+        type Future = future::FutureResult<
+            rusoto_core::request::HttpResponse,
+            rusoto_core::request::HttpDispatchError,
+        >;
+
+        fn dispatch(
+            &self,
+            request: rusoto_core::signature::SignedRequest,
+            _: Option<Duration>,
+        ) -> Self::Future {
+            let body = match request.payload {
+                Some(rusoto_core::signature::SignedRequestPayload::Buffer(bytes)) => {
+                    String::from_utf8_lossy(&bytes).into_owned()
+                }
+                _ => String::new(),
+            };
+            let mut requests = self.requests.lock().unwrap();
+            requests.push(body);
+            if requests.len() == 1 {
+                return future::err(rusoto_core::request::HttpDispatchError::new(
+                    "connection reset by peer".into(),
+                ));
+            }
+            future::ok(rusoto_core::request::HttpResponse {
+                status: hyper::StatusCode::OK,
+                body: b"<CreateChangeSetResponse><CreateChangeSetResult><Id>arn:changeset</Id></CreateChangeSetResult><ResponseMetadata><RequestId>1</RequestId></ResponseMetadata></CreateChangeSetResponse>"
+                    .to_vec()
+                    .into(),
+                headers: Default::default(),
+            })
+        }
+    }
+
+    /// responds to every request with the same status and body
+    #[derive(Clone)]
+    struct StaticDispatcher {
+        status: hyper::StatusCode,
+        body: &'static str,
+    }
+
+    impl rusoto_core::DispatchSignedRequest for StaticDispatcher {
+        type Future = future::FutureResult<
+            rusoto_core::request::HttpResponse,
+            rusoto_core::request::HttpDispatchError,
+        >;
+
+        fn dispatch(
+            &self,
+            _: rusoto_core::signature::SignedRequest,
+            _: Option<Duration>,
+        ) -> Self::Future {
+            future::ok(rusoto_core::request::HttpResponse {
+                status: self.status,
+                body: self.body.as_bytes().to_vec().into(),
+                headers: Default::default(),
+            })
+        }
+    }
+
+    fn static_client(
+        status: hyper::StatusCode,
+        body: &'static str,
+    ) -> CloudFormationClient {
+        CloudFormationClient::new_with(
+            StaticDispatcher { status, body },
+            rusoto_core::credential::StaticProvider::new_minimal("test".into(), "test".into()),
+            Region::UsEast1,
+        )
+    }
+
+    /// reports a change set in progress the first time it's described, and complete
+    /// after that
+    #[derive(Clone, Default)]
+    struct PollingDispatcher {
+        described: std::sync::Arc<std::sync::Mutex<std::collections::HashSet<String>>>,
+    }
+
+    impl rusoto_core::DispatchSignedRequest for PollingDispatcher {
+        type Future = future::FutureResult<
+            rusoto_core::request::HttpResponse,
+            rusoto_core::request::HttpDispatchError,
+        >;
+
+        fn dispatch(
+            &self,
+            request: rusoto_core::signature::SignedRequest,
+            _: Option<Duration>,
+        ) -> Self::Future {
+            let body = match request.payload {
+                Some(rusoto_core::signature::SignedRequestPayload::Buffer(bytes)) => {
+                    String::from_utf8_lossy(&bytes).into_owned()
+                }
+                _ => String::new(),
+            };
+            let status = if self.described.lock().unwrap().insert(body) {
+                "CREATE_IN_PROGRESS"
+            } else {
+                "CREATE_COMPLETE"
+            };
+            future::ok(rusoto_core::request::HttpResponse {
+                status: hyper::StatusCode::OK,
+                body: format!(
+                    "<DescribeChangeSetResponse><DescribeChangeSetResult><Status>{}</Status></DescribeChangeSetResult><ResponseMetadata><RequestId>1</RequestId></ResponseMetadata></DescribeChangeSetResponse>",
+                    status
+                )
+                .into_bytes()
+                .into(),
+                headers: Default::default(),
+            })
+        }
+    }
+
+    #[test]
+    fn describe_changeset_polls_stacks_concurrently() -> Result<(), Box<dyn StdError>> {
+        let cf = CloudFormationClient::new_with(
+            PollingDispatcher::default(),
+            rusoto_core::credential::StaticProvider::new_minimal("test".into(), "test".into()),
+            Region::UsEast1,
+        );
+        let interval = Duration::from_millis(500);
+        let polls = ["a", "b"]
+            .iter()
+            .map(|stack_name| {
+                describe_changeset(
+                    cf.clone(),
+                    stack_name.to_string(),
+                    "cliff".into(),
+                    Poll {
+                        show_progress: false,
+                        timings: Shared::default(),
+                        interval,
+                        max_interval: interval,
+                    },
+                    None,
+                )
+            })
+            .collect::<Vec<_>>();
+        let started = Instant::now();
+        let changesets = tokio::runtime::Runtime::new()?
+            .block_on(stream::iter_ok::<_, Error>(polls).buffered(2).collect())?;
+        assert_eq!(changesets.len(), 2);
+        assert!(changesets
+            .iter()
+            .all(|changeset| changeset.status.as_deref() == Some("CREATE_COMPLETE")));
+        // each stack waits out one interval, side by side
+        assert!(started.elapsed() < interval * 2);
+        Ok(())
+    }
+
+    #[test]
+    fn change_set_exists_distinguishes_missing_change_sets() -> Result<(), Box<dyn StdError>> {
+        let found = static_client(
+            hyper::StatusCode::OK,
+            "<DescribeChangeSetResponse><DescribeChangeSetResult><Status>CREATE_COMPLETE</Status></DescribeChangeSetResult><ResponseMetadata><RequestId>1</RequestId></ResponseMetadata></DescribeChangeSetResponse>",
+        );
+        assert!(
+            existing_change_set(found, "test".into(), "cliff".into(), None)
+                .wait()?
+                .is_some()
+        );
+        let missing = static_client(
+            hyper::StatusCode::NOT_FOUND,
+            "<ErrorResponse><Error><Code>ChangeSetNotFound</Code><Message>ChangeSet [cliff] does not exist</Message></Error></ErrorResponse>",
+        );
+        assert!(
+            existing_change_set(missing, "test".into(), "cliff".into(), None)
+                .wait()?
+                .is_none()
+        );
+        let no_stack = static_client(
+            hyper::StatusCode::BAD_REQUEST,
+            "<ErrorResponse><Error><Code>ValidationError</Code><Message>Stack [test] does not exist</Message></Error></ErrorResponse>",
+        );
+        assert!(
+            existing_change_set(no_stack, "test".into(), "cliff".into(), None)
+                .wait()?
+                .is_none()
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn stale_parameters_names_parameters_that_differ() {
+        let parameter = |key: &str, value: &str| Parameter {
+            parameter_key: Some(key.into()),
+            parameter_value: Some(value.into()),
+            ..Parameter::default()
+        };
+        let changeset = DescribeChangeSetOutput {
+            parameters: Some(vec![
+                parameter("Env", "prod"),
+                parameter("Size", "2"),
+                parameter("Password", REDACTED),
+                parameter("Legacy", "yes"),
+            ]),
+            ..DescribeChangeSetOutput::default()
+        };
+        let submitted = |pairs: &[(&str, &str)]| {
+            pairs
+                .iter()
+                .map(|(k, v)| (k.to_string(), v.to_string()))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            stale_parameters(
+                &changeset,
+                &submitted(&[
+                    ("Env", "staging"),
+                    ("Size", "2"),
+                    ("Password", "s3cr3t"),
+                    ("Name", "svc")
+                ])
+            ),
+            vec!["Env", "Legacy", "Name"]
+        );
+        assert!(stale_parameters(
+            &changeset,
+            &submitted(&[
+                ("Env", "prod"),
+                ("Size", "2"),
+                ("Password", "x"),
+                ("Legacy", "yes")
+            ])
+        )
+        .is_empty());
+    }
+
+    #[test]
+    fn derived_client_token_is_distinct_per_stack_and_region() {
+        let token = derived_client_token("ci-1234", "svc", &Region::UsEast1);
+        assert!(token.starts_with("ci-1234-"));
+        assert_eq!(
+            token,
+            derived_client_token("ci-1234", "svc", &Region::UsEast1)
+        );
+        assert_ne!(
+            token,
+            derived_client_token("ci-1234", "other", &Region::UsEast1)
+        );
+        assert_ne!(
+            token,
+            derived_client_token("ci-1234", "svc", &Region::UsWest2)
+        );
+        let long = "a".repeat(MAX_CLIENT_TOKEN_LEN);
+        assert_eq!(
+            derived_client_token(&long, "svc", &Region::UsEast1).len(),
+            MAX_CLIENT_TOKEN_LEN
+        );
+    }
+
+    #[test]
+    fn client_token_is_a_prefixed_uuid() {
+        let token = client_token();
+        assert!(token.starts_with("cliff-"));
+        assert_eq!(token.len(), "cliff-".len() + 36);
+        assert_eq!(&token["cliff-".len() + 14..][..1], "4");
+        assert_ne!(token, client_token());
+    }
+
+    #[test]
+    fn create_changeset_reuses_client_token_across_retries() -> Result<(), Box<dyn StdError>> {
+        let dispatcher = FlakyDispatcher::default();
+        let cf = CloudFormationClient::new_with(
+            dispatcher.clone(),
+            rusoto_core::credential::StaticProvider::new_minimal("test".into(), "test".into()),
+            Region::UsEast1,
+        );
+        let output = create_changeset(
+            cf,
+            "test".into(),
+            "cliff".into(),
+            TemplateSource::Body("Resources: {}".into()),
+            Vec::new(),
+            ChangeSetOptions {
+                client_token: Some("cliff-token".into()),
+                ..ChangeSetOptions::default()
+            },
+            None,
+        )
+        .wait()?;
+        assert_eq!(output.id.as_deref(), Some("arn:changeset"));
+        let requests = dispatcher.requests.lock().unwrap();
+        assert_eq!(requests.len(), 2);
+        assert!(requests
+            .iter()
+            .all(|request| request.contains("ClientToken=cliff-token")
+                && !request.contains("ChangeSetType")));
+        Ok(())
+    }
+
+    #[test]
+    fn create_changeset_creates_stacks_never_deployed() -> Result<(), Box<dyn StdError>> {
+        let dispatcher = FlakyDispatcher::default();
+        let cf = CloudFormationClient::new_with(
+            dispatcher.clone(),
+            rusoto_core::credential::StaticProvider::new_minimal("test".into(), "test".into()),
+            Region::UsEast1,
+        );
+        create_changeset(
+            cf,
+            "test".into(),
+            "cliff".into(),
+            TemplateSource::Body("Resources: {}".into()),
+            Vec::new(),
+            ChangeSetOptions {
+                create: true,
+                ..ChangeSetOptions::default()
+            },
+            None,
+        )
+        .wait()?;
+        assert!(dispatcher.requests.lock().unwrap()[1].contains("ChangeSetType=CREATE"));
+        Ok(())
+    }
+
+    #[test]
+    fn create_changeset_passes_stack_role() -> Result<(), Box<dyn StdError>> {
+        let dispatcher = FlakyDispatcher::default();
+        let cf = CloudFormationClient::new_with(
+            dispatcher.clone(),
+            rusoto_core::credential::StaticProvider::new_minimal("test".into(), "test".into()),
+            Region::UsEast1,
+        );
+        create_changeset(
+            cf,
+            "test".into(),
+            "cliff".into(),
+            TemplateSource::Body("Resources: {}".into()),
+            Vec::new(),
+            ChangeSetOptions {
+                role_arn: Some("arn:aws:iam::123456789012:role/deploy".into()),
+                ..ChangeSetOptions::default()
+            },
+            None,
+        )
+        .wait()?;
+        assert!(dispatcher.requests.lock().unwrap()[1]
+            .contains("RoleARN=arn%3Aaws%3Aiam%3A%3A123456789012%3Arole%2Fdeploy"));
+        Ok(())
+    }
+
+    #[test]
+    fn change_set_options_build_rollback_configuration() {
+        assert_eq!(ChangeSetOptions::default().rollback_configuration(), None);
+        let options = ChangeSetOptions {
+            rollback_alarm_arns: vec![
+                "arn:aws:cloudwatch:us-east-1:123456789012:alarm:errors".into()
+            ],
+            rollback_monitoring_minutes: Some(10),
+            ..ChangeSetOptions::default()
+        };
+        assert_eq!(
+            options.rollback_configuration(),
+            Some(RollbackConfiguration {
+                monitoring_time_in_minutes: Some(10),
+                rollback_triggers: Some(vec![RollbackTrigger {
+                    arn: "arn:aws:cloudwatch:us-east-1:123456789012:alarm:errors".into(),
+                    type_: "AWS::CloudWatch::Alarm".into(),
+                }]),
+            })
+        );
+        let options = ChangeSetOptions {
+            rollback_monitoring_minutes: Some(5),
+            ..ChangeSetOptions::default()
+        };
+        assert_eq!(
+            options
+                .rollback_configuration()
+                .and_then(|config| config.rollback_triggers),
+            None
+        );
+    }
+
+    #[test]
+    fn change_set_options_add_capabilities_to_defaults() {
+        let options = ChangeSetOptions {
+            capabilities: vec!["CAPABILITY_AUTO_EXPAND".into(), "CAPABILITY_IAM".into()],
+            ..ChangeSetOptions::default()
+        };
+        assert_eq!(
+            options.capabilities(),
+            vec![
+                "CAPABILITY_IAM",
+                "CAPABILITY_NAMED_IAM",
+                "CAPABILITY_AUTO_EXPAND"
+            ]
+        );
+    }
+
+    #[test]
+    fn builder_reads_before_template() -> Result<(), Box<dyn StdError>> {
+        let differ = Differ::builder()
+            .stack_name("test")
+            .template("tests/data/template-after.yml")
+            .before_template("tests/data/template-before.yml")
+            .build()?;
+        assert_eq!(
+            differ.before,
+            Some((
+                PathBuf::from("tests/data/template-before.yml"),
+                include_str!("../tests/data/template-before.yml").into()
+            ))
+        );
+        assert!(Differ::builder()
+            .stack_name("test")
+            .template("tests/data/template-after.yml")
+            .before_template("tests/data/missing.yml")
+            .build()
+            .is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn builder_prefers_provided_template_body() -> Result<(), Box<dyn StdError>> {
+        let differ = Differ::builder()
+            .stack_name("test")
+            .template("TEMPLATE.yml")
+            .template_body("Resources: {}\r\n")
+            .build()?;
+        assert_eq!(differ.filename(), Path::new("TEMPLATE.yml"));
+        assert_eq!(differ.template_body, "Resources: {}\n");
+        Ok(())
+    }
+}
@@ -1,41 +1,61 @@
 //! An AWS CloudFormation stack diff tool
-use colored::Colorize;
-use futures::{future, Future};
-use futures_backoff::Strategy;
-use lazy_static::lazy_static;
-use rusoto_cloudformation::{
-    Change, CloudFormation, CloudFormationClient, CreateChangeSetError, CreateChangeSetInput,
-    CreateChangeSetOutput, DeleteChangeSetError, DeleteChangeSetInput, DescribeChangeSetError,
-    DescribeChangeSetInput, DescribeChangeSetOutput, DescribeStacksInput, GetTemplateInput,
-    GetTemplateOutput, Parameter,
+#![allow(clippy::result_large_err)]
+use chrono::{DateTime, Duration as ChronoDuration, Utc};
+use cliff::{
+    accounts::{self, parse_role_arn, Account, AssumeRole, CallerIdentity},
+    baseline::{self, Baseline},
+    cache::Cache,
+    change_set_creation_time,
+    changeset::{Condition, ResourceFilter, Severity, SortBy, TypeGuard, TypeViolation},
+    credentials::{Chain, Source},
+    diff_all, diff_all_fail_fast,
+    error::Error,
+    execute_changeset,
+    expect::Expectation,
+    http,
+    notify::{notify, Payload},
+    redact::Redactor,
+    region,
+    render::{self, Format, MarkerOverrides, Markers, RenderOptions},
+    stacks, stage_templates,
+    template::{self, Stage, TemplateFormat},
+    validate_template, watch, Diff, Differ, DifferBuilder,
 };
-use rusoto_core::{credential::ChainProvider, request::HttpClient, Region, RusotoError};
+use colored::Colorize;
+use futures::{future, stream, Future, Stream};
+use rusoto_cloudformation::CloudFormationClient;
+use rusoto_core::{credential::ProvideAwsCredentials, Region};
+use rusoto_s3::S3Client;
+use rusoto_ssm::SsmClient;
 use std::{
-    collections::HashMap,
+    collections::{HashMap, HashSet},
     env,
     error::Error as StdError,
+    ffi::OsString,
     fs,
-    io::{self, Write},
+    io::Write,
     path::{Path, PathBuf},
-    process::{exit, Command},
-    str::{from_utf8, FromStr},
-    thread::sleep,
-    time::Duration,
+    process::exit,
+    str::FromStr,
+    time::{Duration, Instant},
 };
 use structopt::StructOpt;
 use tokio::runtime::Runtime;
+use tokio_signal::IoStream;
 
-mod error;
-use crate::error::Error;
+const CACHE_TTL: Duration = Duration::from_secs(60);
+/// stable name of change sets kept with --preview
+const PREVIEW_CHANGESET_NAME: &str = "cliff-preview";
+/// placeholder in template filenames replaced with each stack's name
+const STACK_PLACEHOLDER: &str = "{stack}";
+/// clears the terminal and moves the cursor home between watched diffs
+const CLEAR_SCREEN: &str = "\x1b[2J\x1b[H";
 
-const CHANGESET_NAME: &str = "cliff";
-
-lazy_static! {
-    static ref RETRIES: Strategy = Strategy::exponential(Duration::from_millis(100))
-        .with_max_retries(15)
-        .with_jitter(true);
-}
+/// the label and qualifier of each stack diffed, in order
+type Labels = Vec<(String, String)>;
 
+/// parses a KEY=value pair, splitting on the first `=`. values may contain `=` and an
+/// empty value, as in `KEY=`, is an empty string rather than the previous value
 fn parse_key_val<T, U>(s: &str) -> Result<(T, U), Box<dyn StdError>>
 where
     T: FromStr,
@@ -46,360 +66,1967 @@ where
     let pos = s
         .find('=')
         .ok_or_else(|| format!("invalid KEY=value: no `=` found in `{}`", s))?;
+    if pos == 0 {
+        return Err(format!("invalid KEY=value: no key found in `{}`", s).into());
+    }
     Ok((s[..pos].parse()?, s[pos + 1..].parse()?))
 }
 
+/// parses a positive number of concurrent diffs
+fn parse_concurrency(s: &str) -> Result<usize, String> {
+    match s.parse::<usize>() {
+        Ok(0) | Err(_) => Err(format!(
+            "invalid concurrency `{}`: expected a positive number",
+            s
+        )),
+        Ok(n) => Ok(n),
+    }
+}
+
+/// parses a client request token, which CloudFormation requires to be at most 128
+/// letters, numbers, and dashes, starting with a letter or number
+fn parse_client_request_token(s: &str) -> Result<String, String> {
+    let valid = s.len() <= cliff::MAX_CLIENT_TOKEN_LEN
+        && s.chars().next().is_some_and(|c| c.is_ascii_alphanumeric())
+        && s.chars().all(|c| c.is_ascii_alphanumeric() || c == '-');
+    if valid {
+        Ok(s.to_string())
+    } else {
+        Err(format!(
+            "invalid client request token `{}`: expected at most {} letters, numbers, and dashes, starting with a letter or number",
+            s,
+            cliff::MAX_CLIENT_TOKEN_LEN
+        ))
+    }
+}
+
+/// parses the ARN of a CloudWatch alarm, as in `arn:aws:cloudwatch:us-east-1:123456789012:alarm:name`
+fn parse_alarm_arn(s: &str) -> Result<String, String> {
+    let fields = s.splitn(7, ':').collect::<Vec<_>>();
+    match fields.as_slice() {
+        ["arn", partition, "cloudwatch", region, account, "alarm", name]
+            if !partition.is_empty()
+                && !region.is_empty()
+                && account.len() == 12
+                && account.chars().all(|c| c.is_ascii_digit())
+                && !name.is_empty() =>
+        {
+            Ok(s.to_string())
+        }
+        _ => Err(format!(
+            "invalid alarm ARN `{}`: expected arn:aws:cloudwatch:<region>:<account-id>:alarm:<name>",
+            s
+        )),
+    }
+}
+
+/// parses minutes of rollback monitoring, which CloudFormation limits to 180
+fn parse_monitoring_minutes(s: &str) -> Result<u32, String> {
+    match s.parse::<u32>() {
+        Ok(minutes) if minutes <= 180 => Ok(minutes),
+        _ => Err(format!(
+            "invalid monitoring time `{}`: expected minutes from 0 to 180",
+            s
+        )),
+    }
+}
+
+/// how log records are written to stderr
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+enum LogFormat {
+    /// env_logger's default, human-friendly format
+    #[default]
+    Text,
+    /// one JSON object per line, for log aggregation
+    Json,
+}
+
+impl FromStr for LogFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "text" => Ok(LogFormat::Text),
+            "json" => Ok(LogFormat::Json),
+            other => Err(format!(
+                "invalid log format `{}`. expected text or json",
+                other
+            )),
+        }
+    }
+}
+
+/// a log record as a single line JSON object
+fn json_log_line(
+    timestamp: &str,
+    record: &log::Record,
+) -> String {
+    serde_json::json!({
+        "timestamp": timestamp,
+        "level": record.level().to_string(),
+        "target": record.target(),
+        "message": record.args().to_string(),
+    })
+    .to_string()
+}
+
+/// initializes logging from `RUST_LOG` in the provided format
+fn init_logger(format: LogFormat) {
+    let mut builder = env_logger::Builder::from_default_env();
+    if format == LogFormat::Json {
+        builder.format(|buf, record| {
+            let timestamp = chrono::Utc::now().to_rfc3339();
+            writeln!(buf, "{}", json_log_line(&timestamp, record))
+        });
+    }
+    builder.init();
+}
+
+/// parses a duration like `30s`, `2m`, or a number of seconds
+fn parse_duration(s: &str) -> Result<Duration, String> {
+    let invalid = || {
+        format!(
+            "invalid duration `{}`: expected seconds like 30 or 30s, or minutes like 2m",
+            s
+        )
+    };
+    let (number, unit) = match s.strip_suffix('m') {
+        Some(minutes) => (minutes, 60),
+        _ => (s.strip_suffix('s').unwrap_or(s), 1),
+    };
+    let number = number.trim().parse::<u64>().map_err(|_| invalid())?;
+    Ok(Duration::from_secs(number * unit))
+}
+
+/// parses a JSON object of string parameter values into key value pairs
+fn parse_json_parameters(s: &str) -> Result<Vec<(String, String)>, Box<dyn StdError>> {
+    let object: serde_json::Map<String, serde_json::Value> =
+        serde_json::from_str(s).map_err(|err| format!("invalid --parameters-json: {}", err))?;
+    object
+        .into_iter()
+        .map(|(key, value)| match value {
+            serde_json::Value::String(value) => Ok((key, value)),
+            other => Err(format!(
+                "invalid --parameters-json: value of `{}` must be a string, got `{}`",
+                key, other
+            )
+            .into()),
+        })
+        .collect()
+}
+
+/// parameters from environment variables whose names start with `prefix`, named by the
+/// rest of the variable's name. variables leaving names that aren't alphanumeric, as
+/// CloudFormation requires, are skipped
+fn env_parameters<I>(
+    prefix: &str,
+    vars: I,
+) -> Vec<(String, String)>
+where
+    I: IntoIterator<Item = (String, String)>,
+{
+    let mut parameters = vars
+        .into_iter()
+        .filter_map(|(name, value)| {
+            let key = name.strip_prefix(prefix)?;
+            if key.is_empty() || !key.chars().all(|c| c.is_ascii_alphanumeric()) {
+                log::debug!("skipping environment variable {}", name);
+                return None;
+            }
+            Some((key.to_string(), value))
+        })
+        .collect::<Vec<_>>();
+    parameters.sort();
+    parameters
+}
+
+/// options shared by every subcommand that contacts AWS
 #[derive(Debug, StructOpt)]
-#[structopt(name = "cliff")]
-/// A CloudFormation stack diff tool"
-struct Options {
+struct Common {
+    #[structopt(long, use_delimiter = true)]
+    /// ordered, comma-separated list of credential sources to try: env, web-identity, profile, profile:<name>, container, instance
+    credentials: Vec<Source>,
+    #[structopt(long)]
+    /// named profile to resolve credentials and region from, in place of the default profile
+    profile: Option<String>,
+    #[structopt(long)]
+    /// AWS region of the stack. defaults to the profile's configured region, then AWS_DEFAULT_REGION or AWS_REGION
+    region: Option<String>,
+    #[structopt(long, env = "RUST_LOG_FORMAT", default_value = "text")]
+    /// format of the logs enabled with RUST_LOG: text, or json for one object per line
+    log_format: LogFormat,
+    #[structopt(long, parse(try_from_str = parse_duration))]
+    /// stop retrying throttled or failed requests once this long has passed since each diff or request started, like 30s or 2m. by default, requests are retried up to 15 times
+    retry_deadline: Option<Duration>,
+    #[structopt(long)]
+    /// check credentials with STS before anything else, printing the identity and account they act as
+    warm_credentials: bool,
+}
+
+impl Common {
+    /// the credential chain, resolving --profile in place of the default profile
+    fn chain(&self) -> Chain {
+        let chain = Chain::new(self.credentials.clone());
+        match &self.profile {
+            Some(name) => chain.with_profile(name),
+            _ => chain,
+        }
+    }
+
+    fn region(&self) -> Result<Region, Error> {
+        region::resolve(self.region.as_deref(), self.profile.as_deref())
+    }
+
+    /// confirms the credential chain yields valid credentials, and whose they are
+    fn warm_credentials(&self) -> Result<CallerIdentity, Box<dyn StdError>> {
+        let region = self.region().unwrap_or_default();
+        Runtime::new()?
+            .block_on(accounts::caller_identity(self.chain(), &region))
+            .map_err(|err| format!("no valid AWS credentials found: {}", err.message).into())
+    }
+}
+
+#[derive(Debug, StructOpt)]
+struct DiffOptions {
+    #[structopt(flatten)]
+    common: Common,
     #[structopt(
         short = "p",
         long = "parameters",
         parse(try_from_str = parse_key_val),
-        help = "multi-valued parameter for providing template parameters in the form 'parameter-name=parameter-value'"
+        help = "multi-valued parameter for providing template parameters in the form 'parameter-name=parameter-value'. values in the form 'ssm:/path/to/param' are resolved from SSM Parameter Store, and values in the form 'stack:OtherStack.OutputName' from another stack's outputs"
     )]
     parameters: Vec<(String, String)>,
-    #[structopt(short, long = "stack-name")]
-    /// name of the CloudFormation stack to diff against
-    stack_name: String,
-    /// filename of local template
-    filename: PathBuf,
+    #[structopt(long)]
+    /// template parameters as a JSON object of string values, e.g. '{"Key":"Value"}'. -p values take precedence
+    parameters_json: Option<String>,
+    #[structopt(long)]
+    /// take template parameters from environment variables starting with this prefix, less the prefix, as CFN_Env=prod for Env=prod. --parameters-json and -p values take precedence
+    parameters_from_env: Option<String>,
+    #[structopt(long, use_delimiter = true, conflicts_with_all = &["region", "compare-stages"])]
+    /// comma-separated regions to diff the same stack in, labeling output by region
+    regions: Vec<Region>,
+    #[structopt(long, default_value = "4", parse(try_from_str = parse_concurrency))]
+    /// most stacks to diff at once when diffing many stacks, regions, or accounts
+    concurrency: usize,
+    #[structopt(long)]
+    /// when diffing many stacks, regions, or accounts, stop at the first that fails rather than reporting every failure at the end
+    fail_fast: bool,
+    #[structopt(long, parse(from_os_str), conflicts_with = "compare-stages")]
+    /// file listing role ARNs to assume, one per line optionally followed by comma-separated regions, diffing the stack in each account
+    accounts_file: Option<PathBuf>,
+    #[structopt(long)]
+    /// S3 bucket to upload templates too large to submit inline to
+    artifacts_bucket: Option<String>,
+    #[structopt(long, parse(from_os_str))]
+    /// directory to cache fetched stack templates in between runs
+    cache_dir: Option<PathBuf>,
+    #[structopt(long)]
+    /// always fetch a fresh copy of the current stack template
+    no_cache: bool,
+    #[structopt(long)]
+    /// show before and after property values for modified resources
+    include_property_values: bool,
+    #[structopt(long)]
+    /// show policy statements added, removed, or modified by changes to IAM resources
+    include_policy_changes: bool,
+    #[structopt(long)]
+    /// show before and after values of changed stack parameters
+    include_parameter_values: bool,
+    #[structopt(long, default_value = "action")]
+    /// order of changes: action, type, id, or risk to list the most destructive changes first
+    sort_by: SortBy,
+    #[structopt(long)]
+    /// only show changes to resources whose logical ids match comma-separated globs like
+    /// 'Api*,*Table'. globs prefixed with '!' exclude matching resources
+    only_resources: Option<ResourceFilter>,
+    #[structopt(short, long, default_value = "text")]
+    /// output format: text, json, raw-json, sarif, markdown, or facts
+    output: Format,
+    #[structopt(long, parse(from_os_str))]
+    /// also write each stack's diff as a line of JSON to this file, alongside --output
+    json_file: Option<PathBuf>,
+    #[structopt(long, parse(from_os_str))]
+    /// also write each stack's diff as markdown to this file, alongside --output
+    markdown_file: Option<PathBuf>,
+    #[structopt(long, parse(from_os_str))]
+    /// also write each stack's policy facts as a line of JSON to this file, alongside --output
+    facts_file: Option<PathBuf>,
+    #[structopt(long)]
+    /// only print a single line summary of change counts per stack
+    summary_only: bool,
+    #[structopt(long, default_value = "0")]
+    /// render at most this many changes, after sorting. counts still include every change. 0 renders them all
+    max_changes: usize,
+    #[structopt(long, conflicts_with = "summary-only")]
+    /// render each change on a single line without physical ids or scope, followed by change counts
+    compact: bool,
+    #[structopt(long)]
+    /// also break down changes by resource type, as a section of text and markdown output or a by_type list in JSON
+    by_type: bool,
+    #[structopt(long)]
+    /// also print a one line `cliff: stack=.. add=..` summary per stack to stderr for scripts
+    stderr_summary: bool,
+    #[structopt(long)]
+    /// exit with a status of 2 when any changes are detected
+    exit_code: bool,
+    #[structopt(long, requires = "exit-code")]
+    /// with --exit-code, only exit with a status of 2 when a change is at least this severe: info, low, high, or critical
+    min_severity: Option<Severity>,
+    #[structopt(long, use_delimiter = true)]
+    /// comma-separated conditions that fail the run when any change meets them: add, modify, remove, import, replace, data-loss
+    fail_on: Vec<Condition>,
+    #[structopt(long, use_delimiter = true)]
+    /// comma-separated resource type globs, like AWS::IAM::*, that fail the run when any resource of a matching type changes
+    deny_type: Vec<String>,
+    #[structopt(long, use_delimiter = true)]
+    /// comma-separated resource type globs that fail the run when any resource of a type matching none of them changes
+    allow_type: Vec<String>,
+    #[structopt(long, parse(from_os_str))]
+    /// YAML or JSON file mapping the logical ids of resources allowed to change to their allowed changes. fails the run on unexpected or missing changes
+    expect: Option<PathBuf>,
+    #[structopt(long, parse(from_os_str))]
+    /// file of a previous run's --output json, to report how each stack's changes differ from it
+    baseline: Option<PathBuf>,
+    #[structopt(long)]
+    /// keep the change set, named `cliff-preview` by default, so that it may be reviewed and executed later
+    preview: bool,
+    #[structopt(long)]
+    /// keep change sets that fail or don't complete so that they may be inspected, printing their ARNs
+    retain_on_error: bool,
+    #[structopt(long)]
+    /// name of the change set to create
+    change_set_name: Option<String>,
+    #[structopt(long, conflicts_with = "change-set-name")]
+    /// name of an existing change set to describe and diff rather than creating one, as when a previous run stopped after creating it. created when it doesn't exist
+    resume: Option<String>,
+    #[structopt(long)]
+    /// description of the change set to create
+    description: Option<String>,
+    #[structopt(long = "notification-arn", number_of_values = 1)]
+    /// ARN of an SNS topic for the change set's stack operations to notify. may be repeated
+    notification_arns: Vec<String>,
+    #[structopt(long, parse(try_from_str = parse_role_arn))]
+    /// ARN of the IAM service role CloudFormation uses to make the change set's changes, in place of the caller's credentials
+    stack_role_arn: Option<String>,
+    #[structopt(long = "rollback-alarm-arn", number_of_values = 1, parse(try_from_str = parse_alarm_arn))]
+    /// ARN of a CloudWatch alarm that rolls back the change set's stack operation, as in deploys. may be repeated
+    rollback_alarm_arns: Vec<String>,
+    #[structopt(long, parse(try_from_str = parse_monitoring_minutes))]
+    /// minutes CloudFormation monitors rollback alarms for after deploying the change set, from 0 to 180
+    rollback_monitoring_minutes: Option<u32>,
+    #[structopt(long)]
+    /// fail rather than warn when a template declares more resources than CloudFormation allows in a stack
+    strict: bool,
+    #[structopt(long)]
+    /// fail before creating a change set when a template is larger than this many bytes. CloudFormation's own limits, 51200 bytes inline or 1048576 from S3, always apply
+    max_template_bytes: Option<usize>,
+    #[structopt(long, use_delimiter = true)]
+    /// comma-separated capabilities to acknowledge, like CAPABILITY_AUTO_EXPAND. CAPABILITY_IAM and CAPABILITY_NAMED_IAM are always acknowledged
+    capabilities: Vec<String>,
+    #[structopt(long, parse(try_from_str = parse_client_request_token))]
+    /// token identifying change set creation, to trace it or make it idempotent. each
+    /// stack, region, and watched diff derives its own from it. defaults to a random
+    /// token per stack
+    client_request_token: Option<String>,
+    #[structopt(long)]
+    /// keep CRLF line endings in local templates rather than converting them to LF
+    preserve_line_endings: bool,
+    #[structopt(long)]
+    /// lines of context around each change in the template diff. defaults to 3
+    context_lines: Option<usize>,
+    #[structopt(long, default_value = "1048576")]
+    /// capture at most this many bytes of template diff output, truncating the rest. 0 captures it all
+    max_diff_bytes: usize,
+    #[structopt(long, default_value = "30s", parse(try_from_str = parse_duration))]
+    /// kill the template differ if it runs longer than this, like 30s or 2m. 0 lets it run forever
+    diff_tool_timeout: Duration,
+    #[structopt(long)]
+    /// ignore lines of the template diff that differ only in whitespace, as when reindenting. passes -w to CLIFF_DIFFER, or fills its {ignore_whitespace}
+    ignore_whitespace: bool,
+    #[structopt(long)]
+    /// diff the local template against the deployed one, so lines only deployed are marked as added
+    reverse: bool,
+    #[structopt(long)]
+    /// url to POST a JSON summary of each diff to
+    notify_url: Option<String>,
+    #[structopt(long)]
+    /// wait for a stack with an operation in progress to stabilize before diffing
+    wait_for_stack_ready: bool,
+    #[structopt(long, default_value = "600")]
+    /// maximum number of seconds to wait with --wait-for-stack-ready
+    stack_ready_timeout: u64,
+    #[structopt(long, default_value = "5000")]
+    /// maximum milliseconds between change set polls. polls start fast and back off to this cap
+    max_poll_interval: u64,
+    #[structopt(long, default_value = "Original")]
+    /// stage of the deployed template to diff against: Original or Processed
+    template_stage: Stage,
+    #[structopt(long, requires = "stack-name", conflicts_with = "stack-name-prefix")]
+    /// diff the deployed stack's original template against its processed template to see what macros and transforms did
+    compare_stages: bool,
+    #[structopt(long, parse(from_os_str), conflicts_with = "compare-stages")]
+    /// local template to diff the template's text against in place of the deployed template. a change set is still created against the stack
+    before_file: Option<PathBuf>,
+    #[structopt(long, conflicts_with = "compare-stages")]
+    /// re-diff whenever the local template changes, until interrupted with ctrl-c
+    watch: bool,
+    #[structopt(long, conflicts_with_all = &["compare-stages", "watch", "preview", "resume"])]
+    /// only compare the deployed stack's parameters with those submitted, with NoEcho values redacted, without fetching templates or creating a change set
+    parameters_only: bool,
+    #[structopt(long, conflicts_with = "watch")]
+    /// command that builds the local template before diffing, like 'cdk synth'. `{template}` and `{stack}` are replaced with the template's filename and stack name. its stdout, when any, is written to the template
+    template_command: Option<String>,
+    #[structopt(long)]
+    /// print how long each phase of each diff took to stderr
+    timings: bool,
+    #[structopt(short, long)]
+    /// suppress progress feedback while waiting on change sets
+    quiet: bool,
+    #[structopt(long)]
+    /// render changes with ASCII markers rather than emoji. implied when stdout isn't a UTF-8 terminal
+    no_emoji: bool,
+    #[structopt(long, env = "CLIFF_MARKERS")]
+    /// comma-separated key=marker pairs overriding the markers of changes, as in add=+,remove=-,replacement=(replaced). keys are add, modify, remove, import, and replacement
+    markers: Option<MarkerOverrides>,
+    #[structopt(long)]
+    /// explain the markers and colors of changes before rendering them as text
+    legend: bool,
+    #[structopt(short, long = "stack-name", conflicts_with = "stack-name-prefix")]
+    /// name of the CloudFormation stack to diff against. defaults to the template's Metadata.Cliff.StackName
+    stack_name: Option<String>,
+    #[structopt(long)]
+    /// diff against every stack whose name starts with this prefix
+    stack_name_prefix: Option<String>,
+    #[structopt(
+        long,
+        hidden = true,
+        requires = "against",
+        conflicts_with_all = &["compare-stages", "template-env", "watch"]
+    )]
+    /// deprecated in favor of the offline-diff subcommand, to which it forwards
+    offline: bool,
+    #[structopt(long, hidden = true, parse(from_os_str), requires = "offline")]
+    /// the local template to compare against with --offline
+    against: Option<PathBuf>,
+    #[structopt(
+        long,
+        conflicts_with_all = &["filename", "watch", "template-command", "compare-stages"]
+    )]
+    /// environment variable to read the local template's body from, in place of a file
+    template_env: Option<String>,
+    #[structopt(long)]
+    /// format of the local template, yaml or json, when its extension doesn't say. suffixes the temporary files handed to CLIFF_DIFFER
+    template_format: Option<TemplateFormat>,
+    #[structopt(parse(from_os_str), required_unless_one = &["compare-stages", "template-env"])]
+    /// filename of local template. when diffing many stacks, `{stack}` is replaced with each stack's name
+    filename: Option<PathBuf>,
 }
 
-fn credentials() -> ChainProvider {
-    let mut chain = ChainProvider::new();
-    chain.set_timeout(Duration::from_millis(200));
-    chain
+#[allow(clippy::large_enum_variant)]
+#[derive(Debug, StructOpt)]
+#[structopt(name = "cliff")]
+/// A CloudFormation stack diff tool
+enum Command {
+    /// diff a local template against a deployed stack. this is the default when no subcommand is provided
+    Diff(DiffOptions),
+    /// validate a local template with CloudFormation
+    Validate {
+        #[structopt(flatten)]
+        common: Common,
+        #[structopt(parse(from_os_str))]
+        /// filename of local template
+        filename: PathBuf,
+    },
+    /// execute a change set kept with `diff --preview`
+    Execute {
+        #[structopt(flatten)]
+        common: Common,
+        #[structopt(short, long)]
+        /// name of the CloudFormation stack the change set belongs to
+        stack_name: String,
+        #[structopt(long, default_value = "cliff-preview")]
+        /// name of the change set to execute
+        change_set_name: String,
+        #[structopt(long, default_value = "60")]
+        /// warn when the change set was created more than this many minutes ago
+        stale_after: i64,
+    },
+    /// diff a local template against another local template without contacting AWS
+    OfflineDiff {
+        #[structopt(long, parse(from_os_str))]
+        /// the local template to compare against
+        against: PathBuf,
+        #[structopt(long)]
+        /// keep CRLF line endings in local templates rather than converting them to LF
+        preserve_line_endings: bool,
+        #[structopt(long)]
+        /// exit with a status of 2 when the templates differ
+        exit_code: bool,
+        #[structopt(long)]
+        /// lines of context around each change in the template diff. defaults to 3
+        context_lines: Option<usize>,
+        #[structopt(long, default_value = "1048576")]
+        /// capture at most this many bytes of template diff output, truncating the rest. 0 captures it all
+        max_diff_bytes: usize,
+        #[structopt(long, default_value = "30s", parse(try_from_str = parse_duration))]
+        /// kill the template differ if it runs longer than this, like 30s or 2m. 0 lets it run forever
+        diff_tool_timeout: Duration,
+        #[structopt(long)]
+        /// ignore lines of the template diff that differ only in whitespace, as when reindenting. passes -w to CLIFF_DIFFER, or fills its {ignore_whitespace}
+        ignore_whitespace: bool,
+        #[structopt(long)]
+        /// format of the local templates, yaml or json, when their extensions don't say
+        template_format: Option<TemplateFormat>,
+        #[structopt(long, env = "RUST_LOG_FORMAT", default_value = "text")]
+        /// format of the logs enabled with RUST_LOG: text, or json for one object per line
+        log_format: LogFormat,
+        #[structopt(parse(from_os_str))]
+        /// filename of local template
+        filename: PathBuf,
+    },
 }
 
-fn client() -> CloudFormationClient {
-    CloudFormationClient::new_with(
-        HttpClient::new().expect("failed to create request dispatcher"),
-        credentials(),
-        Region::default(),
-    )
+impl Command {
+    /// options of subcommands that contact AWS
+    fn common(&self) -> Option<&Common> {
+        match self {
+            Command::Diff(options) => Some(&options.common),
+            Command::Validate { common, .. } | Command::Execute { common, .. } => Some(common),
+            Command::OfflineDiff { .. } => None,
+        }
+    }
+
+    fn log_format(&self) -> LogFormat {
+        match self {
+            Command::OfflineDiff { log_format, .. } => *log_format,
+            _ => self
+                .common()
+                .map(|common| common.log_format)
+                .unwrap_or_default(),
+        }
+    }
 }
 
-fn current_parameters(
-    cf: CloudFormationClient,
-    stack_name: String,
-) -> impl Future<Item = Vec<(String, String)>, Error = Error> {
-    RETRIES.retry_if(
-        move || {
-            cf.describe_stacks(DescribeStacksInput {
-                stack_name: Some(stack_name.clone()),
-                ..DescribeStacksInput::default()
-            })
-            .map_err(Error::DescribeStack)
-            .map(|result| {
-                result
-                    .stacks
-                    .unwrap_or_default()
-                    .first()
-                    .map(|stack| {
-                        stack
-                            .clone()
-                            .parameters
-                            .unwrap_or_default()
-                            .into_iter()
-                            .map(|param| {
-                                (
-                                    param.parameter_key.unwrap_or_default(),
-                                    param
-                                        .resolved_value
-                                        .or(param.parameter_value)
-                                        .unwrap_or_default(),
-                                )
-                            })
-                            .collect()
-                    })
-                    .unwrap_or_default()
-            })
-        },
-        |err: &Error| {
-            log::debug!("get describe stacks error {}", err);
-            match err {
-                Error::Throttling(_) => true,
-                _ => false,
-            }
-        },
-    )
+/// names which select a subcommand, or top level help, when given as the first argument
+const SUBCOMMANDS: &[&str] = &[
+    "diff",
+    "validate",
+    "execute",
+    "offline-diff",
+    "help",
+    "-h",
+    "--help",
+    "-V",
+    "--version",
+];
+
+/// defaults invocations without a subcommand to `diff`, as cliff was invoked before
+/// it had subcommands
+fn with_default_subcommand(mut args: Vec<OsString>) -> Vec<OsString> {
+    let explicit = args
+        .get(1)
+        .map(|arg| arg.to_str().is_some_and(|arg| SUBCOMMANDS.contains(&arg)));
+    if explicit == Some(false) {
+        args.insert(1, "diff".into());
+    }
+    args
 }
 
-fn current_template(
-    cf: CloudFormationClient,
-    stack_name: String,
-) -> impl Future<Item = GetTemplateOutput, Error = Error> {
-    RETRIES.retry_if(
-        move || {
-            cf.get_template(GetTemplateInput {
-                stack_name: Some(stack_name.clone()),
-                template_stage: Some("Original".into()),
-                ..GetTemplateInput::default()
-            })
-            .map_err(Error::from)
-        },
-        |err: &Error| {
-            log::debug!("get template error {}", err);
-            match err {
-                Error::Throttling(_) => true,
-                _ => false,
-            }
-        },
-    )
+fn client<P>(
+    credentials: P,
+    region: Region,
+) -> Result<CloudFormationClient, Error>
+where
+    P: ProvideAwsCredentials + Send + Sync + 'static,
+    P::Future: Send,
+{
+    Ok(CloudFormationClient::new_with(
+        http::dispatcher()?,
+        credentials,
+        region,
+    ))
 }
 
-fn create_changeset(
-    cf: CloudFormationClient,
+fn ssm_client<P>(
+    credentials: P,
+    region: Region,
+) -> Result<SsmClient, Error>
+where
+    P: ProvideAwsCredentials + Send + Sync + 'static,
+    P::Future: Send,
+{
+    Ok(SsmClient::new_with(
+        http::dispatcher()?,
+        credentials,
+        region,
+    ))
+}
+
+fn s3_client<P>(
+    credentials: P,
+    region: Region,
+) -> Result<S3Client, Error>
+where
+    P: ProvideAwsCredentials + Send + Sync + 'static,
+    P::Future: Send,
+{
+    Ok(S3Client::new_with(http::dispatcher()?, credentials, region))
+}
+
+/// a builder for diffing in a region with the given credentials, along with the
+/// CloudFormation client it uses
+fn target<P>(
+    builder: &DifferBuilder,
+    credentials: P,
+    region: &Region,
+) -> Result<(CloudFormationClient, DifferBuilder), Error>
+where
+    P: ProvideAwsCredentials + Clone + Send + Sync + 'static,
+    P::Future: Send,
+{
+    let cf = client(credentials.clone(), region.clone())?;
+    let builder = builder
+        .clone()
+        .client(cf.clone())
+        .ssm_client(ssm_client(credentials.clone(), region.clone())?)
+        .s3_client(s3_client(credentials, region.clone())?)
+        .region(region.clone());
+    Ok((cf, builder))
+}
+
+/// what distinguishes one target from others diffing the same stack: its account, when
+/// diffing many accounts, and its region, when diffing many regions
+fn qualifier(
+    account: Option<&str>,
+    region: Option<&Region>,
+) -> String {
+    account
+        .into_iter()
+        .chain(region.map(Region::name))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// a stack's name, followed by its qualifier when it has one
+fn label(
+    stack_name: &str,
+    qualifier: &str,
+) -> String {
+    if qualifier.is_empty() {
+        stack_name.to_string()
+    } else {
+        format!("{} ({})", stack_name, qualifier)
+    }
+}
+
+/// how a run ended. each outcome has an exit status scripts may rely on
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Outcome {
+    /// no changes were detected, or changes were without --exit-code
+    Clean,
+    /// a runtime error, like a stack that failed to diff among many
+    Failed,
+    /// changes were detected with --exit-code
+    Changed,
+    /// a change met a --fail-on condition, a --deny-type or --allow-type guard, or
+    /// departed from --expect
+    Violated,
+}
+
+impl Outcome {
+    /// `Changed` when changes were detected and reporting them was asked for with
+    /// --exit-code, otherwise `Clean`
+    fn of_changes(
+        exit_code: bool,
+        changed: bool,
+    ) -> Self {
+        if exit_code && changed {
+            Outcome::Changed
+        } else {
+            Outcome::Clean
+        }
+    }
+
+    /// the outcome of a run. errors take precedence over violations, and violations
+    /// over changes
+    fn of_run(
+        errored: bool,
+        violated: bool,
+        exit_code: bool,
+        changed: bool,
+    ) -> Self {
+        if errored {
+            Outcome::Failed
+        } else if violated {
+            Outcome::Violated
+        } else {
+            Outcome::of_changes(exit_code, changed)
+        }
+    }
+
+    /// the exit status of the outcome
+    fn code(self) -> i32 {
+        match self {
+            Outcome::Clean => 0,
+            Outcome::Failed => 1,
+            Outcome::Changed => 2,
+            Outcome::Violated => 3,
+        }
+    }
+}
+
+fn main() {
+    match run() {
+        Ok(outcome) => exit(outcome.code()),
+        Err(err) => {
+            eprintln!("{}", err);
+            exit(Outcome::Failed.code())
+        }
+    }
+}
+
+fn run() -> Result<Outcome, Box<dyn StdError>> {
+    let command = Command::from_iter(with_default_subcommand(env::args_os().collect()));
+    init_logger(command.log_format());
+    let identity = match command.common().filter(|common| common.warm_credentials) {
+        Some(common) => {
+            let identity = common.warm_credentials()?;
+            let region = common.region().ok();
+            eprint!(
+                "{}",
+                render::render_identity(&identity, region.as_ref().map(Region::name))
+            );
+            Some(identity)
+        }
+        _ => None,
+    };
+    match command {
+        Command::Diff(options) => diff(options, identity),
+        Command::Validate { common, filename } => validate(common, &filename),
+        Command::Execute {
+            common,
+            stack_name,
+            change_set_name,
+            stale_after,
+        } => execute(common, stack_name, change_set_name, stale_after),
+        Command::OfflineDiff {
+            against,
+            preserve_line_endings,
+            exit_code,
+            context_lines,
+            max_diff_bytes,
+            diff_tool_timeout,
+            ignore_whitespace,
+            template_format,
+            filename,
+            ..
+        } => offline_diff(
+            &against,
+            &filename,
+            preserve_line_endings,
+            exit_code,
+            &diff_options(
+                context_lines,
+                false,
+                ignore_whitespace,
+                max_diff_bytes,
+                diff_tool_timeout,
+            ),
+            template_format,
+        ),
+    }
+}
+
+/// the stack name a template declares in its metadata, for when none was provided.
+/// the template is read from `path` unless its body is provided
+fn template_stack_name(
+    path: Option<&Path>,
+    body: Option<&str>,
+) -> Result<String, Box<dyn StdError>> {
+    let missing = "no stack name provided. pass --stack-name or declare one in the template's Metadata.Cliff.StackName";
+    let path = path.ok_or(missing)?;
+    let body = match body {
+        Some(body) => body.to_string(),
+        _ => read_template(path, false)?,
+    };
+    let template = template::parse(&body)
+        .map_err(|err| format!("Failed to parse template {}: {}", path.display(), err))?;
+    Ok(template::metadata_stack_name(&template).ok_or(missing)?)
+}
+
+/// reads a template body from an environment variable, along with a filename naming it
+/// in diffs whose extension reflects the body's format, unless a format is provided
+fn env_template(
+    name: &str,
+    format: Option<TemplateFormat>,
+) -> Result<(PathBuf, String), Box<dyn StdError>> {
+    let body =
+        env::var(name).map_err(|err| format!("Failed to read template from ${}: {}", name, err))?;
+    if body.trim().is_empty() {
+        return Err(format!("Failed to read template from ${}: it is empty", name).into());
+    }
+    let format = format.unwrap_or(if body.trim_start().starts_with('{') {
+        TemplateFormat::Json
+    } else {
+        TemplateFormat::Yaml
+    });
+    Ok((
+        PathBuf::from(format!("{}.{}", name, format.extension())),
+        body,
+    ))
+}
+
+/// reads a local template, normalizing its line endings unless they're preserved
+fn read_template(
+    path: &Path,
+    preserve_line_endings: bool,
+) -> Result<String, Box<dyn StdError>> {
+    let body = template::template_body(path)
+        .map_err(|err| format!("Failed to read template {}: {}", path.display(), err))?;
+    Ok(if preserve_line_endings {
+        body
+    } else {
+        template::normalize_line_endings(&body)
+    })
+}
+
+/// how template diffs are made, from their flags. a zero timeout lets differs run forever
+fn diff_options(
+    context_lines: Option<usize>,
+    reverse: bool,
+    ignore_whitespace: bool,
+    max_diff_bytes: usize,
+    diff_tool_timeout: Duration,
+) -> template::DiffOptions {
+    template::DiffOptions {
+        context_lines,
+        reverse,
+        ignore_whitespace,
+        max_bytes: max_diff_bytes,
+        timeout: Some(diff_tool_timeout).filter(|timeout| *timeout > Duration::default()),
+    }
+}
+
+fn offline_diff(
+    against: &Path,
+    filename: &Path,
+    preserve_line_endings: bool,
+    exit_code: bool,
+    options: &template::DiffOptions,
+    template_format: Option<TemplateFormat>,
+) -> Result<Outcome, Box<dyn StdError>> {
+    let before = read_template(against, preserve_line_endings)?;
+    let after = read_template(filename, preserve_line_endings)?;
+    let redactor = Redactor::new(&[&before, &after], &[]);
+    let diff = template::diff_template(
+        &against.to_string_lossy(),
+        filename,
+        template_format,
+        &redactor.redact(&after),
+        redactor.redact(&before),
+        options,
+    )?;
+    print!("{}", diff);
+    Ok(Outcome::of_changes(exit_code, !diff.is_empty()))
+}
+
+fn validate(
+    common: Common,
+    filename: &Path,
+) -> Result<Outcome, Box<dyn StdError>> {
+    let body = read_template(filename, false)?;
+    let cf = client(common.chain(), common.region()?)?;
+    let output = Runtime::new()?.block_on(validate_template(
+        cf,
+        body,
+        cliff::deadline(common.retry_deadline),
+    ))?;
+    print!("{}", render::render_validation(&output));
+    Ok(Outcome::Clean)
+}
+
+fn execute(
+    common: Common,
     stack_name: String,
-    template_body: String,
+    change_set_name: String,
+    stale_after: i64,
+) -> Result<Outcome, Box<dyn StdError>> {
+    let cf = client(common.chain(), common.region()?)?;
+    let mut runtime = Runtime::new()?;
+    let created = runtime.block_on(change_set_creation_time(
+        cf.clone(),
+        stack_name.clone(),
+        change_set_name.clone(),
+        cliff::deadline(common.retry_deadline),
+    ))?;
+    if let Some(warning) = created.as_deref().and_then(|created| {
+        stale_warning(
+            &change_set_name,
+            created,
+            Utc::now(),
+            ChronoDuration::minutes(stale_after),
+        )
+    }) {
+        eprintln!("{}", warning);
+    }
+    runtime.block_on(execute_changeset(
+        cf,
+        stack_name.clone(),
+        change_set_name.clone(),
+        cliff::deadline(common.retry_deadline),
+    ))?;
+    eprintln!(
+        "Executing change set {} for {}",
+        change_set_name, stack_name
+    );
+    Ok(Outcome::Clean)
+}
+
+/// reads a file cliff was pointed at, naming what it was for when that fails
+fn read_file(
+    path: &Path,
+    what: &str,
+) -> Result<String, Box<dyn StdError>> {
+    Ok(fs::read_to_string(path)
+        .map_err(|err| format!("Failed to read {} {}: {}", what, path.display(), err))?)
+}
+
+/// template parameters from the environment, then --parameters-json, then
+/// --parameters, with later values taking precedence
+fn merged_parameters(
+    parameters_from_env: Option<&str>,
+    parameters_json: Option<&str>,
     parameters: Vec<(String, String)>,
-) -> impl Future<Item = CreateChangeSetOutput, Error = Error> {
-    RETRIES.retry_if(
-        move || {
-            cf.create_change_set(CreateChangeSetInput {
-                change_set_name: CHANGESET_NAME.into(),
-                stack_name: stack_name.clone(),
-                template_body: Some(template_body.clone()),
-                capabilities: Some(vec!["CAPABILITY_IAM".into(), "CAPABILITY_NAMED_IAM".into()]),
-                parameters: Some(
-                    parameters
-                        .clone()
-                        .into_iter()
-                        .map(|(k, v)| Parameter {
-                            parameter_key: Some(k),
-                            parameter_value: Some(v),
-                            ..Parameter::default()
-                        })
-                        .collect(),
-                ),
-                ..CreateChangeSetInput::default()
-            })
-            .map_err(Error::from)
-        },
-        move |err: &Error| {
-            log::debug!("create changeset error {}", err);
-            match err {
-                Error::Create(RusotoError::Service(CreateChangeSetError::LimitExceeded(_))) => true,
-                Error::Throttling(_) => true,
-                _ => false,
-            }
-        },
-    )
+) -> Result<Vec<(String, String)>, Box<dyn StdError>> {
+    let mut merged = match parameters_from_env {
+        Some(prefix) => env_parameters(
+            prefix,
+            env::vars_os().filter_map(|(name, value)| {
+                Some((name.into_string().ok()?, value.into_string().ok()?))
+            }),
+        ),
+        _ => Vec::new(),
+    };
+    if let Some(json) = parameters_json {
+        merged.extend(parse_json_parameters(json)?);
+    }
+    merged.extend(parameters);
+    Ok(merged)
 }
 
-fn describe_changeset(
+/// diffs a stack's original template against the processed template CloudFormation
+/// deployed, with its macros and transforms expanded
+fn diff_stages(
     cf: CloudFormationClient,
-    stack_name: String,
-) -> Box<
-    dyn Future<Item = DescribeChangeSetOutput, Error = RusotoError<DescribeChangeSetError>> + Send,
-> {
-    Box::new(
-        cf.clone()
-            .describe_change_set(DescribeChangeSetInput {
-                change_set_name: CHANGESET_NAME.into(),
-                stack_name: Some(stack_name.clone()),
-                ..DescribeChangeSetInput::default()
-            })
-            .and_then(move |response| {
-                if response
-                    .status
-                    .iter()
-                    .any(|v| v.ends_with("_PROGRESS") || v.ends_with("_PENDING"))
-                {
-                    sleep(Duration::from_millis(500));
-                    future::Either::A(describe_changeset(cf, stack_name))
+    stack_name: &str,
+    retry_deadline: Option<Duration>,
+    options: &template::DiffOptions,
+    exit_code: bool,
+) -> Result<Outcome, Box<dyn StdError>> {
+    let (original, processed) = Runtime::new()?.block_on(stage_templates(
+        cf,
+        stack_name.to_string(),
+        cliff::deadline(retry_deadline),
+    ))?;
+    let redactor = Redactor::new(&[&original, &processed], &[]);
+    let diff = template::diff_template(
+        &format!("{}/{}", stack_name, Stage::Original),
+        Path::new(&format!("{}/{}", stack_name, Stage::Processed)),
+        None,
+        &redactor.redact(&processed),
+        redactor.redact(&original),
+        options,
+    )?;
+    print!("{}", diff);
+    Ok(Outcome::of_changes(exit_code, !diff.is_empty()))
+}
+
+/// each target's qualifier, its CloudFormation client, and a builder diffing in it
+type Targets = Vec<(String, CloudFormationClient, DifferBuilder)>;
+
+/// the targets a run diffs in: each region, in each account when accounts are provided.
+/// `identity` is who the credentials act as, when they were checked, in which case the
+/// role each account is diffed as is checked too
+fn targets(
+    runtime: &mut Runtime,
+    builder: &DifferBuilder,
+    credentials: &Chain,
+    regions: &[Region],
+    accounts: &[Account],
+    identity: Option<&CallerIdentity>,
+    fail_fast: bool,
+) -> Result<Targets, Box<dyn StdError>> {
+    let regions = if accounts.is_empty() {
+        regions
+            .iter()
+            .map(|region| (None, region.clone()))
+            .collect()
+    } else {
+        accounts
+            .iter()
+            .flat_map(|account| {
+                let regions = if account.regions.is_empty() {
+                    regions
                 } else {
-                    future::Either::B(future::ok(response))
+                    &account.regions
+                };
+                regions
+                    .iter()
+                    .map(move |region| (Some(account), region.clone()))
+            })
+            .collect::<Vec<_>>()
+    };
+    let multi_region = regions.iter().any(|(_, region)| *region != regions[0].1);
+    let mut targets = Vec::new();
+    // an account's role is assumed once, through the first of its regions, and shared
+    // by the rest
+    let mut assumed_roles = HashMap::<&str, (AssumeRole, DifferBuilder)>::new();
+    for (account, region) in &regions {
+        let qualifier = qualifier(
+            account.map(Account::id),
+            Some(region).filter(|_| multi_region),
+        );
+        let (cf, builder) = match account {
+            Some(account) => match assumed_roles.get(account.role_arn.as_str()) {
+                Some((assumed, builder)) => target(builder, assumed.clone(), region)?,
+                None => {
+                    let assumed =
+                        AssumeRole::new(&account.role_arn, region.clone(), credentials.clone());
+                    let mut builder = builder.clone();
+                    // each account acts as its own role, so it's checked on its own
+                    if identity.is_some() {
+                        match runtime.block_on(accounts::caller_identity(assumed.clone(), region)) {
+                            Ok(identity) => {
+                                eprint!(
+                                    "{}",
+                                    render::render_identity(&identity, Some(region.name()))
+                                );
+                                builder = builder.identity(identity);
+                            }
+                            Err(err) if fail_fast => {
+                                return Err(format!(
+                                    "failed to check credentials in {}: {}",
+                                    qualifier, err
+                                )
+                                .into())
+                            }
+                            Err(err) => {
+                                eprintln!("failed to check credentials in {}: {}", qualifier, err)
+                            }
+                        }
+                    }
+                    assumed_roles.insert(&account.role_arn, (assumed.clone(), builder.clone()));
+                    target(&builder, assumed, region)?
                 }
-            }),
-    )
+            },
+            _ => match identity {
+                Some(identity) => target(
+                    &builder.clone().identity(identity.clone()),
+                    credentials.clone(),
+                    region,
+                )?,
+                _ => target(builder, credentials.clone(), region)?,
+            },
+        };
+        targets.push((qualifier, cf, builder));
+    }
+    Ok(targets)
 }
 
-fn delete_changset(
-    cf: CloudFormationClient,
-    stack_name: String,
-) -> impl Future<Item = (), Error = RusotoError<DeleteChangeSetError>> {
-    cf.delete_change_set(DeleteChangeSetInput {
-        change_set_name: CHANGESET_NAME.into(),
-        stack_name: Some(stack_name),
-    })
-    .map(drop)
+/// the stacks a run diffs, and the targets they're diffed in
+struct Stacks {
+    targets: Targets,
+    stack_name: Option<String>,
+    stack_name_prefix: Option<String>,
+    filename: PathBuf,
+    before_file: Option<PathBuf>,
+    template_command: Option<String>,
+    client_request_token: Option<String>,
+    /// true when diffing many accounts, where one unreachable account doesn't keep the
+    /// rest from being diffed
+    accounts: bool,
+    fail_fast: bool,
+    retry_deadline: Option<Duration>,
 }
 
-fn render(change: Change) -> String {
-    let c = change.resource_change.unwrap_or_default();
-
-    let line = format!(
-        "{} {} {} {} {} {}",
-        c.action.clone().unwrap_or_default().bold(),
-        c.resource_type.unwrap_or_default().dimmed(),
-        c.logical_resource_id.unwrap_or_default().bold(),
-        c.physical_resource_id.unwrap_or_default().dimmed(),
-        c.scope.unwrap_or_default().join(", ").bold(),
-        if c.replacement.unwrap_or_default() == "True" {
-            " ⚠️  Requires replacement"
-        } else {
-            ""
-        },
-    );
-    match c.action.unwrap_or_default().as_str() {
-        "Modify" => format!("🔧 {}", line.bright_yellow()),
-        "Remove" => format!("✂️  {}", line.bright_red()),
-        "Add" => format!("🌱 {}", line.bright_green()),
-        _ => line,
-    }
-}
-
-fn sort(changes: &mut Vec<Change>) {
-    changes.sort_by(|a, b| {
-        a.resource_change
-            .clone()
-            .unwrap_or_default()
-            .action
-            .unwrap_or_default()
-            .cmp(
-                &b.resource_change
-                    .clone()
-                    .unwrap_or_default()
-                    .action
-                    .unwrap_or_default(),
-            )
-    });
+/// differs built for a run, along with the label and qualifier of each
+struct Built {
+    differs: Vec<Differ>,
+    labels: Labels,
+    /// true when listing stacks failed in some account
+    listing_failed: bool,
 }
 
-fn diff_changeset(changeset: DescribeChangeSetOutput) {
-    match &changeset
-        .status
-        .as_ref()
-        .map(|s| s.as_str())
-        .unwrap_or_default()[..]
-    {
-        complete if complete.ends_with("_COMPLETE") => {
-            let mut changes = changeset.changes.unwrap_or_default();
-            sort(&mut changes);
-            for change in changes {
-                if change.type_.clone().unwrap_or_default() == "Resource" {
-                    println!("{}", render(change));
-                } else {
-                    println!("other {:#?}", change);
+impl Stacks {
+    /// builds a differ for each stack in each target. stacks are listed anew on each
+    /// build when diffing by prefix. `iteration` counts the builds before this one, so
+    /// that each build's change sets are created with tokens of their own
+    fn build(
+        &self,
+        runtime: &mut Runtime,
+        iteration: usize,
+    ) -> Result<Built, Box<dyn StdError>> {
+        let mut listing_failed = false;
+        let mut differs = Vec::new();
+        let mut labels = Vec::new();
+        // templates are built once, however many regions they're diffed in
+        let mut built = HashSet::new();
+        let mut prepare = |path: &Path, name: &str| match &self.template_command {
+            Some(command) if built.insert((path.to_path_buf(), name.to_string())) => {
+                template::run_template_command(command, path, name)
+            }
+            _ => Ok(()),
+        };
+        for (qualifier, cf, builder) in &self.targets {
+            let builder = match &self.client_request_token {
+                Some(token) => builder
+                    .clone()
+                    .client_request_token(format!("{}-{}", token, iteration)),
+                _ => builder.clone(),
+            };
+            match (&self.stack_name, &self.stack_name_prefix) {
+                (_, Some(prefix)) => {
+                    let names = match runtime.block_on(stacks(
+                        cf.clone(),
+                        prefix.clone(),
+                        cliff::deadline(self.retry_deadline),
+                    )) {
+                        Ok(names) => names,
+                        // one unreachable account shouldn't keep the rest from being diffed
+                        Err(err) if self.accounts && !self.fail_fast => {
+                            listing_failed = true;
+                            eprintln!("failed to list stacks in {}: {}", qualifier, err);
+                            continue;
+                        }
+                        Err(err) if self.accounts => {
+                            return Err(
+                                format!("failed to list stacks in {}: {}", qualifier, err).into()
+                            )
+                        }
+                        Err(err) => return Err(err.into()),
+                    };
+                    for name in names {
+                        let mut builder = builder.clone();
+                        if let Some(before) = &self.before_file {
+                            builder = builder.before_template(stack_template(before, &name));
+                        }
+                        let template = stack_template(&self.filename, &name);
+                        prepare(&template, &name)?;
+                        labels.push((label(&name, qualifier), qualifier.clone()));
+                        differs.push(builder.template(template).stack_name(name).build()?);
+                    }
+                }
+                (Some(name), _) => {
+                    let mut builder = builder.clone();
+                    if let Some(before) = &self.before_file {
+                        builder = builder.before_template(before.clone());
+                    }
+                    prepare(&self.filename, name)?;
+                    differs.push(
+                        builder
+                            .stack_name(name.as_str())
+                            .template(self.filename.clone())
+                            .build()?,
+                    );
+                    labels.push((label(name, qualifier), qualifier.clone()));
                 }
+                _ => unreachable!("a stack name is resolved when no prefix is provided"),
             }
         }
-        "FAILED" => {
-            println!("⚠️ {}", changeset.status_reason.unwrap_or_default());
+        Ok(Built {
+            differs,
+            labels,
+            listing_failed,
+        })
+    }
+}
+
+/// conditions changes are held to with --fail-on, --deny-type, --allow-type, and
+/// --expect
+struct Gate {
+    fail_on: Vec<Condition>,
+    type_guard: TypeGuard,
+    expectation: Option<Expectation>,
+}
+
+impl Gate {
+    /// prints each way a diff's changes violate the gate, returning true when they do
+    fn check(
+        &self,
+        diff: &Diff,
+        label: &str,
+        options: &RenderOptions,
+    ) -> bool {
+        let mut violated = false;
+        for change in &diff.result.changes {
+            for condition in self.fail_on.iter().filter(|c| c.matches(change)) {
+                violated = true;
+                eprintln!(
+                    "--fail-on {} matched in {}: {}",
+                    condition,
+                    label,
+                    render::render_change(change, options)
+                );
+            }
+            if let Some(violation) = self.type_guard.check(change) {
+                violated = true;
+                let reason = match violation {
+                    TypeViolation::Denied(pattern) => {
+                        format!("--deny-type {} matched", pattern)
+                    }
+                    TypeViolation::NotAllowed => {
+                        format!("--allow-type doesn't allow {}", change.resource_type)
+                    }
+                };
+                eprintln!(
+                    "{} in {}: {}",
+                    reason,
+                    label,
+                    render::render_change(change, options)
+                );
+            }
         }
-        other => {
-            println!("change set resulted in status of {}", other);
+        if let Some(expectation) = &self.expectation {
+            for violation in expectation.check(&diff.result.changes) {
+                violated = true;
+                eprintln!("--expect failed in {}: {}", label, violation);
+            }
         }
+        violated
     }
 }
 
-fn suffix_tempfile(filename: &PathBuf) -> io::Result<tempfile::NamedTempFile> {
-    Ok(tempfile::Builder::new()
-        .suffix(
-            &filename
-                .extension()
-                .map(|x| format!(".{}", x.to_str().unwrap_or_default()))
-                .unwrap_or_default(),
-        )
-        .tempfile()?)
+/// how a run's diffs are rendered, written out, and judged
+struct Reporter {
+    output: Format,
+    options: RenderOptions,
+    /// true when diffing many accounts, whose diffs are always grouped
+    accounts: bool,
+    summary_only: bool,
+    compact: bool,
+    legend: bool,
+    stderr_summary: bool,
+    baseline: Option<Baseline>,
+    /// files written alongside --output, each rendered from the same diffs
+    sinks: Vec<(Format, PathBuf)>,
+    /// the change set kept with --preview, whose execution instructions are printed
+    preview: Option<String>,
+    notify_url: Option<String>,
+    timings: bool,
+    /// how long resolving credentials took, when it was timed
+    credentials_elapsed: Option<Duration>,
+    gate: Gate,
+    exit_code: bool,
+    min_severity: Option<Severity>,
 }
 
-fn diff_template(
-    filename: &PathBuf,
-    template_body: String,
-) -> Result<String, Box<dyn StdError>> {
-    let mut tmp = suffix_tempfile(&filename)?;
-    tmp.write_all(&template_body.as_bytes().to_vec()[..])?;
-    tmp.flush()?;
-    let path = tmp.path().to_str().unwrap_or_default();
-    let tool = env::var("CLIFF_DIFFER")
-        .ok()
-        .unwrap_or_else(|| "diff --label -u".to_string());
-    let elements = tool.split_whitespace().collect::<Vec<_>>();
-    let (program, args) = match elements.split_first() {
-        Some(pair) => pair,
-        _ => return Err(Box::new(Error::Differ(tool))),
-    };
-    let output = args
-        .iter()
-        .fold(&mut Command::new(program), |cmd, arg| cmd.arg(arg))
-        .args(&[filename.to_str().unwrap_or_default(), path])
-        .output()?;
-    /*if output.status.code().unwrap_or_default() != 0 {
-        eprintln!("{}", from_utf8(&output.stderr)?);
-        return Err(Box::new(Error::Differ(tool)));
-    }*/
-    Ok(from_utf8(&output.stdout)?.into())
-}
+impl Reporter {
+    /// reports the results of diffing the stacks labeled, yielding the run's outcome.
+    /// with a single stack, its error is returned rather than reported
+    fn report(
+        &self,
+        runtime: &mut Runtime,
+        labels: Labels,
+        listing_failed: bool,
+        results: Vec<Result<Diff, Error>>,
+    ) -> Result<Outcome, Box<dyn StdError>> {
+        let grouped = labels.len() > 1 || self.accounts;
+        let mut errored = listing_failed;
+        let skipped = labels.len() - results.len();
+        let mut diffs = Vec::new();
+        let mut diffed = Vec::new();
+        for (result, (label, qualifier)) in results.into_iter().zip(labels) {
+            match result {
+                Ok(diff) => {
+                    diffs.push(diff);
+                    diffed.push(qualifier);
+                }
+                Err(err) if !grouped => return Err(err.into()),
+                Err(err) => {
+                    errored = true;
+                    eprintln!("failed to diff {}: {}", label, err);
+                }
+            }
+        }
+        for diff in &diffs {
+            for note in &diff.notes {
+                eprintln!("{}", note);
+            }
+            if let Some(err) = &diff.template_error {
+                eprintln!("failed to diff templates of {}: {}", diff.stack_name, err);
+            }
+        }
+        if skipped > 0 {
+            eprintln!(
+                "skipped {} remaining {} after the first failure (--fail-fast)",
+                skipped,
+                if skipped == 1 { "stack" } else { "stacks" }
+            );
+        }
+        self.print(&diffs, &diffed, grouped);
+        self.write(&diffs)?;
+        self.deliver(runtime, &diffs);
+        let mut violated = false;
+        for (diff, qualifier) in diffs.iter().zip(&diffed) {
+            violated |= self
+                .gate
+                .check(diff, &label(&diff.stack_name, qualifier), &self.options);
+        }
+        let changed = diffs.iter().any(|diff| {
+            let summary = &diff.result.summary;
+            summary.changed()
+                && self
+                    .min_severity
+                    .is_none_or(|min| summary.severity >= Some(min))
+        });
+        Ok(Outcome::of_run(errored, violated, self.exit_code, changed))
+    }
+
+    /// prints diffs, each followed by how it departs from the baseline, if any
+    fn print(
+        &self,
+        diffs: &[Diff],
+        qualifiers: &[String],
+        grouped: bool,
+    ) {
+        let (output, options) = (self.output, &self.options);
+        if self.legend && output == Format::Text && !self.summary_only {
+            print!("{}", render::render_legend(options));
+        }
+        for (diff, qualifier) in diffs.iter().zip(qualifiers) {
+            let label = label(&diff.stack_name, qualifier);
+            if output.per_run() {
+                // rendered once for every diff below
+            } else if self.summary_only {
+                if !qualifier.is_empty() && output == Format::Text {
+                    print!("{} ", qualifier);
+                }
+                print!("{}", render::render_summary(output, diff, options));
+            } else {
+                if grouped && output == Format::Text {
+                    println!("{}", format!("== {} ==", label).bold());
+                }
+                if self.compact && output == Format::Text {
+                    print!("{}", render::render_compact(diff, options));
+                } else {
+                    print!("{}", render::render(output, diff, options));
+                }
+            }
+            if let Some(baseline) = &self.baseline {
+                let deltas = baseline
+                    .changes(&diff.stack_name, diff.region.name())
+                    .map(|before| baseline::compare(before, &diff.result.changes));
+                let section = render::render_deltas(deltas.as_deref());
+                if output == Format::Text {
+                    print!("{}", section);
+                } else {
+                    eprint!("{}", section);
+                }
+            }
+            if self.stderr_summary {
+                eprint!("{}", render::render_script_summary(diff));
+            }
+        }
+        if output.per_run() {
+            print!("{}", render::render_all(output, diffs, options));
+        }
+    }
+
+    /// writes diffs to each of the files written alongside --output
+    fn write(
+        &self,
+        diffs: &[Diff],
+    ) -> Result<(), Box<dyn StdError>> {
+        for (format, path) in &self.sinks {
+            let rendered = render::render_all(*format, diffs, &self.options);
+            fs::write(path, rendered)
+                .map_err(|err| format!("Failed to write {}: {}", path.display(), err))?;
+        }
+        Ok(())
+    }
+
+    /// tells of diffs beyond their output: how to execute previewed change sets,
+    /// notifications, and timings
+    fn deliver(
+        &self,
+        runtime: &mut Runtime,
+        diffs: &[Diff],
+    ) {
+        if let Some(name) = &self.preview {
+            for diff in diffs {
+                eprintln!(
+                    "{}",
+                    execute_instructions(
+                        &diff.stack_name,
+                        name,
+                        diff.region.name(),
+                        diff.changeset.creation_time.as_deref()
+                    )
+                );
+            }
+        }
+        if let Some(url) = &self.notify_url {
+            for diff in diffs {
+                let payload = Payload::new(diff);
+                if let Err(err) = runtime.block_on(notify(url, &payload)) {
+                    eprintln!("warning: {}", err);
+                }
+            }
+        }
+        if self.timings {
+            eprint!(
+                "{}",
+                render::render_timings(self.credentials_elapsed, diffs)
+            );
+        }
+    }
 
-fn template_body<P: AsRef<Path>>(filename: P) -> io::Result<String> {
-    fs::read_to_string(filename)
+    /// reports only how the parameters of each stack built would change
+    fn report_parameters(
+        &self,
+        runtime: &mut Runtime,
+        built: Built,
+        concurrency: usize,
+    ) -> Result<Outcome, Box<dyn StdError>> {
+        let Built {
+            differs,
+            labels,
+            listing_failed,
+        } = built;
+        let grouped = labels.len() > 1 || self.accounts;
+        let results = runtime.block_on(
+            stream::iter_ok::<_, Error>(differs)
+                .map(|differ| {
+                    for note in differ.notes() {
+                        eprintln!("{}", note);
+                    }
+                    let (stack_name, region) =
+                        (differ.stack_name().to_string(), differ.region().clone());
+                    differ
+                        .diff_parameters()
+                        .then(move |result| Ok((stack_name, region, result)))
+                })
+                .buffered(concurrency.max(1))
+                .collect(),
+        )?;
+        let mut errored = listing_failed;
+        let mut changed = false;
+        for ((stack_name, region, result), (label, _)) in results.into_iter().zip(labels) {
+            let parameters = match result {
+                Ok(parameters) => parameters,
+                Err(err) if !grouped => return Err(err.into()),
+                Err(err) => {
+                    errored = true;
+                    eprintln!("failed to diff parameters of {}: {}", label, err);
+                    continue;
+                }
+            };
+            changed |= !parameters.is_empty();
+            if grouped && self.output == Format::Text {
+                println!("{}", format!("== {} ==", label).bold());
+            }
+            print!(
+                "{}",
+                render::render_parameters_only(
+                    self.output,
+                    &stack_name,
+                    region.name(),
+                    &parameters,
+                    &self.options,
+                )
+            );
+        }
+        Ok(Outcome::of_run(errored, false, self.exit_code, changed))
+    }
 }
 
-fn main() {
-    if let Err(err) = run() {
-        eprintln!("{}", err);
-        exit(1)
+/// diffs every differ, stopping at the first failure with --fail-fast
+fn run_all(
+    differs: Vec<Differ>,
+    fail_fast: bool,
+    concurrency: usize,
+) -> impl Future<Item = Vec<Result<Diff, Error>>, Error = Error> + Send {
+    if fail_fast {
+        future::Either::A(diff_all_fail_fast(differs, concurrency))
+    } else {
+        future::Either::B(diff_all(differs, concurrency))
     }
 }
 
-fn merge(
-    prev: Vec<(String, String)>,
-    provided: Vec<(String, String)>,
-) -> Vec<(String, String)> {
-    let lookup = provided.into_iter().collect::<HashMap<String, String>>();
-    prev.into_iter()
-        .map(|(k, v)| {
-            let value = lookup.get(&k).cloned().unwrap_or(v);
-            (k, value)
-        })
-        .collect()
+/// diffs stacks anew each time their templates change, until ctrl-c is pressed. change
+/// sets are deleted on the way out unless they're kept with --preview
+fn watch_stacks(
+    runtime: &mut Runtime,
+    stacks: &Stacks,
+    reporter: &Reporter,
+    concurrency: usize,
+) -> Result<Outcome, Box<dyn StdError>> {
+    let mut ctrl_c = Some(runtime.block_on(tokio_signal::ctrl_c())?);
+    let mut paths = Vec::<PathBuf>::new();
+    let mut iteration = 0;
+    loop {
+        print!("{}", CLEAR_SCREEN);
+        let mut since = watch::modified(&paths);
+        match stacks.build(runtime, iteration) {
+            Ok(built) => {
+                paths = built
+                    .differs
+                    .iter()
+                    .map(|differ| differ.filename().to_path_buf())
+                    .collect();
+                paths.sort();
+                paths.dedup();
+                if since.len() != paths.len() {
+                    since = watch::modified(&paths);
+                }
+                let results = until_interrupted(
+                    runtime,
+                    &mut ctrl_c,
+                    run_all(built.differs.clone(), stacks.fail_fast, concurrency),
+                )?;
+                match results {
+                    Some(results) => {
+                        if let Err(err) =
+                            reporter.report(runtime, built.labels, built.listing_failed, results)
+                        {
+                            eprintln!("{}", err);
+                        }
+                    }
+                    _ if reporter.preview.is_some() => return Ok(Outcome::Clean),
+                    _ => {
+                        eprintln!("deleting change sets");
+                        let deletes = built
+                            .differs
+                            .iter()
+                            .map(|differ| differ.delete_change_set().then(|_| Ok::<_, Error>(())))
+                            .collect::<Vec<_>>();
+                        runtime.block_on(future::join_all(deletes))?;
+                        return Ok(Outcome::Clean);
+                    }
+                }
+            }
+            Err(err) if paths.is_empty() => return Err(err),
+            Err(err) => eprintln!("{}", err),
+        }
+        eprintln!(
+            "watching {} for changes. press ctrl-c to stop",
+            paths
+                .iter()
+                .map(|path| path.display().to_string())
+                .collect::<Vec<_>>()
+                .join(", ")
+        );
+        let changed = watch::changed(paths.clone(), since);
+        if until_interrupted(runtime, &mut ctrl_c, changed)?.is_none() {
+            return Ok(Outcome::Clean);
+        }
+        iteration += 1;
+    }
 }
 
-fn run() -> Result<(), Box<dyn StdError>> {
-    env_logger::init();
-    let Options {
+/// diffs stacks. `identity` is who the credentials act as, when they were checked
+fn diff(
+    options: DiffOptions,
+    identity: Option<CallerIdentity>,
+) -> Result<Outcome, Box<dyn StdError>> {
+    let DiffOptions {
+        common,
         parameters,
+        parameters_json,
+        parameters_from_env,
+        regions,
+        concurrency,
+        fail_fast,
+        accounts_file,
+        artifacts_bucket,
+        cache_dir,
+        no_cache,
+        include_property_values,
+        include_policy_changes,
+        include_parameter_values,
+        sort_by,
+        only_resources,
+        output,
+        json_file,
+        markdown_file,
+        facts_file,
+        summary_only,
+        max_changes,
+        compact,
+        by_type,
+        stderr_summary,
+        exit_code,
+        min_severity,
+        fail_on,
+        deny_type,
+        allow_type,
+        expect,
+        baseline,
+        preview,
+        retain_on_error,
+        change_set_name,
+        resume,
+        description,
+        notification_arns,
+        stack_role_arn,
+        rollback_alarm_arns,
+        rollback_monitoring_minutes,
+        strict,
+        max_template_bytes,
+        capabilities,
+        client_request_token,
+        preserve_line_endings,
+        context_lines,
+        max_diff_bytes,
+        diff_tool_timeout,
+        ignore_whitespace,
+        reverse,
+        notify_url,
+        wait_for_stack_ready,
+        stack_ready_timeout,
+        max_poll_interval,
+        template_stage,
+        compare_stages,
+        before_file,
+        watch,
+        parameters_only,
+        template_command,
+        timings,
+        quiet,
+        no_emoji,
+        markers,
+        legend,
         stack_name,
+        stack_name_prefix,
+        offline,
+        against,
+        template_env,
+        template_format,
         filename,
-    } = Options::from_args();
-    let cf = client();
-    let cf2 = cf.clone();
-    let cf3 = cf.clone();
-    let stack_name2 = stack_name.clone();
-    let stack_name3 = stack_name.clone();
-
-    let current_template = current_template(cf.clone(), stack_name.clone());
-    let body = template_body(filename.clone())?;
-    let changeset =
-        current_parameters(cf.clone(), stack_name.clone()).and_then(|prev_parameters| {
-            create_changeset(cf, stack_name, body, merge(prev_parameters, parameters))
-        });
-
-    let diff_templates = current_template.and_then(move |current| {
-        match diff_template(&filename, current.template_body.unwrap_or_default()) {
-            Ok(diff) => {
-                println!("{}", diff);
-                Ok(())
-            }
-            /*todo*/ _ => Ok(()),
+    } = options;
+    if let (true, Some(against)) = (offline, &against) {
+        eprintln!(
+            "warning: --offline is deprecated. use `cliff offline-diff --against {} <filename>` instead",
+            against.display()
+        );
+        return offline_diff(
+            against,
+            &filename.unwrap_or_default(),
+            preserve_line_endings,
+            exit_code,
+            &diff_options(
+                context_lines,
+                false,
+                ignore_whitespace,
+                max_diff_bytes,
+                diff_tool_timeout,
+            ),
+            template_format,
+        );
+    }
+    let env_template = template_env
+        .as_deref()
+        .map(|name| env_template(name, template_format))
+        .transpose()?;
+    let filename = match &env_template {
+        Some((path, _)) => Some(path.clone()),
+        _ => filename,
+    };
+    let stack_name = match stack_name {
+        None if stack_name_prefix.is_none() => Some(template_stack_name(
+            filename.as_deref(),
+            env_template.as_ref().map(|(_, body)| body.as_str()),
+        )?),
+        name => name,
+    };
+    let filename = filename.unwrap_or_default();
+    let options = RenderOptions {
+        markers: if !no_emoji
+            && atty::is(atty::Stream::Stdout)
+            && utf8_locale(|name| env::var(name).ok())
+        {
+            Markers::Emoji
+        } else {
+            Markers::Ascii
+        },
+        overrides: markers.unwrap_or_default(),
+        max_changes,
+        by_type,
+    };
+    let parameters = merged_parameters(
+        parameters_from_env.as_deref(),
+        parameters_json.as_deref(),
+        parameters,
+    )?;
+    let expectation = match expect {
+        Some(path) => Some(Expectation::parse(&read_file(&path, "expectation")?)?),
+        _ => None,
+    };
+    let baseline = match baseline {
+        Some(path) => Some(Baseline::parse(&read_file(&path, "baseline")?)?),
+        _ => None,
+    };
+    let credentials = common.chain();
+    let retry_deadline = common.retry_deadline;
+    let regions = if regions.is_empty() {
+        vec![common.region()?]
+    } else {
+        regions
+    };
+    let accounts = match &accounts_file {
+        Some(path) => accounts::parse(&read_file(path, "accounts file")?)?,
+        _ => Vec::new(),
+    };
+    let template_diff = diff_options(
+        context_lines,
+        reverse,
+        ignore_whitespace,
+        max_diff_bytes,
+        diff_tool_timeout,
+    );
+    if let (true, Some(name)) = (compare_stages, &stack_name) {
+        return diff_stages(
+            client(credentials, regions[0].clone())?,
+            name,
+            retry_deadline,
+            &template_diff,
+            exit_code,
+        );
+    }
+    let mut builder = Differ::builder()
+        .parameters(parameters)
+        .refresh(no_cache)
+        .include_property_values(include_property_values)
+        .include_policy_changes(include_policy_changes)
+        .include_parameter_values(include_parameter_values)
+        .sort_by(sort_by)
+        .max_poll_interval(Duration::from_millis(max_poll_interval))
+        .keep_change_set(preview)
+        .retain_on_error(retain_on_error)
+        .reverse(reverse)
+        .ignore_whitespace(ignore_whitespace)
+        .max_diff_bytes(max_diff_bytes)
+        .diff_timeout(template_diff.timeout)
+        .strict(strict)
+        .preserve_line_endings(preserve_line_endings)
+        .template_stage(template_stage)
+        .notification_arns(notification_arns)
+        .capabilities(capabilities)
+        .progress(!quiet && atty::is(atty::Stream::Stdout));
+    if let Some((_, body)) = env_template {
+        builder = builder.template_body(body);
+    }
+    if let Some(bucket) = artifacts_bucket {
+        builder = builder.artifacts_bucket(bucket);
+    }
+    if let Some(description) = description {
+        builder = builder.description(description);
+    }
+    if let Some(max) = max_template_bytes {
+        builder = builder.max_template_bytes(max);
+    }
+    if let Some(budget) = retry_deadline {
+        builder = builder.retry_deadline(budget);
+    }
+    if let Some(arn) = stack_role_arn {
+        builder = builder.stack_role_arn(arn);
+    }
+    if !rollback_alarm_arns.is_empty() {
+        builder = builder.rollback_alarm_arns(rollback_alarm_arns);
+    }
+    if let Some(minutes) = rollback_monitoring_minutes {
+        builder = builder.rollback_monitoring_minutes(minutes);
+    }
+    if let Some(only_resources) = only_resources {
+        builder = builder.only_resources(only_resources);
+    }
+    if let Some(lines) = context_lines {
+        builder = builder.context_lines(lines);
+    }
+    if let Some(format) = template_format {
+        builder = builder.template_format(format);
+    }
+    if resume.is_some() {
+        builder = builder.resume(true);
+    }
+    let change_set_name = change_set_name.or(resume).or_else(|| {
+        if preview {
+            Some(PREVIEW_CHANGESET_NAME.into())
+        } else {
+            None
         }
     });
+    if let Some(name) = &change_set_name {
+        builder = builder.change_set_name(name.as_str());
+    }
+    if wait_for_stack_ready {
+        builder = builder.wait_for_stack_ready(Duration::from_secs(stack_ready_timeout));
+    }
+    if let Some(dir) = cache_dir {
+        builder = builder.cache(Cache::new(dir, CACHE_TTL));
+    }
 
-    let diff_changeset = diff_templates.and_then(|_| changeset).and_then(|_| {
-        describe_changeset(cf2, stack_name2)
-            .map_err(Error::DescribeChangeset)
-            .map(diff_changeset)
-    });
+    let mut runtime = Runtime::new()?;
+    let credentials_elapsed = if timings {
+        let start = Instant::now();
+        if let Err(err) = runtime.block_on(credentials.credentials()) {
+            log::debug!("failed to resolve credentials {}", err);
+        }
+        Some(start.elapsed())
+    } else {
+        None
+    };
+    let stacks = Stacks {
+        targets: targets(
+            &mut runtime,
+            &builder,
+            &credentials,
+            &regions,
+            &accounts,
+            identity.as_ref(),
+            fail_fast,
+        )?,
+        stack_name,
+        stack_name_prefix,
+        filename,
+        before_file,
+        template_command,
+        client_request_token,
+        accounts: !accounts.is_empty(),
+        fail_fast,
+        retry_deadline,
+    };
+    let reporter = Reporter {
+        output,
+        options,
+        accounts: !accounts.is_empty(),
+        summary_only,
+        compact,
+        legend,
+        stderr_summary,
+        baseline,
+        sinks: vec![
+            (Format::Json, json_file),
+            (Format::Markdown, markdown_file),
+            (Format::Facts, facts_file),
+        ]
+        .into_iter()
+        .filter_map(|(format, path)| Some((format, path?)))
+        .collect(),
+        preview: change_set_name.filter(|_| preview),
+        notify_url,
+        timings,
+        credentials_elapsed,
+        gate: Gate {
+            fail_on,
+            type_guard: TypeGuard::new(allow_type, deny_type),
+            expectation,
+        },
+        exit_code,
+        min_severity,
+    };
+    if parameters_only {
+        let built = stacks.build(&mut runtime, 0)?;
+        return reporter.report_parameters(&mut runtime, built, concurrency);
+    }
+    if watch {
+        return watch_stacks(&mut runtime, &stacks, &reporter, concurrency);
+    }
+    let built = stacks.build(&mut runtime, 0)?;
+    let results = runtime.block_on(run_all(built.differs, fail_fast, concurrency))?;
+    reporter.report(&mut runtime, built.labels, built.listing_failed, results)
+}
+
+/// runs work to completion, yielding None instead when ctrl-c is pressed first
+fn until_interrupted<F>(
+    runtime: &mut Runtime,
+    ctrl_c: &mut Option<IoStream<()>>,
+    work: F,
+) -> Result<Option<F::Item>, Box<dyn StdError>>
+where
+    F: Future + Send + 'static,
+    F::Item: Send + 'static,
+    F::Error: StdError + Send + 'static,
+{
+    let signals = match ctrl_c.take() {
+        Some(signals) => signals,
+        _ => return Ok(None),
+    };
+    match runtime.block_on(work.select2(signals.into_future())) {
+        Ok(future::Either::A((item, signals))) => {
+            *ctrl_c = signals.into_inner();
+            Ok(Some(item))
+        }
+        Ok(future::Either::B(_)) => Ok(None),
+        Err(future::Either::A((err, _))) => Err(Box::new(err)),
+        Err(future::Either::B(((err, _), _))) => Err(Box::new(err)),
+    }
+}
+
+/// true when the first locale variable set names a UTF-8 encoding
+fn utf8_locale<F>(var: F) -> bool
+where
+    F: Fn(&str) -> Option<String>,
+{
+    ["LC_ALL", "LC_CTYPE", "LANG"]
+        .iter()
+        .filter_map(|name| var(name).filter(|value| !value.is_empty()))
+        .next()
+        .map(|value| {
+            let value = value.to_lowercase();
+            value.contains("utf-8") || value.contains("utf8")
+        })
+        .unwrap_or_default()
+}
 
-    let complete =
-        diff_changeset.and_then(|_| delete_changset(cf3, stack_name3).map_err(Error::Delete));
+/// instructions for executing a kept change set
+fn execute_instructions(
+    stack_name: &str,
+    change_set_name: &str,
+    region: &str,
+    created: Option<&str>,
+) -> String {
+    let created = created
+        .map(|created| format!(", created {},", created))
+        .unwrap_or_default();
+    format!(
+        "To deploy change set {}{} for {}, run\n  aws cloudformation execute-change-set --stack-name {} --change-set-name {} --region {}",
+        change_set_name, created, stack_name, stack_name, change_set_name, region
+    )
+}
 
-    Runtime::new().unwrap().block_on(complete)?;
-    Ok(())
+/// a warning for change sets created longer than `stale_after` before `now`, whose
+/// changes may no longer reflect the stack
+fn stale_warning(
+    change_set_name: &str,
+    created: &str,
+    now: DateTime<Utc>,
+    stale_after: ChronoDuration,
+) -> Option<String> {
+    let created = DateTime::parse_from_rfc3339(created).ok()?;
+    let age = now.signed_duration_since(created);
+    if age <= stale_after {
+        return None;
+    }
+    Some(format!(
+        "warning: change set {} was created {} minutes ago, at {}. the stack may have changed since",
+        change_set_name,
+        age.num_minutes(),
+        created.to_rfc3339()
+    ))
+}
+
+/// resolves the template filename for a given stack
+fn stack_template(
+    filename: &Path,
+    stack_name: &str,
+) -> PathBuf {
+    PathBuf::from(
+        filename
+            .to_string_lossy()
+            .replace(STACK_PLACEHOLDER, stack_name),
+    )
 }
 
 #[cfg(test)]
@@ -407,37 +2034,330 @@ mod tests {
     use super::*;
 
     #[test]
-    fn merge_merges_parameters() {
+    fn stack_template_replaces_placeholder() {
         assert_eq!(
-            merge(
-                vec![("foo".into(), "bar".into()), ("baz".into(), "boom".into())],
-                vec![("baz".into(), "zoom".into())]
-            ),
-            vec![("foo".into(), "bar".into()), ("baz".into(), "zoom".into())]
+            stack_template(Path::new("templates/{stack}.yml"), "svc-foo-prod"),
+            PathBuf::from("templates/svc-foo-prod.yml")
+        );
+        assert_eq!(
+            stack_template(Path::new("template.yml"), "svc-foo-prod"),
+            PathBuf::from("template.yml")
+        );
+    }
+
+    #[test]
+    fn with_default_subcommand_inserts_diff() {
+        let args = |args: &[&str]| args.iter().map(OsString::from).collect::<Vec<_>>();
+        assert_eq!(
+            with_default_subcommand(args(&["cliff", "-s", "svc", "template.yml"])),
+            args(&["cliff", "diff", "-s", "svc", "template.yml"])
+        );
+        assert_eq!(
+            with_default_subcommand(args(&["cliff", "validate", "template.yml"])),
+            args(&["cliff", "validate", "template.yml"])
+        );
+        assert_eq!(
+            with_default_subcommand(args(&["cliff", "--help"])),
+            args(&["cliff", "--help"])
+        );
+        assert_eq!(with_default_subcommand(args(&["cliff"])), args(&["cliff"]));
+    }
+
+    #[test]
+    fn bare_offline_invocations_still_parse() {
+        let args = [
+            "cliff",
+            "--offline",
+            "--against",
+            "golden.yml",
+            "template.yml",
+        ]
+        .iter()
+        .map(OsString::from)
+        .collect();
+        assert!(matches!(
+            Command::from_iter_safe(with_default_subcommand(args)),
+            Ok(Command::Diff(DiffOptions {
+                offline: true,
+                against: Some(against),
+                ..
+            })) if against == Path::new("golden.yml")
+        ));
+    }
+
+    #[test]
+    fn utf8_locale_uses_first_set_variable() {
+        let env = |vars: &'static [(&'static str, &'static str)]| {
+            move |name: &str| {
+                vars.iter()
+                    .find(|(key, _)| *key == name)
+                    .map(|(_, value)| value.to_string())
+            }
+        };
+        assert!(utf8_locale(env(&[("LANG", "en_US.UTF-8")])));
+        assert!(utf8_locale(env(&[("LC_ALL", ""), ("LANG", "C.utf8")])));
+        assert!(!utf8_locale(env(&[
+            ("LC_ALL", "C"),
+            ("LANG", "en_US.UTF-8")
+        ])));
+        assert!(!utf8_locale(env(&[])));
+    }
+
+    #[test]
+    fn execute_instructions_include_command() {
+        assert!(execute_instructions("svc", "cliff-preview", "us-east-1", None).ends_with(
+            "aws cloudformation execute-change-set --stack-name svc --change-set-name cliff-preview --region us-east-1"
+        ));
+        assert!(execute_instructions(
+            "svc",
+            "cliff-preview",
+            "us-east-1",
+            Some("2020-01-01T00:00:00Z")
         )
+        .starts_with("To deploy change set cliff-preview, created 2020-01-01T00:00:00Z, for svc"));
+    }
+
+    #[test]
+    fn stale_warning_warns_of_old_change_sets() {
+        let now = DateTime::parse_from_rfc3339("2020-01-01T02:00:00Z")
+            .unwrap()
+            .with_timezone(&Utc);
+        let hour = ChronoDuration::minutes(60);
+        assert_eq!(
+            stale_warning("cliff-preview", "2020-01-01T00:30:00.000Z", now, hour).as_deref(),
+            Some("warning: change set cliff-preview was created 90 minutes ago, at 2020-01-01T00:30:00+00:00. the stack may have changed since")
+        );
+        assert_eq!(
+            stale_warning("cliff-preview", "2020-01-01T01:30:00Z", now, hour),
+            None
+        );
+        assert_eq!(stale_warning("cliff-preview", "yesterday", now, hour), None);
+    }
+
+    #[test]
+    fn parse_key_val_splits_on_first_equals() {
+        assert_eq!(
+            parse_key_val::<String, String>("Token=dG9rZW4=").ok(),
+            Some(("Token".into(), "dG9rZW4=".into()))
+        );
+        assert_eq!(
+            parse_key_val::<String, String>("Query=a=1&b=2").ok(),
+            Some(("Query".into(), "a=1&b=2".into()))
+        );
+    }
+
+    #[test]
+    fn parse_key_val_allows_empty_values() {
+        assert_eq!(
+            parse_key_val::<String, String>("Key=").ok(),
+            Some(("Key".into(), String::new()))
+        );
     }
 
     #[test]
-    fn template_body_reads_from_disk() {
-        assert!(template_body("tests/data/template-after.yml").is_ok())
+    fn parse_key_val_rejects_missing_keys() {
+        assert!(parse_key_val::<String, String>("=value").is_err());
+        assert!(parse_key_val::<String, String>("Key").is_err());
+    }
+
+    #[test]
+    fn json_log_line_serializes_records() {
+        let line = json_log_line(
+            "2020-01-01T00:00:00+00:00",
+            &log::Record::builder()
+                .level(log::Level::Debug)
+                .target("cliff")
+                .args(format_args!(
+                    "create changeset error for stack test: throttled"
+                ))
+                .build(),
+        );
+        assert_eq!(
+            line,
+            r#"{"level":"DEBUG","message":"create changeset error for stack test: throttled","target":"cliff","timestamp":"2020-01-01T00:00:00+00:00"}"#
+        );
+        assert_eq!("json".parse(), Ok(LogFormat::Json));
+        assert!("yaml".parse::<LogFormat>().is_err());
     }
 
     #[test]
-    fn diff_template_yields_diff() -> Result<(), Box<dyn StdError>> {
-        let diff = diff_template(
-            &PathBuf::from("tests/data/template-before.yml"),
-            include_str!("../tests/data/template-after.yml").into(),
+    fn template_stack_name_requires_metadata() -> Result<(), Box<dyn StdError>> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("template.yml");
+        fs::write(
+            &path,
+            "Metadata:\n  Cliff:\n    StackName: svc\nResources: {}\n",
         )?;
+        assert_eq!(template_stack_name(Some(&path), None)?, "svc");
         assert_eq!(
-            diff,
-            r#"5c5
-<       TableName: test
-\ No newline at end of file
----
->       TableName: test2
-\ No newline at end of file
-"#
+            template_stack_name(Some(&path), Some("Metadata: {Cliff: {StackName: env}}"))?,
+            "env"
         );
+        fs::write(&path, "Resources: {}\n")?;
+        assert!(template_stack_name(Some(&path), None)
+            .unwrap_err()
+            .to_string()
+            .contains("pass --stack-name"));
+        assert!(template_stack_name(None, None).is_err());
         Ok(())
     }
+
+    #[test]
+    fn env_template_names_template_by_format() -> Result<(), Box<dyn StdError>> {
+        env::set_var("CLIFF_TEST_YAML_TEMPLATE", "Resources: {}\n");
+        env::set_var("CLIFF_TEST_JSON_TEMPLATE", " {\"Resources\": {}}");
+        env::set_var("CLIFF_TEST_EMPTY_TEMPLATE", " \n");
+        assert_eq!(
+            env_template("CLIFF_TEST_YAML_TEMPLATE", None)?,
+            (
+                PathBuf::from("CLIFF_TEST_YAML_TEMPLATE.yml"),
+                "Resources: {}\n".to_string()
+            )
+        );
+        assert_eq!(
+            env_template("CLIFF_TEST_JSON_TEMPLATE", None)?.0,
+            PathBuf::from("CLIFF_TEST_JSON_TEMPLATE.json")
+        );
+        assert!(env_template("CLIFF_TEST_EMPTY_TEMPLATE", None)
+            .unwrap_err()
+            .to_string()
+            .contains("it is empty"));
+        assert_eq!(
+            env_template("CLIFF_TEST_JSON_TEMPLATE", Some(TemplateFormat::Yaml))?.0,
+            PathBuf::from("CLIFF_TEST_JSON_TEMPLATE.yml")
+        );
+        assert!(env_template("CLIFF_TEST_UNSET_TEMPLATE", None).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn parse_duration_parses_seconds_and_minutes() {
+        assert_eq!(parse_duration("30"), Ok(Duration::from_secs(30)));
+        assert_eq!(parse_duration("30s"), Ok(Duration::from_secs(30)));
+        assert_eq!(parse_duration("2m"), Ok(Duration::from_secs(120)));
+        assert!(parse_duration("soon").is_err());
+        assert!(parse_duration("1h").is_err());
+    }
+
+    #[test]
+    fn label_qualifies_stacks_by_account_and_region() {
+        assert_eq!(label("api", &qualifier(None, None)), "api");
+        assert_eq!(
+            label("api", &qualifier(None, Some(&Region::EuWest1))),
+            "api (eu-west-1)"
+        );
+        assert_eq!(
+            label(
+                "api",
+                &qualifier(Some("123456789012"), Some(&Region::UsEast1))
+            ),
+            "api (123456789012 us-east-1)"
+        );
+    }
+
+    #[test]
+    fn parse_client_request_token_follows_cloudformation_rules() {
+        assert_eq!(
+            parse_client_request_token("ci-1234-abc"),
+            Ok("ci-1234-abc".into())
+        );
+        assert!(parse_client_request_token("1-deploy").is_ok());
+        assert!(parse_client_request_token(&"a".repeat(128)).is_ok());
+        assert!(parse_client_request_token(&"a".repeat(129)).is_err());
+        assert!(parse_client_request_token("").is_err());
+        assert!(parse_client_request_token("-deploy").is_err());
+        assert!(parse_client_request_token("ci_1234").is_err());
+    }
+
+    #[test]
+    fn parse_alarm_arn_requires_cloudwatch_alarms() {
+        assert!(
+            parse_alarm_arn("arn:aws:cloudwatch:us-east-1:123456789012:alarm:5xx:errors").is_ok()
+        );
+        assert!(parse_alarm_arn("arn:aws:cloudwatch::123456789012:alarm:errors").is_err());
+        assert!(parse_alarm_arn("arn:aws:sns:us-east-1:123456789012:alarm:errors").is_err());
+        assert!(parse_alarm_arn("arn:aws:cloudwatch:us-east-1:123456789012:alarm:").is_err());
+        assert_eq!(parse_monitoring_minutes("180"), Ok(180));
+        assert!(parse_monitoring_minutes("181").is_err());
+        assert!(parse_monitoring_minutes("-1").is_err());
+    }
+
+    #[test]
+    fn parse_concurrency_rejects_zero() {
+        assert_eq!(parse_concurrency("8"), Ok(8));
+        assert!(parse_concurrency("0").is_err());
+        assert!(parse_concurrency("many").is_err());
+    }
+
+    #[test]
+    fn parse_json_parameters_parses_string_values() {
+        assert_eq!(
+            parse_json_parameters(r#"{"Env":"prod","Size":"2"}"#).ok(),
+            Some(vec![
+                ("Env".to_string(), "prod".to_string()),
+                ("Size".to_string(), "2".to_string())
+            ])
+        );
+    }
+
+    #[test]
+    fn env_parameters_strips_prefix() {
+        let vars = vec![
+            ("CFN_Size".to_string(), "2".to_string()),
+            ("CFN_Env".to_string(), "prod".to_string()),
+            ("CFN_".to_string(), "empty".to_string()),
+            ("CFN_Db-Host".to_string(), "db".to_string()),
+            ("HOME".to_string(), "/root".to_string()),
+        ];
+        assert_eq!(
+            env_parameters("CFN_", vars),
+            vec![
+                ("Env".to_string(), "prod".to_string()),
+                ("Size".to_string(), "2".to_string())
+            ]
+        );
+    }
+
+    #[test]
+    fn parse_json_parameters_rejects_non_string_values() {
+        assert!(parse_json_parameters(r#"{"Size":2}"#).is_err());
+        assert!(parse_json_parameters(r#"["Size"]"#).is_err());
+    }
+
+    #[test]
+    fn outcome_codes_are_stable() {
+        assert_eq!(
+            [
+                Outcome::Clean,
+                Outcome::Failed,
+                Outcome::Changed,
+                Outcome::Violated
+            ]
+            .iter()
+            .map(|outcome| outcome.code())
+            .collect::<Vec<_>>(),
+            vec![0, 1, 2, 3]
+        );
+    }
+
+    #[test]
+    fn outcome_of_changes_requires_exit_code() {
+        assert_eq!(Outcome::of_changes(true, true), Outcome::Changed);
+        assert_eq!(Outcome::of_changes(false, true), Outcome::Clean);
+        assert_eq!(Outcome::of_changes(true, false), Outcome::Clean);
+    }
+
+    #[test]
+    fn outcome_of_run_prefers_errors_then_violations_then_changes() {
+        assert_eq!(Outcome::of_run(true, true, true, true), Outcome::Failed);
+        assert_eq!(Outcome::of_run(true, false, false, false), Outcome::Failed);
+        assert_eq!(Outcome::of_run(false, true, true, true), Outcome::Violated);
+        assert_eq!(
+            Outcome::of_run(false, true, false, false),
+            Outcome::Violated
+        );
+        assert_eq!(Outcome::of_run(false, false, true, true), Outcome::Changed);
+        assert_eq!(Outcome::of_run(false, false, false, true), Outcome::Clean);
+        assert_eq!(Outcome::of_run(false, false, true, false), Outcome::Clean);
+    }
 }
@@ -0,0 +1,128 @@
+use crate::{changeset::Summary, error::Error, http, Diff};
+use futures::{future, Future, Stream};
+use hyper::{header::CONTENT_TYPE, Body, Client, Request};
+use serde::Serialize;
+
+/// the JSON payload posted to a notification url for each diffed stack
+#[derive(Debug, PartialEq, Serialize)]
+pub struct Payload<'a> {
+    pub stack_name: &'a str,
+    pub region: &'a str,
+    pub summary: &'a Summary,
+    /// logical ids of resources that require replacement
+    pub replacements: Vec<&'a str>,
+}
+
+impl<'a> Payload<'a> {
+    pub fn new(diff: &'a Diff) -> Self {
+        Payload {
+            stack_name: &diff.stack_name,
+            region: diff.region.name(),
+            summary: &diff.result.summary,
+            replacements: diff
+                .result
+                .changes
+                .iter()
+                .filter(|change| change.replacement)
+                .map(|change| change.logical_resource_id.as_str())
+                .collect(),
+        }
+    }
+}
+
+/// posts a diff's payload to a notification url
+pub fn notify(
+    url: &str,
+    payload: &Payload,
+) -> Box<dyn Future<Item = (), Error = Error> + Send> {
+    let body = match serde_json::to_string(payload) {
+        Ok(body) => body,
+        Err(err) => return Box::new(future::err(Error::Notify(err.to_string()))),
+    };
+    let request = match Request::post(url)
+        .header(CONTENT_TYPE, "application/json")
+        .body(Body::from(body))
+    {
+        Ok(request) => request,
+        Err(err) => return Box::new(future::err(Error::Notify(err.to_string()))),
+    };
+    let connector = match http::connector() {
+        Ok(connector) => connector,
+        Err(err) => return Box::new(future::err(err)),
+    };
+    Box::new(
+        Client::builder()
+            .build::<_, Body>(connector)
+            .request(request)
+            .map_err(|err| Error::Notify(err.to_string()))
+            .and_then(|response| {
+                let status = response.status();
+                response
+                    .into_body()
+                    .concat2()
+                    .map_err(|err| Error::Notify(err.to_string()))
+                    .and_then(move |body| {
+                        if status.is_success() {
+                            Ok(())
+                        } else {
+                            Err(Error::Notify(format!(
+                                "{} {}",
+                                status,
+                                String::from_utf8_lossy(&body)
+                            )))
+                        }
+                    })
+            }),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::changeset::{DiffResult, RenderedChange, Status};
+
+    #[test]
+    fn payload_serializes() -> Result<(), serde_json::Error> {
+        let diff = Diff {
+            stack_name: "test".into(),
+            region: rusoto_core::Region::UsEast1,
+            filename: Default::default(),
+            template_body: String::new(),
+            template: None,
+            changeset: Default::default(),
+            parameters: Vec::new(),
+            timings: Default::default(),
+            identity: None,
+            template_error: None,
+            notes: Vec::new(),
+            result: DiffResult {
+                status: Status::Complete,
+                changes: vec![
+                    RenderedChange {
+                        action: "Modify".into(),
+                        logical_resource_id: "Table".into(),
+                        replacement: true,
+                        ..RenderedChange::default()
+                    },
+                    RenderedChange {
+                        action: "Add".into(),
+                        logical_resource_id: "Queue".into(),
+                        ..RenderedChange::default()
+                    },
+                ],
+                summary: Summary {
+                    add: 1,
+                    modify: 1,
+                    replace: 1,
+                    ..Summary::default()
+                },
+                hidden: 0,
+            },
+        };
+        assert_eq!(
+            serde_json::to_string(&Payload::new(&diff))?,
+            r#"{"stack_name":"test","region":"us-east-1","summary":{"add":1,"modify":1,"remove":0,"import":0,"replace":1},"replacements":["Table"]}"#
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,168 @@
+use crate::redact::REDACTED;
+use serde::Serialize;
+use serde_yaml::Value;
+use std::collections::HashMap;
+
+/// a change to a stack parameter between the live stack and the submitted change set
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ParameterChange {
+    /// one of Add, Modify, or Remove
+    pub action: String,
+    pub key: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub before: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub after: Option<String>,
+}
+
+fn scalar(value: &Value) -> Option<String> {
+    match value {
+        Value::String(s) => Some(s.clone()),
+        Value::Number(n) => Some(n.to_string()),
+        Value::Bool(b) => Some(b.to_string()),
+        _ => None,
+    }
+}
+
+/// names and default values of the parameters a template declares, in order, or
+/// None when the template declares no parameters section that can be read
+fn declared(template: &Value) -> Option<Vec<(String, Option<String>)>> {
+    match template.get("Parameters") {
+        Some(Value::Mapping(parameters)) => Some(
+            parameters
+                .iter()
+                .filter_map(|(name, parameter)| {
+                    Some((scalar(name)?, parameter.get("Default").and_then(scalar)))
+                })
+                .collect(),
+        ),
+        _ => None,
+    }
+}
+
+/// compares a live stack's parameters with those submitted for a local template.
+/// parameters the template declares that the stack lacks are added with their
+/// provided or default value, and stack parameters the template no longer declares
+/// are removed. NoEcho parameters, whose current values CloudFormation masks, can't be
+/// compared and are never reported as modified
+pub fn parameter_changes(
+    current: &[(String, String)],
+    submitted: &[(String, String)],
+    provided: &[(String, String)],
+    template: &Value,
+) -> Vec<ParameterChange> {
+    let submitted = submitted.iter().cloned().collect::<HashMap<_, _>>();
+    let provided = provided.iter().cloned().collect::<HashMap<_, _>>();
+    let declared = declared(template).unwrap_or_default();
+    let mut changes = Vec::new();
+    for (key, before) in current {
+        match submitted.get(key) {
+            _ if !declared.iter().any(|(name, _)| name == key) => changes.push(ParameterChange {
+                action: "Remove".into(),
+                key: key.clone(),
+                before: Some(before.clone()),
+                after: None,
+            }),
+            _ if before == REDACTED => (),
+            Some(after) if after != before => changes.push(ParameterChange {
+                action: "Modify".into(),
+                key: key.clone(),
+                before: Some(before.clone()),
+                after: Some(after.clone()),
+            }),
+            _ => (),
+        }
+    }
+    for (key, default) in declared {
+        if current.iter().any(|(name, _)| *name == key) {
+            continue;
+        }
+        changes.push(ParameterChange {
+            action: "Add".into(),
+            after: provided.get(&key).cloned().or(default),
+            key,
+            before: None,
+        });
+    }
+    changes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::template::parse;
+    use std::error::Error as StdError;
+
+    fn pairs(pairs: &[(&str, &str)]) -> Vec<(String, String)> {
+        pairs
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn parameter_changes_reports_added_modified_and_removed() -> Result<(), Box<dyn StdError>> {
+        let template = parse(
+            r#"
+Parameters:
+  Env:
+    Type: String
+  Size:
+    Type: Number
+    Default: 2
+  Name:
+    Type: String
+"#,
+        )?;
+        assert_eq!(
+            parameter_changes(
+                &pairs(&[("Env", "prod"), ("Name", "svc"), ("Legacy", "yes")]),
+                &pairs(&[("Env", "staging"), ("Name", "svc"), ("Legacy", "yes")]),
+                &pairs(&[("Env", "staging")]),
+                &template,
+            ),
+            vec![
+                ParameterChange {
+                    action: "Modify".into(),
+                    key: "Env".into(),
+                    before: Some("prod".into()),
+                    after: Some("staging".into()),
+                },
+                ParameterChange {
+                    action: "Remove".into(),
+                    key: "Legacy".into(),
+                    before: Some("yes".into()),
+                    after: None,
+                },
+                ParameterChange {
+                    action: "Add".into(),
+                    key: "Size".into(),
+                    before: None,
+                    after: Some("2".into()),
+                },
+            ]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn parameter_changes_ignores_unchanged_parameters() -> Result<(), Box<dyn StdError>> {
+        let template = parse("Parameters:\n  Env:\n    Type: String\n")?;
+        let current = pairs(&[("Env", "prod")]);
+        assert!(parameter_changes(&current, &current, &[], &template).is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn parameter_changes_skips_masked_no_echo_values() -> Result<(), Box<dyn StdError>> {
+        let template = parse("Parameters:\n  DbPassword:\n    Type: String\n    NoEcho: true\n")?;
+        assert!(parameter_changes(
+            &pairs(&[("DbPassword", "****")]),
+            &pairs(&[("DbPassword", "s3cr3t")]),
+            &pairs(&[("DbPassword", "s3cr3t")]),
+            &template,
+        )
+        .is_empty());
+        Ok(())
+    }
+}
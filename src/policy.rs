@@ -0,0 +1,328 @@
+use crate::changeset::DiffResult;
+use serde::{Deserialize, Serialize};
+use serde_yaml::{Mapping, Value};
+
+/// properties holding IAM policy documents
+const DOCUMENTS: &[&str] = &["PolicyDocument", "AssumeRolePolicyDocument"];
+
+/// statement keys whose values may be a single string or a list of strings
+const LISTS: &[&str] = &["Action", "NotAction", "Resource", "NotResource"];
+
+/// a statement added to, removed from, or modified within an IAM policy document
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct StatementChange {
+    /// Add, Remove, or Modify
+    pub action: String,
+    /// dotted path to the policy document, e.g. `Properties.Policies[s3].PolicyDocument`
+    pub path: String,
+    /// the statement's effect, Allow or Deny
+    pub effect: String,
+    pub before: Option<String>,
+    pub after: Option<String>,
+}
+
+impl StatementChange {
+    /// true for added or modified statements that allow, the changes security review
+    /// cares most about
+    pub fn grants(&self) -> bool {
+        self.action != "Remove" && self.effect == "Allow"
+    }
+}
+
+fn inline(value: &Value) -> String {
+    serde_json::to_string(value).unwrap_or_default()
+}
+
+/// the path segment of a list item, named by its `PolicyName` when it has one so that
+/// reordering policies doesn't read as changing them
+fn segment(
+    index: usize,
+    item: &Value,
+) -> String {
+    match item.get("PolicyName") {
+        Some(Value::String(name)) => format!("[{}]", name),
+        _ => format!("[{}]", index),
+    }
+}
+
+/// collects the policy documents nested within a value, keyed by path. documents
+/// written as JSON strings are parsed
+fn documents(
+    path: String,
+    value: &Value,
+    found: &mut Vec<(String, Value)>,
+) {
+    match value {
+        Value::Mapping(mapping) => {
+            for (key, value) in mapping {
+                let key = match key {
+                    Value::String(key) => key,
+                    _ => continue,
+                };
+                let path = format!("{}.{}", path, key);
+                if DOCUMENTS.contains(&key.as_str()) {
+                    let document = match value {
+                        Value::String(body) => serde_yaml::from_str(body).unwrap_or(Value::Null),
+                        other => other.clone(),
+                    };
+                    found.push((path, document));
+                } else {
+                    documents(path, value, found);
+                }
+            }
+        }
+        Value::Sequence(items) => {
+            for (index, item) in items.iter().enumerate() {
+                documents(format!("{}{}", path, segment(index, item)), item, found);
+            }
+        }
+        _ => (),
+    }
+}
+
+/// a statement with single values lifted into sorted lists, so that equivalent
+/// statements compare equal
+fn normalize(statement: &Value) -> Value {
+    let mapping = match statement {
+        Value::Mapping(mapping) => mapping,
+        other => return other.clone(),
+    };
+    let mut normalized = Mapping::new();
+    for (key, value) in mapping {
+        let list = key.as_str().is_some_and(|key| LISTS.contains(&key));
+        let value = match value {
+            Value::String(_) if list => Value::Sequence(vec![value.clone()]),
+            Value::Sequence(items) if items.iter().all(Value::is_string) => {
+                let mut items = items.clone();
+                items.sort_by(|a, b| a.as_str().cmp(&b.as_str()));
+                items.dedup();
+                Value::Sequence(items)
+            }
+            other => other.clone(),
+        };
+        normalized.insert(key.clone(), value);
+    }
+    Value::Mapping(normalized)
+}
+
+fn statements(document: Option<&Value>) -> Vec<Value> {
+    match document.and_then(|document| document.get("Statement")) {
+        Some(Value::Sequence(statements)) => statements.iter().map(normalize).collect(),
+        Some(statement) => vec![normalize(statement)],
+        None => Vec::new(),
+    }
+}
+
+fn sid(statement: &Value) -> Option<&str> {
+    statement.get("Sid").and_then(Value::as_str)
+}
+
+fn effect(statement: &Value) -> String {
+    statement
+        .get("Effect")
+        .and_then(Value::as_str)
+        .unwrap_or_default()
+        .to_string()
+}
+
+/// compares the statements of two versions of a policy document. statements with a
+/// `Sid` are matched by it, others by value
+fn compare(
+    path: &str,
+    before: Option<&Value>,
+    after: Option<&Value>,
+    changes: &mut Vec<StatementChange>,
+) {
+    let (before, after) = (statements(before), statements(after));
+    let counterpart = |statement: &Value, others: &[Value]| {
+        sid(statement).and_then(|id| others.iter().find(|other| sid(other) == Some(id)).cloned())
+    };
+    for statement in &after {
+        let change = match counterpart(statement, &before) {
+            Some(previous) if previous != *statement => Some(("Modify", Some(previous))),
+            Some(_) => None,
+            None if !before.contains(statement) => Some(("Add", None)),
+            None => None,
+        };
+        if let Some((action, previous)) = change {
+            changes.push(StatementChange {
+                action: action.into(),
+                path: path.into(),
+                effect: effect(statement),
+                before: previous.as_ref().map(inline),
+                after: Some(inline(statement)),
+            });
+        }
+    }
+    for statement in &before {
+        if counterpart(statement, &after).is_none() && !after.contains(statement) {
+            changes.push(StatementChange {
+                action: "Remove".into(),
+                path: path.into(),
+                effect: effect(statement),
+                before: Some(inline(statement)),
+                after: None,
+            });
+        }
+    }
+}
+
+/// computes the policy statement changes of a single resource between two templates
+pub fn policy_changes(
+    before: &Value,
+    after: &Value,
+    logical_resource_id: &str,
+) -> Vec<StatementChange> {
+    let documents = |template: &Value| {
+        let mut found = Vec::new();
+        if let Some(properties) = template
+            .get("Resources")
+            .and_then(|resources| resources.get(logical_resource_id))
+            .and_then(|resource| resource.get("Properties"))
+        {
+            documents("Properties".into(), properties, &mut found);
+        }
+        found
+    };
+    let (before, after) = (documents(before), documents(after));
+    let find = |documents: &[(String, Value)], path: &str| {
+        documents
+            .iter()
+            .find(|(candidate, _)| candidate == path)
+            .map(|(_, document)| document.clone())
+    };
+    let mut changes = Vec::new();
+    for (path, document) in &after {
+        compare(
+            path,
+            find(&before, path).as_ref(),
+            Some(document),
+            &mut changes,
+        );
+    }
+    for (path, document) in &before {
+        if find(&after, path).is_none() {
+            compare(path, Some(document), None, &mut changes);
+        }
+    }
+    changes
+}
+
+/// annotates changed IAM resources with their policy statement changes
+pub fn annotate(
+    result: &mut DiffResult,
+    before: &Value,
+    after: &Value,
+) {
+    for change in result
+        .changes
+        .iter_mut()
+        .filter(|change| change.resource_type.starts_with("AWS::IAM::"))
+    {
+        change.policies = policy_changes(before, after, &change.logical_resource_id);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::template::parse;
+    use std::error::Error as StdError;
+
+    const BEFORE: &str = r#"
+Resources:
+  Role:
+    Type: AWS::IAM::Role
+    Properties:
+      AssumeRolePolicyDocument:
+        Statement:
+          - Effect: Allow
+            Principal: { Service: lambda.amazonaws.com }
+            Action: sts:AssumeRole
+      Policies:
+        - PolicyName: s3
+          PolicyDocument:
+            Statement:
+              - Sid: Read
+                Effect: Allow
+                Action: [s3:GetObject, s3:ListBucket]
+                Resource: "*"
+              - Effect: Deny
+                Action: s3:DeleteObject
+                Resource: "*"
+"#;
+
+    #[test]
+    fn policy_changes_reports_statement_deltas() -> Result<(), Box<dyn StdError>> {
+        let after = parse(
+            r#"
+Resources:
+  Role:
+    Type: AWS::IAM::Role
+    Properties:
+      AssumeRolePolicyDocument:
+        Statement:
+          Effect: Allow
+          Principal: { Service: lambda.amazonaws.com }
+          Action: [sts:AssumeRole]
+      Policies:
+        - PolicyName: sqs
+          PolicyDocument: '{"Statement": [{"Effect": "Allow", "Action": "sqs:*", "Resource": "*"}]}'
+        - PolicyName: s3
+          PolicyDocument:
+            Statement:
+              - Sid: Read
+                Effect: Allow
+                Action: [s3:ListBucket, s3:GetObject, s3:PutObject]
+                Resource: "*"
+"#,
+        )?;
+        assert_eq!(
+            policy_changes(&parse(BEFORE)?, &after, "Role"),
+            vec![
+                StatementChange {
+                    action: "Add".into(),
+                    path: "Properties.Policies[sqs].PolicyDocument".into(),
+                    effect: "Allow".into(),
+                    before: None,
+                    after: Some(r#"{"Effect":"Allow","Action":["sqs:*"],"Resource":["*"]}"#.into()),
+                },
+                StatementChange {
+                    action: "Modify".into(),
+                    path: "Properties.Policies[s3].PolicyDocument".into(),
+                    effect: "Allow".into(),
+                    before: Some(
+                        r#"{"Sid":"Read","Effect":"Allow","Action":["s3:GetObject","s3:ListBucket"],"Resource":["*"]}"#
+                            .into()
+                    ),
+                    after: Some(
+                        r#"{"Sid":"Read","Effect":"Allow","Action":["s3:GetObject","s3:ListBucket","s3:PutObject"],"Resource":["*"]}"#
+                            .into()
+                    ),
+                },
+                StatementChange {
+                    action: "Remove".into(),
+                    path: "Properties.Policies[s3].PolicyDocument".into(),
+                    effect: "Deny".into(),
+                    before: Some(r#"{"Effect":"Deny","Action":["s3:DeleteObject"],"Resource":["*"]}"#.into()),
+                    after: None,
+                },
+            ]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn grants_flags_added_allow_statements() -> Result<(), Box<dyn StdError>> {
+        let changes = policy_changes(&Value::Null, &parse(BEFORE)?, "Role");
+        assert_eq!(
+            changes
+                .iter()
+                .map(StatementChange::grants)
+                .collect::<Vec<_>>(),
+            vec![true, true, false]
+        );
+        assert!(policy_changes(&parse(BEFORE)?, &parse(BEFORE)?, "Role").is_empty());
+        Ok(())
+    }
+}
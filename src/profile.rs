@@ -0,0 +1,76 @@
+use std::{env, fs, path::PathBuf};
+
+/// location of the shared aws config file, honoring `AWS_CONFIG_FILE`
+fn config_file() -> Option<PathBuf> {
+    env::var_os("AWS_CONFIG_FILE")
+        .map(PathBuf::from)
+        .or_else(|| home().map(|home| home.join(".aws").join("config")))
+}
+
+/// the current user's home directory
+pub(crate) fn home() -> Option<PathBuf> {
+    env::var_os("HOME")
+        .or_else(|| env::var_os("USERPROFILE"))
+        .map(PathBuf::from)
+}
+
+/// reads the shared aws config file, if present
+pub(crate) fn config() -> Option<String> {
+    config_file().and_then(|path| fs::read_to_string(path).ok())
+}
+
+/// the config file section holding a profile's settings
+pub(crate) fn section(profile: &str) -> String {
+    if profile == "default" {
+        "default".to_string()
+    } else {
+        format!("profile {}", profile)
+    }
+}
+
+/// reads a setting from a section of the contents of an aws config file
+pub(crate) fn setting(
+    config: &str,
+    section: &str,
+    key: &str,
+) -> Option<String> {
+    let mut current = None;
+    for line in config.lines().map(str::trim) {
+        if line.starts_with('[') && line.ends_with(']') {
+            current = Some(line[1..line.len() - 1].trim().to_string());
+        } else if current.as_deref() == Some(section) {
+            let mut pair = line.splitn(2, '=').map(str::trim);
+            if let (Some(name), Some(value)) = (pair.next(), pair.next()) {
+                if name == key {
+                    return Some(value.to_string());
+                }
+            }
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CONFIG: &str =
+        "[default]\nregion = us-west-2\n\n[profile prod]\noutput = json\nregion=eu-west-1\n";
+
+    #[test]
+    fn setting_reads_profile_sections() {
+        assert_eq!(
+            setting(CONFIG, &section("default"), "region"),
+            Some("us-west-2".into())
+        );
+        assert_eq!(
+            setting(CONFIG, &section("prod"), "region"),
+            Some("eu-west-1".into())
+        );
+        assert_eq!(
+            setting(CONFIG, &section("prod"), "output"),
+            Some("json".into())
+        );
+        assert_eq!(setting(CONFIG, &section("missing"), "region"), None);
+    }
+}
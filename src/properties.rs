@@ -0,0 +1,161 @@
+use crate::changeset::DiffResult;
+use serde::{Deserialize, Serialize};
+use serde_yaml::Value;
+
+/// a before and after value for a single resource property path
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PropertyChange {
+    /// dotted path to the property, e.g. `Properties.Tags[0].Value`
+    pub path: String,
+    pub before: Option<String>,
+    pub after: Option<String>,
+}
+
+fn inline(value: &Value) -> String {
+    match value {
+        Value::String(s) => s.clone(),
+        other => serde_json::to_string(other).unwrap_or_default(),
+    }
+}
+
+fn compare(
+    path: String,
+    before: Option<&Value>,
+    after: Option<&Value>,
+    changes: &mut Vec<PropertyChange>,
+) {
+    match (before, after) {
+        (Some(Value::Mapping(b)), Some(Value::Mapping(a))) => {
+            for key in b.keys().chain(a.keys().filter(|key| !b.contains_key(*key))) {
+                compare(
+                    format!("{}.{}", path, inline(key)),
+                    b.get(key),
+                    a.get(key),
+                    changes,
+                );
+            }
+        }
+        (Some(Value::Sequence(b)), Some(Value::Sequence(a))) if b.len() == a.len() => {
+            for (i, (b, a)) in b.iter().zip(a).enumerate() {
+                compare(format!("{}[{}]", path, i), Some(b), Some(a), changes);
+            }
+        }
+        (b, a) if b != a => changes.push(PropertyChange {
+            path,
+            before: b.map(inline),
+            after: a.map(inline),
+        }),
+        _ => (),
+    }
+}
+
+/// computes the property value changes of a single resource between two templates
+pub fn property_changes(
+    before: &Value,
+    after: &Value,
+    logical_resource_id: &str,
+) -> Vec<PropertyChange> {
+    let properties = |template: &Value| {
+        template
+            .get("Resources")
+            .and_then(|resources| resources.get(logical_resource_id))
+            .and_then(|resource| resource.get("Properties"))
+            .cloned()
+    };
+    let mut changes = Vec::new();
+    compare(
+        "Properties".into(),
+        properties(before).as_ref(),
+        properties(after).as_ref(),
+        &mut changes,
+    );
+    changes
+}
+
+/// annotates modified resources with their property value changes
+pub fn annotate(
+    result: &mut DiffResult,
+    before: &Value,
+    after: &Value,
+) {
+    for change in result
+        .changes
+        .iter_mut()
+        .filter(|change| change.action == "Modify")
+    {
+        change.properties = property_changes(before, after, &change.logical_resource_id);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::template::parse;
+    use std::error::Error as StdError;
+
+    #[test]
+    fn property_changes_reports_value_deltas() -> Result<(), Box<dyn StdError>> {
+        let before = parse(
+            r#"
+Resources:
+  Table:
+    Type: AWS::DynamoDB::Table
+    Properties:
+      TableName: test
+      BillingMode: PAY_PER_REQUEST
+      Tags:
+        - Key: team
+          Value: a
+"#,
+        )?;
+        let after = parse(
+            r#"
+Resources:
+  Table:
+    Type: AWS::DynamoDB::Table
+    Properties:
+      TableName: !Ref Name
+      Tags:
+        - Key: team
+          Value: b
+      StreamSpecification:
+        StreamViewType: NEW_IMAGE
+"#,
+        )?;
+        assert_eq!(
+            property_changes(&before, &after, "Table"),
+            vec![
+                PropertyChange {
+                    path: "Properties.TableName".into(),
+                    before: Some("test".into()),
+                    after: Some(r#"{"!Ref":"Name"}"#.into()),
+                },
+                PropertyChange {
+                    path: "Properties.BillingMode".into(),
+                    before: Some("PAY_PER_REQUEST".into()),
+                    after: None,
+                },
+                PropertyChange {
+                    path: "Properties.Tags[0].Value".into(),
+                    before: Some("a".into()),
+                    after: Some("b".into()),
+                },
+                PropertyChange {
+                    path: "Properties.StreamSpecification".into(),
+                    before: None,
+                    after: Some(r#"{"StreamViewType":"NEW_IMAGE"}"#.into()),
+                },
+            ]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn property_changes_ignores_unchanged_resources() -> Result<(), Box<dyn StdError>> {
+        let before = parse(include_str!("../tests/data/template-before.yml"))?;
+        let after = parse(include_str!("../tests/data/template-after.yml"))?;
+        assert!(property_changes(&before, &before, "DynamodbTable").is_empty());
+        assert_eq!(property_changes(&before, &after, "DynamodbTable").len(), 1);
+        Ok(())
+    }
+}
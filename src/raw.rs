@@ -0,0 +1,198 @@
+use crate::redact::REDACTED;
+use rusoto_cloudformation::{
+    Change, DescribeChangeSetOutput, Parameter, ResourceChange, ResourceChangeDetail,
+    RollbackConfiguration, Tag,
+};
+use serde_json::{Map, Value};
+
+/// an object of the provided fields, omitting those without a value
+fn object(fields: Vec<(&str, Option<Value>)>) -> Value {
+    Value::Object(
+        fields
+            .into_iter()
+            .filter_map(|(key, value)| value.map(|value| (key.to_string(), value)))
+            .collect::<Map<_, _>>(),
+    )
+}
+
+fn string(value: &Option<String>) -> Option<Value> {
+    value.clone().map(Value::from)
+}
+
+fn strings(values: &Option<Vec<String>>) -> Option<Value> {
+    values.clone().map(Value::from)
+}
+
+fn list<T>(
+    values: &Option<Vec<T>>,
+    f: fn(&T) -> Value,
+) -> Option<Value> {
+    values
+        .as_ref()
+        .map(|values| Value::Array(values.iter().map(f).collect()))
+}
+
+fn parameter(parameter: &Parameter) -> Value {
+    let redacted = |value: &Option<String>| value.as_ref().map(|_| Value::from(REDACTED));
+    object(vec![
+        ("ParameterKey", string(&parameter.parameter_key)),
+        ("ParameterValue", redacted(&parameter.parameter_value)),
+        ("ResolvedValue", redacted(&parameter.resolved_value)),
+        (
+            "UsePreviousValue",
+            parameter.use_previous_value.map(Value::from),
+        ),
+    ])
+}
+
+fn detail(detail: &ResourceChangeDetail) -> Value {
+    object(vec![
+        ("CausingEntity", string(&detail.causing_entity)),
+        ("ChangeSource", string(&detail.change_source)),
+        ("Evaluation", string(&detail.evaluation)),
+        (
+            "Target",
+            detail.target.as_ref().map(|target| {
+                object(vec![
+                    ("Attribute", string(&target.attribute)),
+                    ("Name", string(&target.name)),
+                    ("RequiresRecreation", string(&target.requires_recreation)),
+                ])
+            }),
+        ),
+    ])
+}
+
+fn resource_change(change: &ResourceChange) -> Value {
+    object(vec![
+        ("Action", string(&change.action)),
+        ("Details", list(&change.details, detail)),
+        ("LogicalResourceId", string(&change.logical_resource_id)),
+        ("PhysicalResourceId", string(&change.physical_resource_id)),
+        ("Replacement", string(&change.replacement)),
+        ("ResourceType", string(&change.resource_type)),
+        ("Scope", strings(&change.scope)),
+    ])
+}
+
+fn change(change: &Change) -> Value {
+    object(vec![
+        (
+            "ResourceChange",
+            change.resource_change.as_ref().map(resource_change),
+        ),
+        ("Type", string(&change.type_)),
+    ])
+}
+
+fn rollback_configuration(config: &RollbackConfiguration) -> Value {
+    object(vec![
+        (
+            "MonitoringTimeInMinutes",
+            config.monitoring_time_in_minutes.map(Value::from),
+        ),
+        (
+            "RollbackTriggers",
+            config.rollback_triggers.as_ref().map(|triggers| {
+                Value::Array(
+                    triggers
+                        .iter()
+                        .map(|trigger| {
+                            object(vec![
+                                ("Arn", Some(trigger.arn.clone().into())),
+                                ("Type", Some(trigger.type_.clone().into())),
+                            ])
+                        })
+                        .collect(),
+                )
+            }),
+        ),
+    ])
+}
+
+fn tag(tag: &Tag) -> Value {
+    object(vec![
+        ("Key", Some(tag.key.clone().into())),
+        ("Value", Some(tag.value.clone().into())),
+    ])
+}
+
+/// the full DescribeChangeSet response, with parameter values redacted, for tooling
+/// that needs fields cliff doesn't otherwise render. rusoto doesn't implement
+/// `Serialize` for CloudFormation responses, so fields are mapped to the API's names
+pub fn changeset(output: &DescribeChangeSetOutput) -> Value {
+    object(vec![
+        ("Capabilities", strings(&output.capabilities)),
+        ("ChangeSetId", string(&output.change_set_id)),
+        ("ChangeSetName", string(&output.change_set_name)),
+        ("Changes", list(&output.changes, change)),
+        ("CreationTime", string(&output.creation_time)),
+        ("Description", string(&output.description)),
+        ("ExecutionStatus", string(&output.execution_status)),
+        ("NextToken", string(&output.next_token)),
+        ("NotificationARNs", strings(&output.notification_ar_ns)),
+        ("Parameters", list(&output.parameters, parameter)),
+        (
+            "RollbackConfiguration",
+            output
+                .rollback_configuration
+                .as_ref()
+                .map(rollback_configuration),
+        ),
+        ("StackId", string(&output.stack_id)),
+        ("StackName", string(&output.stack_name)),
+        ("Status", string(&output.status)),
+        ("StatusReason", string(&output.status_reason)),
+        ("Tags", list(&output.tags, tag)),
+    ])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rusoto_cloudformation::ResourceTargetDefinition;
+
+    #[test]
+    fn changeset_includes_fields_cliff_drops() {
+        let output = DescribeChangeSetOutput {
+            execution_status: Some("AVAILABLE".into()),
+            creation_time: Some("2019-10-01T00:00:00Z".into()),
+            notification_ar_ns: Some(vec!["arn:aws:sns:us-east-1:123:topic".into()]),
+            changes: Some(vec![Change {
+                type_: Some("Resource".into()),
+                resource_change: Some(ResourceChange {
+                    action: Some("Modify".into()),
+                    details: Some(vec![ResourceChangeDetail {
+                        target: Some(ResourceTargetDefinition {
+                            name: Some("TableName".into()),
+                            ..ResourceTargetDefinition::default()
+                        }),
+                        ..ResourceChangeDetail::default()
+                    }]),
+                    ..ResourceChange::default()
+                }),
+            }]),
+            ..DescribeChangeSetOutput::default()
+        };
+        assert_eq!(
+            changeset(&output).to_string(),
+            r#"{"Changes":[{"ResourceChange":{"Action":"Modify","Details":[{"Target":{"Name":"TableName"}}]},"Type":"Resource"}],"CreationTime":"2019-10-01T00:00:00Z","ExecutionStatus":"AVAILABLE","NotificationARNs":["arn:aws:sns:us-east-1:123:topic"]}"#
+        );
+    }
+
+    #[test]
+    fn changeset_redacts_parameter_values() {
+        let output = DescribeChangeSetOutput {
+            parameters: Some(vec![Parameter {
+                parameter_key: Some("DbPassword".into()),
+                parameter_value: Some("hunter2".into()),
+                ..Parameter::default()
+            }]),
+            ..DescribeChangeSetOutput::default()
+        };
+        assert_eq!(
+            changeset(&output).to_string(),
+            r#"{"Parameters":[{"ParameterKey":"DbPassword","ParameterValue":"****"}]}"#
+        );
+    }
+}
@@ -0,0 +1,300 @@
+use crate::{parameters::ParameterChange, template::parse, Diff};
+use serde_yaml::Value;
+use std::collections::HashSet;
+
+/// replaces redacted values, matching how CloudFormation masks NoEcho parameters
+pub const REDACTED: &str = "****";
+
+fn truthy(value: &Value) -> bool {
+    match value {
+        Value::Bool(b) => *b,
+        Value::String(s) => s.eq_ignore_ascii_case("true"),
+        _ => false,
+    }
+}
+
+fn scalar(value: &Value) -> Option<String> {
+    match value {
+        Value::String(s) => Some(s.clone()),
+        Value::Number(n) => Some(n.to_string()),
+        Value::Bool(b) => Some(b.to_string()),
+        _ => None,
+    }
+}
+
+/// names and default values of a template's NoEcho parameters, or None when the
+/// template can't be parsed
+pub fn no_echo_parameters(template_body: &str) -> Option<Vec<(String, Option<String>)>> {
+    let template = parse(template_body).ok()?;
+    let parameters = match template.get("Parameters") {
+        Some(Value::Mapping(parameters)) => parameters,
+        _ => return Some(Vec::new()),
+    };
+    Some(
+        parameters
+            .iter()
+            .filter(|(_, parameter)| parameter.get("NoEcho").is_some_and(truthy))
+            .filter_map(|(name, parameter)| {
+                Some((scalar(name)?, parameter.get("Default").and_then(scalar)))
+            })
+            .collect(),
+    )
+}
+
+/// redacts the values of NoEcho parameters wherever a diff prints them
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct Redactor {
+    /// names of parameters to redact, or None to redact every parameter
+    keys: Option<HashSet<String>>,
+    /// secret values, longest first
+    secrets: Vec<String>,
+}
+
+impl Redactor {
+    /// collects the NoEcho parameters of each template along with their default and
+    /// provided values. when any template can't be parsed, every parameter is redacted
+    pub fn new(
+        templates: &[&str],
+        parameters: &[(String, String)],
+    ) -> Self {
+        let mut keys = Some(HashSet::new());
+        let mut secrets = Vec::new();
+        for template in templates {
+            match (no_echo_parameters(template), keys.as_mut()) {
+                (Some(no_echo), Some(keys)) => {
+                    for (name, default) in no_echo {
+                        secrets.extend(default);
+                        keys.insert(name);
+                    }
+                }
+                (None, _) => keys = None,
+                _ => (),
+            }
+        }
+        secrets.extend(
+            parameters
+                .iter()
+                .filter(|(key, _)| keys.as_ref().is_none_or(|keys| keys.contains(key)))
+                .map(|(_, value)| value.clone()),
+        );
+        Redactor { keys, secrets }.sorted()
+    }
+
+    /// also redacts the named parameters, like those resolved from SSM SecureStrings,
+    /// whether or not a template marks them NoEcho
+    pub fn sensitive(
+        mut self,
+        sensitive: &[String],
+        parameters: &[(String, String)],
+    ) -> Self {
+        if let Some(keys) = self.keys.as_mut() {
+            keys.extend(sensitive.iter().cloned());
+        }
+        self.secrets.extend(
+            parameters
+                .iter()
+                .filter(|(key, _)| sensitive.contains(key))
+                .map(|(_, value)| value.clone()),
+        );
+        self.sorted()
+    }
+
+    /// orders secrets longest first, so that secrets containing others are replaced whole
+    fn sorted(mut self) -> Self {
+        self.secrets
+            .retain(|secret| !secret.is_empty() && secret != REDACTED);
+        self.secrets
+            .sort_by(|a, b| b.len().cmp(&a.len()).then_with(|| a.cmp(b)));
+        self.secrets.dedup();
+        self
+    }
+
+    /// true when the named parameter's value should be redacted
+    pub fn redacts(
+        &self,
+        key: &str,
+    ) -> bool {
+        self.keys.as_ref().is_none_or(|keys| keys.contains(key))
+    }
+
+    /// replaces any secret values in text
+    pub fn redact(
+        &self,
+        text: &str,
+    ) -> String {
+        self.secrets.iter().fold(text.to_string(), |text, secret| {
+            text.replace(secret, REDACTED)
+        })
+    }
+
+    /// redacts secrets from a diff's template diff, property values, parameter changes,
+    /// and change set parameters
+    pub fn apply(
+        &self,
+        diff: &mut Diff,
+    ) {
+        diff.template = diff.template.as_ref().map(|template| self.redact(template));
+        diff.template_body = self.redact(&diff.template_body);
+        for change in &mut diff.result.changes {
+            for property in &mut change.properties {
+                property.before = property.before.as_ref().map(|value| self.redact(value));
+                property.after = property.after.as_ref().map(|value| self.redact(value));
+            }
+        }
+        self.apply_parameters(&mut diff.parameters);
+        for parameter in diff.changeset.parameters.iter_mut().flatten() {
+            if self.redacts(parameter.parameter_key.as_deref().unwrap_or_default()) {
+                for value in vec![
+                    &mut parameter.parameter_value,
+                    &mut parameter.resolved_value,
+                ]
+                .into_iter()
+                .flatten()
+                {
+                    *value = REDACTED.into();
+                }
+            }
+        }
+    }
+
+    /// redacts the values of parameter changes, wholly for redacted parameters
+    pub fn apply_parameters(
+        &self,
+        changes: &mut [ParameterChange],
+    ) {
+        for change in changes {
+            let redacts = self.redacts(&change.key);
+            for value in vec![&mut change.before, &mut change.after]
+                .into_iter()
+                .flatten()
+            {
+                *value = if redacts {
+                    REDACTED.into()
+                } else {
+                    self.redact(value)
+                };
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        changeset::{DiffResult, Status},
+        parameters::ParameterChange,
+    };
+
+    const TEMPLATE: &str = r#"
+Parameters:
+  DbPassword:
+    Type: String
+    NoEcho: true
+    Default: hunter22
+  Env:
+    Type: String
+    Default: prod
+"#;
+
+    #[test]
+    fn no_echo_parameters_lists_no_echo_parameters() {
+        assert_eq!(
+            no_echo_parameters(TEMPLATE),
+            Some(vec![("DbPassword".into(), Some("hunter22".into()))])
+        );
+        assert_eq!(no_echo_parameters("Resources: {}"), Some(Vec::new()));
+        assert_eq!(no_echo_parameters("{ not: [valid"), None);
+    }
+
+    #[test]
+    fn redactor_redacts_no_echo_values() {
+        let redactor = Redactor::new(
+            &[TEMPLATE],
+            &[
+                ("DbPassword".into(), "s3cr3t-value".into()),
+                ("Env".into(), "staging".into()),
+            ],
+        );
+        assert!(redactor.redacts("DbPassword"));
+        assert!(!redactor.redacts("Env"));
+        assert_eq!(
+            redactor.redact("password s3cr3t-value default hunter22 env staging"),
+            "password **** default **** env staging"
+        );
+    }
+
+    #[test]
+    fn sensitive_redacts_named_parameters() {
+        let parameters = vec![
+            ("ApiKey".to_string(), "k3y-from-ssm".to_string()),
+            ("Env".to_string(), "staging".to_string()),
+        ];
+        let redactor =
+            Redactor::new(&[TEMPLATE], &parameters).sensitive(&["ApiKey".into()], &parameters);
+        assert!(redactor.redacts("ApiKey"));
+        assert!(!redactor.redacts("Env"));
+        assert_eq!(
+            redactor.redact("key k3y-from-ssm env staging"),
+            "key **** env staging"
+        );
+    }
+
+    #[test]
+    fn apply_redacts_parameter_changes() {
+        let mut diff = Diff {
+            stack_name: "test".into(),
+            region: rusoto_core::Region::UsEast1,
+            filename: Default::default(),
+            template_body: String::new(),
+            template: None,
+            changeset: Default::default(),
+            result: DiffResult {
+                status: Status::Complete,
+                changes: Vec::new(),
+                summary: Default::default(),
+                hidden: 0,
+            },
+            parameters: vec![
+                ParameterChange {
+                    action: "Modify".into(),
+                    key: "DbPassword".into(),
+                    before: Some("old".into()),
+                    after: Some("s3cr3t-value".into()),
+                },
+                ParameterChange {
+                    action: "Modify".into(),
+                    key: "Env".into(),
+                    before: Some("prod".into()),
+                    after: Some("staging".into()),
+                },
+            ],
+            timings: Default::default(),
+            identity: None,
+            template_error: None,
+            notes: Vec::new(),
+        };
+        Redactor::new(&[TEMPLATE], &[("DbPassword".into(), "s3cr3t-value".into())])
+            .apply(&mut diff);
+        assert_eq!(
+            diff.parameters
+                .iter()
+                .map(|change| (change.before.as_deref(), change.after.as_deref()))
+                .collect::<Vec<_>>(),
+            vec![
+                (Some(REDACTED), Some(REDACTED)),
+                (Some("prod"), Some("staging"))
+            ]
+        );
+    }
+
+    #[test]
+    fn redactor_redacts_everything_for_unparsable_templates() {
+        let redactor = Redactor::new(
+            &[TEMPLATE, "{ not: [valid"],
+            &[("Env".into(), "staging".into())],
+        );
+        assert!(redactor.redacts("Env"));
+        assert_eq!(redactor.redact("env staging"), "env ****");
+    }
+}
@@ -0,0 +1,47 @@
+use crate::{error::Error, profile};
+use rusoto_core::Region;
+use std::{env, str::FromStr};
+
+fn select(
+    explicit: Option<&str>,
+    profile_region: Option<String>,
+) -> Result<Region, Error> {
+    match explicit.map(String::from).or(profile_region) {
+        Some(name) => Region::from_str(&name)
+            .map_err(|err| Error::Config(format!("invalid region `{}`: {}", name, err))),
+        _ => Ok(Region::default()),
+    }
+}
+
+/// resolves a region, preferring an explicit region, then the region configured for
+/// the profile in ~/.aws/config, then `AWS_DEFAULT_REGION` or `AWS_REGION`, then us-east-1
+///
+/// the profile defaults to `AWS_PROFILE`, or `default` when unset
+pub fn resolve(
+    explicit: Option<&str>,
+    profile: Option<&str>,
+) -> Result<Region, Error> {
+    let profile = profile
+        .map(String::from)
+        .or_else(|| env::var("AWS_PROFILE").ok())
+        .unwrap_or_else(|| "default".into());
+    let configured = profile::config()
+        .and_then(|config| profile::setting(&config, &profile::section(&profile), "region"));
+    select(explicit, configured)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn select_prefers_explicit_then_profile_region() {
+        assert_eq!(
+            select(Some("ap-southeast-2"), Some("eu-west-1".into())),
+            Ok(Region::ApSoutheast2)
+        );
+        assert_eq!(select(None, Some("eu-west-1".into())), Ok(Region::EuWest1));
+        assert_eq!(select(None, None), Ok(Region::default()));
+        assert!(select(Some("nowhere-1"), None).is_err());
+    }
+}
@@ -0,0 +1,1566 @@
+use crate::{
+    accounts::CallerIdentity,
+    baseline::Delta,
+    changeset::{by_type, DiffResult, RenderedChange, Status, Summary, TypeSummary},
+    facts,
+    parameters::ParameterChange,
+    policy::StatementChange,
+    properties::PropertyChange,
+    raw,
+    redact::REDACTED,
+    sarif, Diff,
+};
+use colored::Colorize;
+use rusoto_cloudformation::ValidateTemplateOutput;
+use serde::Serialize;
+use std::{str::FromStr, time::Duration};
+
+/// markers rendered alongside changes
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum Markers {
+    #[default]
+    Emoji,
+    /// plain ASCII for terminals and log viewers that mangle emoji
+    Ascii,
+}
+
+impl Markers {
+    /// marker prefixing a change with the provided action
+    pub fn action(
+        self,
+        action: &str,
+    ) -> Option<&'static str> {
+        match (self, action) {
+            (Markers::Emoji, "Modify") => Some("🔧 "),
+            (Markers::Emoji, "Remove") => Some("✂️  "),
+            (Markers::Emoji, "Add") => Some("🌱 "),
+            (Markers::Emoji, "Import") => Some("📥 "),
+            (Markers::Ascii, "Modify") => Some("[~] "),
+            (Markers::Ascii, "Remove") => Some("[-] "),
+            (Markers::Ascii, "Add") => Some("[+] "),
+            (Markers::Ascii, "Import") => Some("[>] "),
+            _ => None,
+        }
+    }
+
+    /// marker suffixing a change that requires replacement
+    pub fn replacement(self) -> &'static str {
+        match self {
+            Markers::Emoji => " ⚠️  Requires replacement",
+            Markers::Ascii => " !! replacement",
+        }
+    }
+
+    /// marker prefixing a failure reason
+    pub fn warning(self) -> &'static str {
+        match self {
+            Markers::Emoji => "⚠️ ",
+            Markers::Ascii => "!! ",
+        }
+    }
+
+    /// arrow between a value before and after a change
+    pub fn arrow(self) -> &'static str {
+        match self {
+            Markers::Emoji => "→",
+            Markers::Ascii => "->",
+        }
+    }
+}
+
+/// markers overriding those of `Markers`, whichever of emoji or ASCII is in use. parsed
+/// from comma-separated `key=marker` pairs, as in `add=+,remove=-,replacement=(replaced)`
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct MarkerOverrides {
+    add: Option<String>,
+    modify: Option<String>,
+    remove: Option<String>,
+    import: Option<String>,
+    replacement: Option<String>,
+}
+
+impl MarkerOverrides {
+    fn action(
+        &self,
+        action: &str,
+    ) -> Option<&str> {
+        match action {
+            "Add" => self.add.as_deref(),
+            "Modify" => self.modify.as_deref(),
+            "Remove" => self.remove.as_deref(),
+            "Import" => self.import.as_deref(),
+            _ => None,
+        }
+    }
+
+    fn replacement(&self) -> Option<&str> {
+        self.replacement.as_deref()
+    }
+}
+
+/// true for a single user-perceived character: one character, optionally followed by
+/// variation selectors, skin tone modifiers, combining marks, or zero-width joined
+/// characters, as in emoji sequences
+fn single_grapheme(s: &str) -> bool {
+    let mut chars = s.chars();
+    match chars.next() {
+        Some(c) if !c.is_whitespace() && !c.is_control() => (),
+        _ => return false,
+    }
+    let mut joined = false;
+    for c in chars {
+        match c {
+            '\u{200d}' if !joined => joined = true,
+            '\u{fe00}'..='\u{fe0f}'
+            | '\u{1f3fb}'..='\u{1f3ff}'
+            | '\u{0300}'..='\u{036f}'
+            | '\u{20d0}'..='\u{20ff}' => (),
+            _ if joined => joined = false,
+            _ => return false,
+        }
+    }
+    !joined
+}
+
+impl FromStr for MarkerOverrides {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut overrides = MarkerOverrides::default();
+        for pair in s.split(',').map(str::trim).filter(|pair| !pair.is_empty()) {
+            let (key, marker) = match pair.split_once('=') {
+                Some((key, marker)) if !marker.trim().is_empty() => (key.trim(), marker.trim()),
+                _ => {
+                    return Err(format!(
+                        "invalid marker `{}`: expected key=marker, as in add=+",
+                        pair
+                    ))
+                }
+            };
+            if key != "replacement" && !single_grapheme(marker) {
+                return Err(format!(
+                    "invalid {} marker `{}`: action markers must be a single character so that changes line up",
+                    key, marker
+                ));
+            }
+            let action = Some(format!("{} ", marker));
+            match key {
+                "add" => overrides.add = action,
+                "modify" => overrides.modify = action,
+                "remove" => overrides.remove = action,
+                "import" => overrides.import = action,
+                "replacement" => overrides.replacement = Some(format!(" {}", marker)),
+                other => {
+                    return Err(format!(
+                        "invalid marker `{}`. expected one of add, modify, remove, import, or replacement",
+                        other
+                    ))
+                }
+            }
+        }
+        Ok(overrides)
+    }
+}
+
+/// how diffs are rendered
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct RenderOptions {
+    /// markers alongside changes in rendered text, independent of color
+    pub markers: Markers,
+    /// markers overriding some of `markers`
+    pub overrides: MarkerOverrides,
+    /// limits the changes rendered as text to the first N, or 0 for all of them.
+    /// summary counts still reflect every change
+    pub max_changes: usize,
+    /// breaks changes down by resource type
+    pub by_type: bool,
+}
+
+impl RenderOptions {
+    /// marker prefixing a change with the provided action
+    fn action(
+        &self,
+        action: &str,
+    ) -> Option<&str> {
+        self.overrides
+            .action(action)
+            .or_else(|| self.markers.action(action))
+    }
+
+    /// marker suffixing a change that requires replacement
+    fn replacement(&self) -> &str {
+        self.overrides
+            .replacement()
+            .unwrap_or_else(|| self.markers.replacement())
+    }
+
+    fn warning(&self) -> &'static str {
+        self.markers.warning()
+    }
+
+    fn arrow(&self) -> &'static str {
+        self.markers.arrow()
+    }
+}
+
+/// counts of a diff's changes by resource type, when enabled
+fn types(
+    result: &DiffResult,
+    options: &RenderOptions,
+) -> Option<Vec<TypeSummary>> {
+    Some(by_type(&result.changes)).filter(|_| options.by_type)
+}
+
+/// a phrase tallying the changes of a summary, as in `3 modified, 1 replaced`
+fn tally(summary: &Summary) -> String {
+    [
+        (summary.add, "added"),
+        (summary.modify, "modified"),
+        (summary.remove, "removed"),
+        (summary.import, "imported"),
+        (summary.replace, "replaced"),
+    ]
+    .iter()
+    .filter(|(count, _)| *count > 0)
+    .map(|(count, verb)| format!("{} {}", count, verb))
+    .collect::<Vec<_>>()
+    .join(", ")
+}
+
+/// renders a section of change counts per resource type, or nothing when there are no
+/// changes
+pub fn render_by_type(result: &DiffResult) -> String {
+    let types = by_type(&result.changes);
+    if types.is_empty() {
+        return String::new();
+    }
+    let mut lines = format!("{}\n", "By resource type".bold());
+    for TypeSummary {
+        resource_type,
+        summary,
+    } in &types
+    {
+        lines.push_str(&format!("{}: {}\n", resource_type.bold(), tally(summary)));
+    }
+    lines.push('\n');
+    lines
+}
+
+/// the breakdown by resource type when enabled
+fn breakdown(
+    result: &DiffResult,
+    options: &RenderOptions,
+) -> String {
+    if options.by_type {
+        render_by_type(result)
+    } else {
+        String::new()
+    }
+}
+
+/// renders a section of how a stack's changes differ from its baseline, or notes that
+/// the baseline didn't include the stack
+pub fn render_deltas(deltas: Option<&[Delta]>) -> String {
+    let mut lines = format!("{}\n", "Since baseline".bold());
+    match deltas {
+        None => lines.push_str("stack not comparable: it's not in the baseline, or its change set there didn't complete\n"),
+        Some([]) => lines.push_str("no differences\n"),
+        Some(deltas) => {
+            for delta in deltas {
+                let line = delta.to_string();
+                lines.push_str(&format!(
+                    "{}\n",
+                    match delta {
+                        Delta::New(_) => line.bright_green(),
+                        Delta::Resolved(_) => line.dimmed(),
+                        Delta::Replacement(_) => line.bright_red(),
+                        Delta::Changed { .. } => line.bright_yellow(),
+                    }
+                ));
+            }
+        }
+    }
+    lines.push('\n');
+    lines
+}
+
+/// the first `max` changes, and how many more were left out
+fn limit(
+    changes: &[RenderedChange],
+    max: usize,
+) -> (&[RenderedChange], usize) {
+    match max {
+        0 => (changes, 0),
+        max => {
+            let shown = &changes[..changes.len().min(max)];
+            (shown, changes.len() - shown.len())
+        }
+    }
+}
+
+/// notes how many changes `--max-changes` left out, if any
+fn more(omitted: usize) -> String {
+    match omitted {
+        0 => String::new(),
+        omitted => format!(
+            "... and {} more (use --max-changes 0 to show all)\n",
+            omitted
+        ),
+    }
+}
+
+/// supported output formats
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum Format {
+    /// colorized, human-friendly text
+    #[default]
+    Text,
+    /// a single line JSON object per stack
+    Json,
+    /// the full DescribeChangeSet response per stack, with parameter values redacted
+    RawJson,
+    /// a single line SARIF log of every stack's risky changes, for code scanning tools
+    Sarif,
+    /// uncolored markdown tables per stack, for pull request comments and job summaries
+    Markdown,
+    /// a single line JSON object of every stack's policy-relevant facts, for policy engines
+    Facts,
+}
+
+impl Format {
+    /// true for formats rendered as one document per run rather than one per stack
+    pub fn per_run(self) -> bool {
+        matches!(self, Format::Sarif | Format::Facts)
+    }
+}
+
+impl FromStr for Format {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "text" => Ok(Format::Text),
+            "json" => Ok(Format::Json),
+            "raw-json" => Ok(Format::RawJson),
+            "sarif" => Ok(Format::Sarif),
+            "markdown" => Ok(Format::Markdown),
+            "facts" => Ok(Format::Facts),
+            other => Err(format!(
+                "invalid output format `{}`. expected one of text, json, raw-json, sarif, markdown, or facts",
+                other
+            )),
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct Report<'a> {
+    stack_name: &'a str,
+    region: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    template: Option<&'a str>,
+    #[serde(skip_serializing_if = "<[_]>::is_empty")]
+    parameters: &'a [ParameterChange],
+    /// when the change set was created
+    #[serde(skip_serializing_if = "Option::is_none")]
+    created: Option<&'a str>,
+    /// the account and caller the diff was made as, when credentials were checked
+    #[serde(skip_serializing_if = "Option::is_none")]
+    identity: Option<&'a CallerIdentity>,
+    #[serde(flatten)]
+    result: &'a DiffResult,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    by_type: Option<Vec<TypeSummary>>,
+}
+
+#[derive(Serialize)]
+struct SummaryReport<'a> {
+    stack_name: &'a str,
+    region: &'a str,
+    #[serde(flatten)]
+    summary: &'a Summary,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    by_type: Option<Vec<TypeSummary>>,
+}
+
+/// renders a diff in the provided format
+pub fn render(
+    format: Format,
+    diff: &Diff,
+    options: &RenderOptions,
+) -> String {
+    match format {
+        Format::Text => {
+            let template = diff
+                .template
+                .as_ref()
+                .map(|template| format!("{}\n", colorize_template(template)))
+                .unwrap_or_default();
+            format!(
+                "{}{}{}{}",
+                template,
+                render_parameters(&diff.parameters, options),
+                breakdown(&diff.result, options),
+                text(&diff.result, options)
+            )
+        }
+        Format::Json => json(&Report {
+            stack_name: &diff.stack_name,
+            region: diff.region.name(),
+            template: diff.template.as_deref(),
+            parameters: &diff.parameters,
+            created: diff.changeset.creation_time.as_deref(),
+            identity: diff.identity.as_ref(),
+            result: &diff.result,
+            by_type: types(&diff.result, options),
+        }),
+        Format::RawJson => json(&raw::changeset(&diff.changeset)),
+        Format::Sarif => json(&sarif::log(std::slice::from_ref(diff))),
+        Format::Markdown => markdown(diff, options),
+        Format::Facts => json(&facts::report(std::slice::from_ref(diff))),
+    }
+}
+
+/// renders every diff of a run in the provided format. formats describing a whole run,
+/// like SARIF, render a single document of every diff rather than one per stack
+pub fn render_all(
+    format: Format,
+    diffs: &[Diff],
+    options: &RenderOptions,
+) -> String {
+    match format {
+        Format::Sarif => json(&sarif::log(diffs)),
+        Format::Facts => json(&facts::report(diffs)),
+        _ => diffs
+            .iter()
+            .map(|diff| render(format, diff, options))
+            .collect(),
+    }
+}
+
+/// renders a diff as markdown without colors or emoji, so that it reads the same
+/// wherever it's posted
+fn markdown(
+    diff: &Diff,
+    options: &RenderOptions,
+) -> String {
+    let mut lines = format!("### {} ({})\n\n", diff.stack_name, diff.region.name());
+    if let Some(template) = diff.template.as_deref().filter(|t| !t.trim().is_empty()) {
+        let fence = fence(template);
+        lines.push_str(&format!(
+            "<details><summary>Template diff</summary>\n\n{}diff\n{}\n{}\n\n</details>\n\n",
+            fence,
+            template.trim_end(),
+            fence
+        ));
+    }
+    lines.push_str(&markdown_parameters(&diff.parameters));
+    let result = &diff.result;
+    match &result.status {
+        Status::Complete => {
+            if options.by_type && !result.changes.is_empty() {
+                lines.push_str("| Type | Changes |\n| --- | --- |\n");
+                for TypeSummary {
+                    resource_type,
+                    summary,
+                } in by_type(&result.changes)
+                {
+                    lines.push_str(&format!(
+                        "| {} | {} |\n",
+                        cell(&resource_type),
+                        tally(&summary)
+                    ));
+                }
+                lines.push('\n');
+            }
+            if !result.changes.is_empty() {
+                lines.push_str(
+                    "| Action | Logical ID | Type | Replacement |\n| --- | --- | --- | --- |\n",
+                );
+                for change in &result.changes {
+                    lines.push_str(&format!(
+                        "| {} | {} | {} | {} |\n",
+                        cell(&change.action),
+                        cell(&change.logical_resource_id),
+                        cell(&change.resource_type),
+                        if change.replacement { "yes" } else { "" }
+                    ));
+                }
+                lines.push('\n');
+            }
+            lines.push_str(none(result));
+            let summary = &result.summary;
+            lines.push_str(&format!("{}\n", counts(summary)));
+            lines.push_str(&shown(result));
+        }
+        Status::Failed(reason) => lines.push_str(&format!("> {}\n", reason)),
+        Status::Other(status) => {
+            lines.push_str(&format!("change set resulted in status of {}\n", status))
+        }
+    }
+    lines.push('\n');
+    lines
+}
+
+/// a markdown table of parameter changes, or nothing when no parameters changed
+fn markdown_parameters(parameters: &[ParameterChange]) -> String {
+    if parameters.is_empty() {
+        return String::new();
+    }
+    let mut lines =
+        "| Action | Parameter | Before | After |\n| --- | --- | --- | --- |\n".to_string();
+    for parameter in parameters {
+        lines.push_str(&format!(
+            "| {} | {} | {} | {} |\n",
+            cell(&parameter.action),
+            cell(&parameter.key),
+            cell(parameter.before.as_deref().unwrap_or_default()),
+            cell(parameter.after.as_deref().unwrap_or_default())
+        ));
+    }
+    lines.push('\n');
+    lines
+}
+
+/// escapes text for a markdown table cell, in which a pipe would end the cell and a
+/// line break the row
+fn cell(text: &str) -> String {
+    text.replace('|', "\\|")
+        .replace("\r\n", "<br>")
+        .replace('\n', "<br>")
+}
+
+/// a code fence longer than any run of backticks in `text`, so that it can't be closed
+/// early by the text it fences
+fn fence(text: &str) -> String {
+    let longest = text
+        .split(|c| c != '`')
+        .map(str::len)
+        .max()
+        .unwrap_or_default();
+    "`".repeat(longest.max(2) + 1)
+}
+
+#[derive(Serialize)]
+struct ParametersReport<'a> {
+    stack_name: &'a str,
+    region: &'a str,
+    parameters: &'a [ParameterChange],
+}
+
+/// renders only the parameter changes of a stack, as with `--parameters-only`. raw
+/// JSON and SARIF, which describe change sets, render as JSON
+pub fn render_parameters_only(
+    format: Format,
+    stack_name: &str,
+    region: &str,
+    parameters: &[ParameterChange],
+    options: &RenderOptions,
+) -> String {
+    let none = if parameters.is_empty() {
+        "No parameter changes\n"
+    } else {
+        ""
+    };
+    match format {
+        Format::Text => format!("{}{}", render_parameters(parameters, options), none),
+        Format::Markdown if parameters.is_empty() => {
+            format!("### {} ({})\n\n{}\n", stack_name, region, none)
+        }
+        Format::Markdown => format!(
+            "### {} ({})\n\n{}",
+            stack_name,
+            region,
+            markdown_parameters(parameters)
+        ),
+        Format::Json | Format::RawJson | Format::Sarif | Format::Facts => json(&ParametersReport {
+            stack_name,
+            region,
+            parameters,
+        }),
+    }
+}
+
+/// renders a line of `key=value` pairs naming the account, caller, and region cliff
+/// operates as, so that logs record where diffs were made
+pub fn render_identity(
+    identity: &CallerIdentity,
+    region: Option<&str>,
+) -> String {
+    let region = region
+        .map(|region| format!(" region={}", region))
+        .unwrap_or_default();
+    format!(
+        "identity account={} arn={}{}\n",
+        identity.account, identity.arn, region
+    )
+}
+
+/// `key=value` counts of a summary's changes, followed by their highest severity
+fn counts(summary: &Summary) -> String {
+    let severity = summary
+        .severity
+        .map(|severity| format!(" severity={}", severity))
+        .unwrap_or_default();
+    format!(
+        "add={} modify={} remove={} import={} replace={}{}",
+        summary.add, summary.modify, summary.remove, summary.import, summary.replace, severity
+    )
+}
+
+/// renders only the summary counts of a diff in the provided format
+pub fn render_summary(
+    format: Format,
+    diff: &Diff,
+    options: &RenderOptions,
+) -> String {
+    let summary = &diff.result.summary;
+    match format {
+        Format::Text => format!("{} {}\n", diff.stack_name, counts(summary)),
+        Format::Markdown => format!("**{}** {}\n", diff.stack_name, counts(summary)),
+        // documents with a stable shape of their own are rendered whole
+        Format::Sarif | Format::Facts => render(format, diff, options),
+        Format::Json | Format::RawJson => json(&SummaryReport {
+            stack_name: &diff.stack_name,
+            region: diff.region.name(),
+            summary,
+            by_type: types(&diff.result, options),
+        }),
+    }
+}
+
+/// renders each change of a diff on a single tight line, without physical ids, scope,
+/// or the template diff, followed by the counts of changes
+pub fn render_compact(
+    diff: &Diff,
+    options: &RenderOptions,
+) -> String {
+    format!(
+        "{}{}",
+        breakdown(&diff.result, options),
+        compact(&diff.result, options)
+    )
+}
+
+fn compact(
+    result: &DiffResult,
+    options: &RenderOptions,
+) -> String {
+    match &result.status {
+        Status::Complete => {
+            let summary = &result.summary;
+            let (changes, omitted) = limit(&result.changes, options.max_changes);
+            let mut lines = changes
+                .iter()
+                .map(|change| format!("{}\n", render_compact_change(change, options)))
+                .collect::<String>();
+            lines.push_str(&more(omitted));
+            lines.push_str(none(result));
+            lines.push_str(&format!("{}\n", counts(summary)));
+            lines.push_str(&shown(result));
+            lines
+        }
+        _ => text(result, options),
+    }
+}
+
+/// renders a single change as a tight line of its type, logical id, and replacement
+pub fn render_compact_change(
+    c: &RenderedChange,
+    options: &RenderOptions,
+) -> String {
+    let line = format!(
+        "{} {}{}",
+        c.resource_type,
+        c.logical_resource_id.bold(),
+        if c.replacement { " (replace)" } else { "" }
+    );
+    let marker = match options.action(&c.action) {
+        Some(marker) => marker.to_string(),
+        _ => format!("{} ", c.action),
+    };
+    format!("{}{}", marker, paint(&c.action, &line))
+}
+
+/// renders a single `key=value` line summarizing a diff for scripts, independent of format
+pub fn render_script_summary(diff: &Diff) -> String {
+    let summary = &diff.result.summary;
+    format!(
+        "cliff: stack={} region={} {}\n",
+        diff.stack_name,
+        diff.region.name(),
+        counts(summary)
+    )
+}
+
+fn seconds(duration: Duration) -> String {
+    format!("{:.2}s", duration.as_secs_f64())
+}
+
+/// renders a table of how long each phase of each diff took
+pub fn render_timings(
+    credentials: Option<Duration>,
+    diffs: &[Diff],
+) -> String {
+    let mut table = credentials
+        .map(|elapsed| format!("credentials {}\n", seconds(elapsed)))
+        .unwrap_or_default();
+    table.push_str(&format!(
+        "{:<32} {:>12} {:>12} {:>12} {:>6} {:>12}\n",
+        "stack", "get_template", "create", "describe", "polls", "delete"
+    ));
+    for diff in diffs {
+        let t = &diff.timings;
+        table.push_str(&format!(
+            "{:<32} {:>12} {:>12} {:>12} {:>6} {:>12}\n",
+            diff.stack_name,
+            seconds(t.get_template),
+            seconds(t.create_change_set),
+            seconds(t.describe_change_set),
+            t.describe_polls,
+            seconds(t.delete_change_set)
+        ));
+    }
+    table
+}
+
+/// renders the parameters and capabilities of a validated template
+pub fn render_validation(output: &ValidateTemplateOutput) -> String {
+    let mut text = format!("{}\n", "template is valid".green());
+    if let Some(description) = &output.description {
+        text.push_str(&format!("{}\n", description.trim()));
+    }
+    for param in output.parameters.iter().flatten() {
+        text.push_str(&format!(
+            "  parameter {}{}\n",
+            param.parameter_key.as_deref().unwrap_or_default().bold(),
+            param
+                .default_value
+                .as_ref()
+                .map(|value| format!(
+                    " (default {})",
+                    if param.no_echo == Some(true) {
+                        REDACTED
+                    } else {
+                        value
+                    }
+                ))
+                .unwrap_or_default()
+        ));
+    }
+    if let Some(capabilities) = output.capabilities.as_ref().filter(|c| !c.is_empty()) {
+        text.push_str(&format!(
+            "  requires {}{}\n",
+            capabilities.join(", "),
+            output
+                .capabilities_reason
+                .as_ref()
+                .map(|reason| format!(": {}", reason))
+                .unwrap_or_default()
+        ));
+    }
+    text
+}
+
+fn json<T: Serialize>(value: &T) -> String {
+    format!("{}\n", serde_json::to_string(value).unwrap_or_default())
+}
+
+/// kinds of lines in a unified diff
+#[derive(Debug, PartialEq)]
+enum DiffLine {
+    Header,
+    Hunk,
+    Added,
+    Removed,
+    Context,
+}
+
+impl<'a> From<&'a str> for DiffLine {
+    fn from(line: &'a str) -> Self {
+        if line.starts_with("+++") || line.starts_with("---") {
+            DiffLine::Header
+        } else if line.starts_with("@@") {
+            DiffLine::Hunk
+        } else if line.starts_with('+') {
+            DiffLine::Added
+        } else if line.starts_with('-') {
+            DiffLine::Removed
+        } else {
+            DiffLine::Context
+        }
+    }
+}
+
+/// colors added and removed lines of a textual diff, passing through diffs
+/// that are already colored by an external differ
+fn colorize_template(template: &str) -> String {
+    if template.contains('\x1b') {
+        return template.to_string();
+    }
+    template
+        .split('\n')
+        .map(|line| match DiffLine::from(line) {
+            DiffLine::Header => line.bold().to_string(),
+            DiffLine::Hunk => line.cyan().to_string(),
+            DiffLine::Added => line.bright_green().to_string(),
+            DiffLine::Removed => line.bright_red().to_string(),
+            DiffLine::Context => line.to_string(),
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// a line stating that there are no changes at all, unambiguous to scripts and people,
+/// or nothing when there are changes, shown or not
+fn none(result: &DiffResult) -> &'static str {
+    if result.changes.is_empty() && result.hidden == 0 {
+        "No changes detected\n"
+    } else {
+        ""
+    }
+}
+
+/// notes how many changes a resource filter left out, if any
+fn shown(result: &DiffResult) -> String {
+    if result.hidden == 0 {
+        return String::new();
+    }
+    let shown = result.changes.len();
+    format!("{} of {} changes shown\n", shown, shown + result.hidden)
+}
+
+fn text(
+    result: &DiffResult,
+    options: &RenderOptions,
+) -> String {
+    match &result.status {
+        Status::Complete => {
+            let (changes, omitted) = limit(&result.changes, options.max_changes);
+            let mut lines = changes
+                .iter()
+                .map(|change| {
+                    let mut lines = format!("{}\n", render_change(change, options));
+                    for property in &change.properties {
+                        lines.push_str(&format!("    {}\n", render_property(property, options)));
+                    }
+                    for statement in &change.policies {
+                        lines.push_str(&format!("    {}\n", render_statement(statement, options)));
+                    }
+                    lines
+                })
+                .collect::<String>();
+            lines.push_str(&more(omitted));
+            lines.push_str(none(result));
+            lines.push_str(&shown(result));
+            lines
+        }
+        Status::Failed(reason) => format!("{}{}\n", options.warning(), reason),
+        Status::Other(status) => format!("change set resulted in status of {}\n", status),
+    }
+}
+
+/// renders a section of parameter changes, or nothing when no parameters changed
+pub fn render_parameters(
+    parameters: &[ParameterChange],
+    options: &RenderOptions,
+) -> String {
+    if parameters.is_empty() {
+        return String::new();
+    }
+    let mut lines = format!("{}\n", "Parameters".bold());
+    for parameter in parameters {
+        let values = match (&parameter.before, &parameter.after) {
+            (None, None) => String::new(),
+            (before, after) => {
+                let none = || "<none>".to_string();
+                format!(
+                    ": {} {} {}",
+                    before.clone().unwrap_or_else(none).bright_red(),
+                    options.arrow(),
+                    after.clone().unwrap_or_else(none).bright_green()
+                )
+            }
+        };
+        let line = format!("{} {}", parameter.action.bold(), parameter.key.bold());
+        let marker = options.action(&parameter.action).unwrap_or_default();
+        let line = match parameter.action.as_str() {
+            "Modify" => line.bright_yellow().to_string(),
+            "Remove" => line.bright_red().to_string(),
+            "Add" => line.bright_green().to_string(),
+            _ => line,
+        };
+        lines.push_str(&format!("{}{}{}\n", marker, line, values));
+    }
+    lines.push('\n');
+    lines
+}
+
+/// renders a single property value change as one line of text
+pub fn render_property(
+    property: &PropertyChange,
+    options: &RenderOptions,
+) -> String {
+    let none = || "<none>".to_string();
+    format!(
+        "{}: {} {} {}",
+        property.path.dimmed(),
+        property.before.clone().unwrap_or_else(none).bright_red(),
+        options.arrow(),
+        property.after.clone().unwrap_or_else(none).bright_green()
+    )
+}
+
+/// renders a single policy statement change as one line of text, flagging statements
+/// that allow
+pub fn render_statement(
+    statement: &StatementChange,
+    options: &RenderOptions,
+) -> String {
+    let none = || "<none>".to_string();
+    let line = match statement.action.as_str() {
+        "Add" => format!("+ {}", statement.after.clone().unwrap_or_else(none)).bright_green(),
+        "Remove" => format!("- {}", statement.before.clone().unwrap_or_else(none)).bright_red(),
+        _ => format!(
+            "~ {} {} {}",
+            statement.before.clone().unwrap_or_else(none),
+            options.arrow(),
+            statement.after.clone().unwrap_or_else(none)
+        )
+        .bright_yellow(),
+    };
+    if statement.grants() {
+        format!(
+            "{}{}: {}",
+            options.warning(),
+            statement.path.dimmed(),
+            line.bold()
+        )
+    } else {
+        format!("{}: {}", statement.path.dimmed(), line)
+    }
+}
+
+/// renders a single change as one line of text
+pub fn render_change(
+    c: &RenderedChange,
+    options: &RenderOptions,
+) -> String {
+    let line = format!(
+        "{} {} {} {} {} {}",
+        c.action.bold(),
+        c.resource_type.dimmed(),
+        c.logical_resource_id.bold(),
+        c.physical_resource_id.dimmed(),
+        c.scope.join(", ").bold(),
+        if c.replacement {
+            options.replacement()
+        } else {
+            ""
+        },
+    );
+    let marker = options.action(&c.action).unwrap_or_default();
+    format!("{}{}", marker, paint(&c.action, &line))
+}
+
+/// colors a line of text by the action of the change it describes
+fn paint(
+    action: &str,
+    line: &str,
+) -> String {
+    match action {
+        "Modify" => line.bright_yellow().to_string(),
+        "Remove" => line.bright_red().to_string(),
+        "Add" => line.bright_green().to_string(),
+        "Import" => line.bright_blue().to_string(),
+        _ => line.to_string(),
+    }
+}
+
+/// actions in the order they're explained by the legend, with their meanings
+const LEGEND: &[(&str, &str)] = &[
+    ("Add", "the resource will be created"),
+    ("Modify", "the resource will be updated"),
+    ("Remove", "the resource will be deleted"),
+    (
+        "Import",
+        "an existing resource will be brought into the stack",
+    ),
+];
+
+fn legend(options: &RenderOptions) -> String {
+    let mut lines = format!("{}\n", "Legend".bold());
+    for (action, meaning) in LEGEND {
+        lines.push_str(&format!(
+            "{}{} {}\n",
+            options.action(action).unwrap_or_default(),
+            paint(action, &format!("{:<6}", action)).bold(),
+            meaning
+        ));
+    }
+    lines.push_str(&format!(
+        "{} the resource will be recreated with a new physical id, losing any data it stores\n",
+        options.replacement().trim_start()
+    ));
+    lines.push_str(&format!(
+        "{}a change set failure, or a policy statement that allows\n\n",
+        options.warning()
+    ));
+    lines
+}
+
+/// renders an explanation of the markers and colors of rendered changes
+pub fn render_legend(options: &RenderOptions) -> String {
+    legend(options)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::changeset::Severity;
+    use rusoto_cloudformation::Change;
+
+    fn ascii() -> RenderOptions {
+        RenderOptions {
+            markers: Markers::Ascii,
+            ..RenderOptions::default()
+        }
+    }
+
+    fn limited(max_changes: usize) -> RenderOptions {
+        RenderOptions {
+            max_changes,
+            ..RenderOptions::default()
+        }
+    }
+
+    fn change(
+        action: &str,
+        replacement: bool,
+    ) -> RenderedChange {
+        colored::control::set_override(false);
+        RenderedChange {
+            action: action.into(),
+            resource_type: "AWS::DynamoDB::Table".into(),
+            logical_resource_id: "Table".into(),
+            physical_resource_id: "table-123".into(),
+            scope: vec!["Properties".into(), "Tags".into()],
+            replacement,
+            properties: Vec::new(),
+            policies: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn render_validation_lists_parameters_and_capabilities() {
+        colored::control::set_override(false);
+        let output = ValidateTemplateOutput {
+            description: Some("a stack".into()),
+            parameters: Some(vec![rusoto_cloudformation::TemplateParameter {
+                parameter_key: Some("Env".into()),
+                default_value: Some("prod".into()),
+                ..Default::default()
+            }]),
+            capabilities: Some(vec!["CAPABILITY_IAM".into()]),
+            capabilities_reason: Some("creates roles".into()),
+            ..ValidateTemplateOutput::default()
+        };
+        assert_eq!(
+            render_validation(&output),
+            "template is valid\na stack\n  parameter Env (default prod)\n  requires CAPABILITY_IAM: creates roles\n"
+        );
+    }
+
+    #[test]
+    fn legend_explains_markers() {
+        colored::control::set_override(false);
+        assert_eq!(
+            legend(&ascii()),
+            "Legend\n[+] Add    the resource will be created\n[~] Modify the resource will be updated\n[-] Remove the resource will be deleted\n[>] Import an existing resource will be brought into the stack\n!! replacement the resource will be recreated with a new physical id, losing any data it stores\n!! a change set failure, or a policy statement that allows\n\n"
+        );
+        assert!(legend(&RenderOptions::default()).contains("🌱 Add"));
+    }
+
+    #[test]
+    fn marker_overrides_parse_single_characters() -> Result<(), String> {
+        let overrides = "add=+, remove=🗑️,replacement=(replaced)".parse::<MarkerOverrides>()?;
+        assert_eq!(overrides.action("Add"), Some("+ "));
+        assert_eq!(overrides.action("Remove"), Some("🗑️ "));
+        assert_eq!(overrides.action("Modify"), None);
+        assert_eq!(overrides.replacement(), Some(" (replaced)"));
+        assert!("modify=👩‍💻".parse::<MarkerOverrides>().is_ok());
+        assert!("add=[+]".parse::<MarkerOverrides>().is_err());
+        assert!("add=".parse::<MarkerOverrides>().is_err());
+        assert!("delete=-".parse::<MarkerOverrides>().is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn render_change_applies_marker_overrides() -> Result<(), String> {
+        let options = RenderOptions {
+            overrides: "add=+,replacement=(replaced)".parse()?,
+            ..ascii()
+        };
+        assert_eq!(
+            render_change(&change("Add", true), &options),
+            "+ Add AWS::DynamoDB::Table Table table-123 Properties, Tags  (replaced)"
+        );
+        assert!(render_change(&change("Remove", false), &options).starts_with("[-] "));
+        Ok(())
+    }
+
+    #[test]
+    fn render_by_type_tallies_changes_per_type() {
+        colored::control::set_override(false);
+        let mut result = diff().result;
+        result.changes.push(RenderedChange {
+            action: "Modify".into(),
+            resource_type: "AWS::Lambda::Function".into(),
+            ..RenderedChange::default()
+        });
+        assert_eq!(
+            render_by_type(&result),
+            "By resource type\nAWS::DynamoDB::Table: 1 added, 1 replaced\nAWS::Lambda::Function: 1 modified\n\n"
+        );
+        result.changes.clear();
+        assert_eq!(render_by_type(&result), "");
+    }
+
+    #[test]
+    fn render_deltas_lists_differences() {
+        colored::control::set_override(false);
+        assert_eq!(
+            render_deltas(Some(&[Delta::Resolved(change("Remove", false))])),
+            "Since baseline\nresolved remove of Table (AWS::DynamoDB::Table)\n\n"
+        );
+        assert_eq!(
+            render_deltas(Some(&[])),
+            "Since baseline\nno differences\n\n"
+        );
+        assert_eq!(
+            render_deltas(None),
+            "Since baseline\nstack not comparable: it's not in the baseline, or its change set there didn't complete\n\n"
+        );
+    }
+
+    #[test]
+    fn render_parameters_only_renders_parameter_changes() {
+        colored::control::set_override(false);
+        let parameters = [ParameterChange {
+            action: "Modify".into(),
+            key: "Size".into(),
+            before: Some("1".into()),
+            after: Some("2".into()),
+        }];
+        assert_eq!(
+            render_parameters_only(Format::Json, "test", "us-east-1", &parameters, &RenderOptions::default()),
+            "{\"stack_name\":\"test\",\"region\":\"us-east-1\",\"parameters\":[{\"action\":\"Modify\",\"key\":\"Size\",\"before\":\"1\",\"after\":\"2\"}]}\n"
+        );
+        assert_eq!(
+            render_parameters_only(Format::Markdown, "test", "us-east-1", &parameters, &RenderOptions::default()),
+            "### test (us-east-1)\n\n| Action | Parameter | Before | After |\n| --- | --- | --- | --- |\n| Modify | Size | 1 | 2 |\n\n"
+        );
+        assert_eq!(
+            render_parameters_only(
+                Format::Text,
+                "test",
+                "us-east-1",
+                &[],
+                &RenderOptions::default()
+            ),
+            "No parameter changes\n"
+        );
+    }
+
+    #[test]
+    fn ascii_markers_replace_emoji() {
+        let markers = Markers::Ascii;
+        assert_eq!(markers.action("Modify"), Some("[~] "));
+        assert_eq!(markers.action("Remove"), Some("[-] "));
+        assert_eq!(markers.action("Add"), Some("[+] "));
+        assert_eq!(markers.action("Import"), Some("[>] "));
+        assert_eq!(markers.action("Dynamic"), None);
+        assert_eq!(markers.replacement(), " !! replacement");
+        assert!(markers.warning().is_ascii());
+    }
+
+    #[test]
+    fn render_change_renders_modify() {
+        assert_eq!(
+            render_change(&change("Modify", false), &RenderOptions::default()),
+            "🔧 Modify AWS::DynamoDB::Table Table table-123 Properties, Tags "
+        );
+        assert_eq!(
+            render_change(&change("Modify", true), &RenderOptions::default()),
+            "🔧 Modify AWS::DynamoDB::Table Table table-123 Properties, Tags  ⚠️  Requires replacement"
+        );
+    }
+
+    #[test]
+    fn render_change_renders_remove() {
+        assert_eq!(
+            render_change(&change("Remove", false), &RenderOptions::default()),
+            "✂️  Remove AWS::DynamoDB::Table Table table-123 Properties, Tags "
+        );
+        assert_eq!(
+            render_change(&change("Remove", true), &RenderOptions::default()),
+            "✂️  Remove AWS::DynamoDB::Table Table table-123 Properties, Tags  ⚠️  Requires replacement"
+        );
+    }
+
+    #[test]
+    fn render_change_renders_add() {
+        assert_eq!(
+            render_change(&change("Add", false), &RenderOptions::default()),
+            "🌱 Add AWS::DynamoDB::Table Table table-123 Properties, Tags "
+        );
+        assert_eq!(
+            render_change(&change("Add", true), &RenderOptions::default()),
+            "🌱 Add AWS::DynamoDB::Table Table table-123 Properties, Tags  ⚠️  Requires replacement"
+        );
+    }
+
+    #[test]
+    fn render_change_renders_import() {
+        assert_eq!(
+            render_change(&change("Import", false), &RenderOptions::default()),
+            "📥 Import AWS::DynamoDB::Table Table table-123 Properties, Tags "
+        );
+    }
+
+    #[test]
+    fn render_change_renders_unknown_actions_unstyled() {
+        assert_eq!(
+            render_change(&change("Dynamic", false), &RenderOptions::default()),
+            "Dynamic AWS::DynamoDB::Table Table table-123 Properties, Tags "
+        );
+    }
+
+    #[test]
+    fn render_property_renders_before_and_after() {
+        colored::control::set_override(false);
+        assert_eq!(
+            render_property(
+                &PropertyChange {
+                    path: "Properties.TableName".into(),
+                    before: Some("test".into()),
+                    after: None,
+                },
+                &RenderOptions::default()
+            ),
+            "Properties.TableName: test → <none>"
+        );
+    }
+
+    #[test]
+    fn render_statement_flags_granted_permissions() {
+        colored::control::set_override(false);
+        let statement = |action: &str, effect: &str| StatementChange {
+            action: action.into(),
+            path: "Properties.PolicyDocument".into(),
+            effect: effect.into(),
+            before: Some(r#"{"Effect":"Deny"}"#.into()),
+            after: Some(format!(r#"{{"Effect":"{}"}}"#, effect)),
+        };
+        assert_eq!(
+            render_statement(&statement("Add", "Allow"), &RenderOptions::default()),
+            r#"⚠️ Properties.PolicyDocument: + {"Effect":"Allow"}"#
+        );
+        assert_eq!(
+            render_statement(&statement("Modify", "Deny"), &RenderOptions::default()),
+            r#"Properties.PolicyDocument: ~ {"Effect":"Deny"} → {"Effect":"Deny"}"#
+        );
+        assert_eq!(
+            render_statement(&statement("Remove", "Deny"), &RenderOptions::default()),
+            r#"Properties.PolicyDocument: - {"Effect":"Deny"}"#
+        );
+    }
+
+    #[test]
+    fn render_parameters_renders_changes() {
+        colored::control::set_override(false);
+        assert_eq!(render_parameters(&[], &RenderOptions::default()), "");
+        assert_eq!(
+            render_parameters(
+                &[
+                    ParameterChange {
+                        action: "Modify".into(),
+                        key: "Env".into(),
+                        before: Some("prod".into()),
+                        after: Some("staging".into()),
+                    },
+                    ParameterChange {
+                        action: "Remove".into(),
+                        key: "Legacy".into(),
+                        before: None,
+                        after: None,
+                    },
+                ],
+                &RenderOptions::default()
+            ),
+            "Parameters\n🔧 Modify Env: prod → staging\n✂️  Remove Legacy\n\n"
+        );
+        let ascii = RenderOptions {
+            markers: Markers::Ascii,
+            ..RenderOptions::default()
+        };
+        assert_eq!(
+            render_parameters(
+                &[ParameterChange {
+                    action: "Modify".into(),
+                    key: "Env".into(),
+                    before: Some("prod".into()),
+                    after: Some("staging".into()),
+                }],
+                &ascii
+            ),
+            "Parameters\n[~] Modify Env: prod -> staging\n\n"
+        );
+    }
+
+    fn diff() -> Diff {
+        Diff {
+            stack_name: "test".into(),
+            region: rusoto_core::Region::UsEast1,
+            filename: Default::default(),
+            template_body: String::new(),
+            template: None,
+            changeset: Default::default(),
+            parameters: Vec::new(),
+            timings: Default::default(),
+            identity: None,
+            template_error: None,
+            notes: Vec::new(),
+            result: DiffResult {
+                status: Status::Complete,
+                changes: vec![change("Add", true)],
+                summary: Summary {
+                    add: 1,
+                    replace: 1,
+                    ..Summary::default()
+                },
+                hidden: 0,
+            },
+        }
+    }
+
+    #[test]
+    fn diff_line_classifies_unified_diff_lines() {
+        assert_eq!(DiffLine::from("--- a/test"), DiffLine::Header);
+        assert_eq!(DiffLine::from("+++ b/template.yml"), DiffLine::Header);
+        assert_eq!(DiffLine::from("@@ -2,4 +2,4 @@"), DiffLine::Hunk);
+        assert_eq!(DiffLine::from("+      TableName: test2"), DiffLine::Added);
+        assert_eq!(DiffLine::from("-      TableName: test"), DiffLine::Removed);
+        assert_eq!(DiffLine::from("   Properties:"), DiffLine::Context);
+    }
+
+    #[test]
+    fn colorize_template_passes_through_colored_diffs() {
+        let colored = "\x1b[31m-      TableName: test\x1b[0m";
+        assert_eq!(colorize_template(colored), colored);
+    }
+
+    #[test]
+    fn render_identity_records_account_and_region() {
+        let identity = CallerIdentity {
+            account: "123456789012".into(),
+            arn: "arn:aws:sts::123456789012:assumed-role/deploy/ci".into(),
+        };
+        assert_eq!(
+            render_identity(&identity, Some("us-east-1")),
+            "identity account=123456789012 arn=arn:aws:sts::123456789012:assumed-role/deploy/ci region=us-east-1\n"
+        );
+        let mut diff = diff();
+        assert!(!render(Format::Json, &diff, &RenderOptions::default()).contains("identity"));
+        diff.identity = Some(identity);
+        assert!(render(Format::Json, &diff, &RenderOptions::default()).contains(
+            r#""identity":{"account":"123456789012","arn":"arn:aws:sts::123456789012:assumed-role/deploy/ci"}"#
+        ));
+    }
+
+    #[test]
+    fn render_markdown_tabulates_changes() {
+        let mut diff = diff();
+        diff.template = Some("-a\n+b\n".into());
+        assert_eq!(
+            render(Format::Markdown, &diff, &RenderOptions::default()),
+            "### test (us-east-1)\n\n<details><summary>Template diff</summary>\n\n```diff\n-a\n+b\n```\n\n</details>\n\n| Action | Logical ID | Type | Replacement |\n| --- | --- | --- | --- |\n| Add | Table | AWS::DynamoDB::Table | yes |\n\nadd=1 modify=0 remove=0 import=0 replace=1\n\n"
+        );
+    }
+
+    #[test]
+    fn render_markdown_escapes_cells_and_fences() {
+        assert_eq!(cell("a|b\nc\r\nd"), "a\\|b<br>c<br>d");
+        assert_eq!(fence("-a\n+b"), "```");
+        assert_eq!(fence("+ Description: ```code````"), "`````");
+        assert_eq!(
+            markdown_parameters(&[ParameterChange {
+                action: "Modify".into(),
+                key: "Pattern".into(),
+                before: Some("a|b".into()),
+                after: Some("line\nbreak".into()),
+            }]),
+            "| Action | Parameter | Before | After |\n| --- | --- | --- | --- |\n| Modify | Pattern | a\\|b | line<br>break |\n\n"
+        );
+    }
+
+    #[test]
+    fn render_notes_hidden_changes() {
+        let mut diff = diff();
+        diff.result.hidden = 2;
+        assert!(
+            render_compact(&diff, &RenderOptions::default()).ends_with("1 of 3 changes shown\n")
+        );
+        assert!(text(&diff.result, &RenderOptions::default()).ends_with("1 of 3 changes shown\n"));
+    }
+
+    #[test]
+    fn render_states_when_there_are_no_changes() {
+        let mut diff = diff();
+        diff.result.changes.clear();
+        diff.result.summary = Summary::default();
+        assert_eq!(
+            text(&diff.result, &RenderOptions::default()),
+            "No changes detected\n"
+        );
+        assert_eq!(
+            compact(&diff.result, &RenderOptions::default()),
+            "No changes detected\nadd=0 modify=0 remove=0 import=0 replace=0\n"
+        );
+        assert!(render(Format::Json, &diff, &RenderOptions::default())
+            .contains("\"changes\":[],\"summary\":{"));
+        diff.result.hidden = 2;
+        assert!(!text(&diff.result, &RenderOptions::default()).contains("No changes"));
+    }
+
+    #[test]
+    fn max_changes_truncates_rendered_changes() {
+        let mut result = diff().result;
+        result.changes = vec![
+            change("Add", false),
+            change("Modify", false),
+            change("Remove", false),
+        ];
+        let text = text(&result, &limited(2));
+        assert_eq!(text.lines().count(), 3);
+        assert!(text.ends_with("... and 1 more (use --max-changes 0 to show all)\n"));
+        assert!(compact(&result, &limited(1)).contains("... and 2 more"));
+        assert!(
+            compact(&result, &limited(1)).ends_with("add=1 modify=0 remove=0 import=0 replace=1\n")
+        );
+        assert!(!compact(&result, &limited(3)).contains("more"));
+    }
+
+    #[test]
+    fn render_summary_renders_counts() {
+        assert_eq!(
+            render_summary(Format::Text, &diff(), &RenderOptions::default()),
+            "test add=1 modify=0 remove=0 import=0 replace=1\n"
+        );
+        assert_eq!(
+            render_summary(Format::Json, &diff(), &RenderOptions::default()),
+            "{\"stack_name\":\"test\",\"region\":\"us-east-1\",\"add\":1,\"modify\":0,\"remove\":0,\"import\":0,\"replace\":1}\n"
+        );
+    }
+
+    #[test]
+    fn render_timings_renders_table() {
+        let mut diff = diff();
+        diff.timings.describe_change_set = Duration::from_millis(1500);
+        diff.timings.describe_polls = 3;
+        assert_eq!(
+            render_timings(Some(Duration::from_millis(120)), &[diff]),
+            format!(
+                "credentials 0.12s\n{:<32} {:>12} {:>12} {:>12} {:>6} {:>12}\n{:<32} {:>12} {:>12} {:>12} {:>6} {:>12}\n",
+                "stack", "get_template", "create", "describe", "polls", "delete",
+                "test", "0.00s", "0.00s", "1.50s", 3, "0.00s"
+            )
+        );
+    }
+
+    #[test]
+    fn render_renders_json() {
+        assert_eq!(
+            render(Format::Json, &diff(), &RenderOptions::default()),
+            "{\"stack_name\":\"test\",\"region\":\"us-east-1\",\"status\":\"complete\",\"changes\":[{\"action\":\"Add\",\"resource_type\":\"AWS::DynamoDB::Table\",\"logical_resource_id\":\"Table\",\"physical_resource_id\":\"table-123\",\"scope\":[\"Properties\",\"Tags\"],\"replacement\":true,\"properties\":[]}],\"summary\":{\"add\":1,\"modify\":0,\"remove\":0,\"import\":0,\"replace\":1}}\n"
+        );
+    }
+
+    #[test]
+    fn render_compact_renders_tight_lines_and_counts() {
+        assert_eq!(
+            render_compact(&diff(), &RenderOptions::default()),
+            "🌱 AWS::DynamoDB::Table Table (replace)\nadd=1 modify=0 remove=0 import=0 replace=1\n"
+        );
+    }
+
+    #[test]
+    fn render_compact_change_names_unmarked_actions() {
+        colored::control::set_override(false);
+        assert_eq!(
+            render_compact_change(&change("Dynamic", false), &RenderOptions::default()),
+            "Dynamic AWS::DynamoDB::Table Table"
+        );
+    }
+
+    #[test]
+    fn render_script_summary_renders_key_values() {
+        assert_eq!(
+            render_script_summary(&diff()),
+            "cliff: stack=test region=us-east-1 add=1 modify=0 remove=0 import=0 replace=1\n"
+        );
+        let mut diff = diff();
+        diff.result.summary.severity = Some(Severity::High);
+        assert_eq!(
+            render_summary(Format::Text, &diff, &RenderOptions::default()),
+            "test add=1 modify=0 remove=0 import=0 replace=1 severity=high\n"
+        );
+        assert!(
+            render_summary(Format::Json, &diff, &RenderOptions::default())
+                .contains("\"severity\":\"high\"")
+        );
+    }
+
+    #[test]
+    fn render_all_renders_one_document_per_run() -> Result<(), serde_json::Error> {
+        let diffs = [diff(), diff()];
+        let options = RenderOptions::default();
+        let facts: serde_json::Value =
+            serde_json::from_str(&render_all(Format::Facts, &diffs, &options))?;
+        assert_eq!(facts["stacks"].as_array().map(Vec::len), Some(2));
+        assert_eq!(
+            render_summary(Format::Facts, &diffs[0], &options)
+                .lines()
+                .count(),
+            1
+        );
+        assert!(render_summary(Format::Facts, &diffs[0], &options).contains("\"stacks\""));
+        assert_eq!(
+            render_all(Format::Json, &diffs, &options).lines().count(),
+            2
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn format_parses_from_str() {
+        assert_eq!("text".parse(), Ok(Format::Text));
+        assert_eq!("json".parse(), Ok(Format::Json));
+        assert_eq!("raw-json".parse(), Ok(Format::RawJson));
+        assert_eq!("sarif".parse(), Ok(Format::Sarif));
+        assert_eq!("facts".parse(), Ok(Format::Facts));
+        assert!("yaml".parse::<Format>().is_err());
+    }
+
+    #[test]
+    fn render_change_renders_empty_fields() {
+        colored::control::set_override(false);
+        assert_eq!(
+            render_change(
+                &RenderedChange::from(Change::default()),
+                &RenderOptions::default()
+            ),
+            "     "
+        );
+    }
+}
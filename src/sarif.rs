@@ -0,0 +1,328 @@
+use crate::{
+    changeset::{Condition, RenderedChange},
+    Diff,
+};
+use serde::Serialize;
+
+const SCHEMA: &str = "https://json.schemastore.org/sarif-2.1.0.json";
+
+/// a kind of risky change, reported as a SARIF rule
+struct Rule {
+    id: &'static str,
+    description: &'static str,
+    level: &'static str,
+    matches: fn(&RenderedChange) -> bool,
+}
+
+/// rules in order of precedence. a change is reported under the first rule it matches
+const RULES: &[Rule] = &[
+    Rule {
+        id: "data-loss",
+        description: "removal or replacement of a resource that stores data",
+        level: "error",
+        matches: |change| Condition::DataLoss.matches(change),
+    },
+    Rule {
+        id: "removal",
+        description: "removal of a resource",
+        level: "warning",
+        matches: |change| Condition::Remove.matches(change),
+    },
+    Rule {
+        id: "replacement",
+        description: "replacement of a resource",
+        level: "warning",
+        matches: |change| Condition::Replace.matches(change),
+    },
+    Rule {
+        id: "iam",
+        description: "change to an IAM resource",
+        level: "warning",
+        matches: |change| change.resource_type.starts_with("AWS::IAM::"),
+    },
+];
+
+#[derive(Serialize)]
+pub struct Log {
+    #[serde(rename = "$schema")]
+    schema: &'static str,
+    version: &'static str,
+    runs: Vec<Run>,
+}
+
+#[derive(Serialize)]
+struct Run {
+    tool: Tool,
+    results: Vec<SarifResult>,
+}
+
+#[derive(Serialize)]
+struct Tool {
+    driver: Driver,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct Driver {
+    name: &'static str,
+    version: &'static str,
+    information_uri: &'static str,
+    rules: Vec<ReportingDescriptor>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct ReportingDescriptor {
+    id: &'static str,
+    short_description: Message,
+    default_configuration: Configuration,
+}
+
+#[derive(Serialize)]
+struct Configuration {
+    level: &'static str,
+}
+
+#[derive(Serialize)]
+struct Message {
+    text: String,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct SarifResult {
+    rule_id: &'static str,
+    level: &'static str,
+    message: Message,
+    locations: Vec<Location>,
+    properties: Properties,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct Location {
+    physical_location: PhysicalLocation,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct PhysicalLocation {
+    artifact_location: ArtifactLocation,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    region: Option<Region>,
+}
+
+#[derive(Serialize)]
+struct ArtifactLocation {
+    uri: String,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct Region {
+    start_line: usize,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct Properties {
+    stack_name: String,
+    region: String,
+    logical_resource_id: String,
+    resource_type: String,
+}
+
+/// the 1-based line declaring a resource in a YAML or JSON template, if it can be found
+fn line(
+    template_body: &str,
+    logical_resource_id: &str,
+) -> Option<usize> {
+    let yaml = format!("{}:", logical_resource_id);
+    let json = format!("\"{}\"", logical_resource_id);
+    template_body
+        .lines()
+        .position(|line| {
+            let line = line.trim_start();
+            line.starts_with(&yaml)
+                || line
+                    .strip_prefix(&json)
+                    .is_some_and(|rest| rest.trim_start().starts_with(':'))
+        })
+        .map(|index| index + 1)
+}
+
+/// the results of a diff's risky changes, located in its local template
+fn results(diff: &Diff) -> impl Iterator<Item = SarifResult> + '_ {
+    let uri = diff.filename.to_string_lossy().replace('\\', "/");
+    diff.result.changes.iter().filter_map(move |change| {
+        let rule = RULES.iter().find(|rule| (rule.matches)(change))?;
+        Some(SarifResult {
+            rule_id: rule.id,
+            level: rule.level,
+            message: Message {
+                text: format!(
+                    "{} {} ({}) in stack {}",
+                    rule.description,
+                    change.logical_resource_id,
+                    change.resource_type,
+                    diff.stack_name
+                ),
+            },
+            locations: vec![Location {
+                physical_location: PhysicalLocation {
+                    artifact_location: ArtifactLocation { uri: uri.clone() },
+                    region: line(&diff.template_body, &change.logical_resource_id)
+                        .map(|start_line| Region { start_line }),
+                },
+            }],
+            properties: Properties {
+                stack_name: diff.stack_name.clone(),
+                region: diff.region.name().into(),
+                logical_resource_id: change.logical_resource_id.clone(),
+                resource_type: change.resource_type.clone(),
+            },
+        })
+    })
+}
+
+/// a SARIF log with a single run of the risky changes of every diff
+pub fn log(diffs: &[Diff]) -> Log {
+    Log {
+        schema: SCHEMA,
+        version: "2.1.0",
+        runs: vec![Run {
+            tool: Tool {
+                driver: Driver {
+                    name: "cliff",
+                    version: env!("CARGO_PKG_VERSION"),
+                    information_uri: "https://github.com/meetuparchive/cliff",
+                    rules: RULES
+                        .iter()
+                        .map(|rule| ReportingDescriptor {
+                            id: rule.id,
+                            short_description: Message {
+                                text: rule.description.into(),
+                            },
+                            default_configuration: Configuration { level: rule.level },
+                        })
+                        .collect(),
+                },
+            },
+            results: diffs.iter().flat_map(results).collect(),
+        }],
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::changeset::{DiffResult, Status};
+    use std::path::PathBuf;
+
+    const TEMPLATE: &str = r#"
+Resources:
+  Table:
+    Type: AWS::DynamoDB::Table
+  Role:
+    Type: AWS::IAM::Role
+  Topic:
+    Type: AWS::SNS::Topic
+"#;
+
+    fn change(
+        action: &str,
+        resource_type: &str,
+        logical_resource_id: &str,
+        replacement: bool,
+    ) -> RenderedChange {
+        RenderedChange {
+            action: action.into(),
+            resource_type: resource_type.into(),
+            logical_resource_id: logical_resource_id.into(),
+            replacement,
+            ..RenderedChange::default()
+        }
+    }
+
+    #[test]
+    fn line_finds_yaml_and_json_resources() {
+        assert_eq!(line(TEMPLATE, "Role"), Some(5));
+        assert_eq!(
+            line("{\n  \"Resources\": {\n    \"Role\" : {}\n  }\n}", "Role"),
+            Some(3)
+        );
+        assert_eq!(line(TEMPLATE, "Queue"), None);
+        assert_eq!(line(TEMPLATE, "Tab"), None);
+    }
+
+    #[test]
+    fn log_reports_risky_changes() -> Result<(), serde_json::Error> {
+        let diff = Diff {
+            stack_name: "test".into(),
+            region: rusoto_core::Region::UsEast1,
+            filename: PathBuf::from("templates/test.yml"),
+            template_body: TEMPLATE.into(),
+            template: None,
+            changeset: Default::default(),
+            parameters: Vec::new(),
+            timings: Default::default(),
+            identity: None,
+            template_error: None,
+            notes: Vec::new(),
+            result: DiffResult {
+                status: Status::Complete,
+                changes: vec![
+                    change("Modify", "AWS::DynamoDB::Table", "Table", true),
+                    change("Modify", "AWS::IAM::Role", "Role", false),
+                    change("Modify", "AWS::SNS::Topic", "Topic", false),
+                    change("Remove", "AWS::SQS::Queue", "Queue", false),
+                ],
+                summary: Default::default(),
+                hidden: 0,
+            },
+        };
+        let other = Diff {
+            stack_name: "other".into(),
+            template_body: String::new(),
+            result: DiffResult {
+                changes: vec![change("Remove", "AWS::SNS::Topic", "Topic", false)],
+                ..diff.result.clone()
+            },
+            ..diff.clone()
+        };
+        let log = serde_json::to_value(log(&[diff, other]))?;
+        assert_eq!(log["runs"].as_array().map(Vec::len), Some(1));
+        let results = log["runs"][0]["results"]
+            .as_array()
+            .cloned()
+            .unwrap_or_default();
+        assert_eq!(
+            results
+                .iter()
+                .map(|result| (result["ruleId"].as_str(), result["level"].as_str()))
+                .collect::<Vec<_>>(),
+            vec![
+                (Some("data-loss"), Some("error")),
+                (Some("iam"), Some("warning")),
+                (Some("data-loss"), Some("error")),
+                (Some("removal"), Some("warning")),
+            ]
+        );
+        assert_eq!(
+            results[1]["locations"][0]["physicalLocation"],
+            serde_json::json!({
+                "artifactLocation": { "uri": "templates/test.yml" },
+                "region": { "startLine": 5 }
+            })
+        );
+        assert!(results[2]["locations"][0]["physicalLocation"]
+            .get("region")
+            .is_none());
+        assert_eq!(results[3]["properties"]["stackName"], "other");
+        assert_eq!(
+            results[1]["message"]["text"],
+            "change to an IAM resource Role (AWS::IAM::Role) in stack test"
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,231 @@
+use crate::{http, profile, web_identity};
+use chrono::{DateTime, TimeZone, Utc};
+use futures::{future, Future, Stream};
+use hyper::{Body, Client, Request};
+use rusoto_core::credential::{AwsCredentials, CredentialsError};
+use serde::Deserialize;
+use std::fs;
+
+/// the settings of an SSO-configured profile
+#[derive(Debug, PartialEq)]
+struct Settings {
+    /// the key of the cached token, a session name or legacy start url
+    cache_key: String,
+    region: String,
+    account_id: String,
+    role_name: String,
+}
+
+/// the portal url from which an SSO profile's role credentials are fetched
+fn credentials_url(settings: &Settings) -> String {
+    format!(
+        "https://portal.sso.{}.amazonaws.com/federation/credentials?role_name={}&account_id={}",
+        settings.region,
+        web_identity::encode(&settings.role_name),
+        web_identity::encode(&settings.account_id)
+    )
+}
+
+/// reads the SSO settings of a profile from the contents of an aws config file
+fn settings(
+    config: &str,
+    name: &str,
+) -> Option<Settings> {
+    let section = profile::section(name);
+    let get = |key: &str| profile::setting(config, &section, key);
+    let (cache_key, region) = match get("sso_session") {
+        Some(session) => {
+            let region =
+                profile::setting(config, &format!("sso-session {}", session), "sso_region")?;
+            (session, region)
+        }
+        _ => (get("sso_start_url")?, get("sso_region")?),
+    };
+    Some(Settings {
+        cache_key,
+        region,
+        account_id: get("sso_account_id")?,
+        role_name: get("sso_role_name")?,
+    })
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct CachedToken {
+    access_token: String,
+    expires_at: String,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct RoleCredentialsResponse {
+    role_credentials: RoleCredentials,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct RoleCredentials {
+    access_key_id: String,
+    secret_access_key: String,
+    session_token: String,
+    /// milliseconds since the epoch
+    expiration: i64,
+}
+
+fn expired(name: &str) -> CredentialsError {
+    CredentialsError::new(format!(
+        "The SSO session for profile {} has expired. Run `aws sso login --profile {}` to refresh it",
+        name, name
+    ))
+}
+
+/// parses a cached token's expiry, which the aws cli writes in rfc3339 or with a UTC suffix
+fn expiry(expires_at: &str) -> Option<DateTime<Utc>> {
+    DateTime::parse_from_rfc3339(&expires_at.replace("UTC", "Z"))
+        .ok()
+        .map(|at| at.with_timezone(&Utc))
+}
+
+/// reads the unexpired cached access token written by `aws sso login`
+fn access_token(
+    name: &str,
+    cache_key: &str,
+) -> Result<String, CredentialsError> {
+    let path = profile::home()
+        .ok_or_else(|| CredentialsError::new("Couldn't locate a home directory"))?
+        .join(".aws")
+        .join("sso")
+        .join("cache")
+        .join(format!("{}.json", sha1::Sha1::from(cache_key).digest()));
+    let token: CachedToken = fs::read_to_string(&path)
+        .ok()
+        .and_then(|json| serde_json::from_str(&json).ok())
+        .ok_or_else(|| expired(name))?;
+    match expiry(&token.expires_at) {
+        Some(at) if at > Utc::now() => Ok(token.access_token),
+        _ => Err(expired(name)),
+    }
+}
+
+/// true when a profile is configured for AWS SSO
+pub(crate) fn configured(name: &str) -> bool {
+    profile::config()
+        .and_then(|config| settings(&config, name))
+        .is_some()
+}
+
+/// resolves role credentials for an SSO-configured profile using the token cached by
+/// `aws sso login`
+pub(crate) fn credentials(
+    name: &str
+) -> Box<dyn Future<Item = AwsCredentials, Error = CredentialsError> + Send> {
+    let settings = match profile::config().and_then(|config| settings(&config, name)) {
+        Some(settings) => settings,
+        _ => {
+            return Box::new(future::err(CredentialsError::new(format!(
+                "Profile {} is not configured for SSO",
+                name
+            ))))
+        }
+    };
+    let token = match access_token(name, &settings.cache_key) {
+        Ok(token) => token,
+        Err(err) => return Box::new(future::err(err)),
+    };
+    let connector = match http::connector() {
+        Ok(connector) => connector,
+        Err(err) => return Box::new(future::err(CredentialsError::new(err.to_string()))),
+    };
+    let request = Request::get(credentials_url(&settings))
+        .header("x-amz-sso_bearer_token", token)
+        .body(Body::empty());
+    let request = match request {
+        Ok(request) => request,
+        Err(err) => return Box::new(future::err(CredentialsError::new(err.to_string()))),
+    };
+    let name = name.to_string();
+    Box::new(
+        Client::builder()
+            .build::<_, Body>(connector)
+            .request(request)
+            .map_err(|err| CredentialsError::new(err.to_string()))
+            .and_then(move |response| {
+                let status = response.status();
+                response
+                    .into_body()
+                    .concat2()
+                    .map_err(|err| CredentialsError::new(err.to_string()))
+                    .and_then(move |body| {
+                        if status.as_u16() == 401 || status.as_u16() == 403 {
+                            return Err(expired(&name));
+                        }
+                        if !status.is_success() {
+                            return Err(CredentialsError::new(format!(
+                                "Failed to get SSO role credentials: {} {}",
+                                status,
+                                String::from_utf8_lossy(&body)
+                            )));
+                        }
+                        let RoleCredentialsResponse { role_credentials } =
+                            serde_json::from_slice(&body)
+                                .map_err(|err| CredentialsError::new(err.to_string()))?;
+                        Ok(AwsCredentials::new(
+                            role_credentials.access_key_id,
+                            role_credentials.secret_access_key,
+                            Some(role_credentials.session_token),
+                            Some(Utc.timestamp_millis(role_credentials.expiration)),
+                        ))
+                    })
+            }),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn settings_reads_legacy_profiles() {
+        let config = "[profile dev]\nsso_start_url = https://example.awsapps.com/start\nsso_region = us-east-1\nsso_account_id = 123456789012\nsso_role_name = Developer\n";
+        assert_eq!(
+            settings(config, "dev"),
+            Some(Settings {
+                cache_key: "https://example.awsapps.com/start".into(),
+                region: "us-east-1".into(),
+                account_id: "123456789012".into(),
+                role_name: "Developer".into(),
+            })
+        );
+        assert_eq!(settings(config, "default"), None);
+    }
+
+    #[test]
+    fn settings_reads_sso_sessions() {
+        let config = "[profile dev]\nsso_session = corp\nsso_account_id = 123456789012\nsso_role_name = Developer\n\n[sso-session corp]\nsso_start_url = https://example.awsapps.com/start\nsso_region = eu-west-1\n";
+        assert_eq!(
+            settings(config, "dev").map(|s| (s.cache_key, s.region)),
+            Some(("corp".into(), "eu-west-1".into()))
+        );
+    }
+
+    #[test]
+    fn credentials_url_encodes_query_values() {
+        let settings = Settings {
+            cache_key: "corp".into(),
+            region: "us-east-1".into(),
+            account_id: "123456789012".into(),
+            role_name: "Dev Ops&Admin".into(),
+        };
+        assert_eq!(
+            credentials_url(&settings),
+            "https://portal.sso.us-east-1.amazonaws.com/federation/credentials?role_name=Dev%20Ops%26Admin&account_id=123456789012"
+        );
+    }
+
+    #[test]
+    fn expiry_parses_cli_formats() {
+        assert!(expiry("2030-01-01T00:00:00Z").is_some());
+        assert!(expiry("2030-01-01T00:00:00UTC").is_some());
+        assert!(expiry("tomorrow").is_none());
+    }
+}
@@ -0,0 +1,1059 @@
+use crate::error::Error;
+use std::{
+    env,
+    error::Error as StdError,
+    fmt, fs,
+    io::{self, Read, Write},
+    path::Path,
+    process::{Child, Command, ExitStatus, Stdio},
+    str::FromStr,
+    sync::mpsc::{self, RecvTimeoutError},
+    thread,
+    time::{Duration, Instant},
+};
+
+/// lines of context around each change in template diffs, matching `diff -u`
+pub const DEFAULT_CONTEXT_LINES: usize = 3;
+
+/// most bytes of differ output captured by default
+pub const DEFAULT_MAX_DIFF_BYTES: usize = 1024 * 1024;
+
+/// how long a differ may run by default before it's killed
+pub const DEFAULT_DIFF_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// how often a differ that closed its output is checked for having exited
+const WAIT_INTERVAL: Duration = Duration::from_millis(10);
+
+/// how template diffs are made
+#[derive(Debug, Clone, PartialEq)]
+pub struct DiffOptions {
+    /// lines of context around each change. passed to `CLIFF_DIFFER` only when set
+    pub context_lines: Option<usize>,
+    /// diff the local template against the deployed template instead
+    pub reverse: bool,
+    /// ignore lines that differ only in whitespace
+    pub ignore_whitespace: bool,
+    /// most bytes of differ output captured, truncating larger diffs with a notice.
+    /// 0 captures everything
+    pub max_bytes: usize,
+    /// how long a differ may run before it's killed and the diff fails, guarding
+    /// against differs that wait for input. None lets differs run forever
+    pub timeout: Option<Duration>,
+}
+
+impl Default for DiffOptions {
+    fn default() -> Self {
+        DiffOptions {
+            context_lines: None,
+            reverse: false,
+            ignore_whitespace: false,
+            max_bytes: DEFAULT_MAX_DIFF_BYTES,
+            timeout: Some(DEFAULT_DIFF_TIMEOUT),
+        }
+    }
+}
+
+/// which stage of a deployed template to fetch
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum Stage {
+    /// the template as it was submitted
+    #[default]
+    Original,
+    /// the template after macros and transforms were applied
+    Processed,
+}
+
+impl FromStr for Stage {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_lowercase().as_str() {
+            "original" => Ok(Stage::Original),
+            "processed" => Ok(Stage::Processed),
+            _ => Err(format!(
+                "invalid template stage `{}`. expected Original or Processed",
+                s.trim()
+            )),
+        }
+    }
+}
+
+impl fmt::Display for Stage {
+    fn fmt(
+        &self,
+        f: &mut fmt::Formatter,
+    ) -> fmt::Result {
+        match self {
+            Stage::Original => write!(f, "Original"),
+            Stage::Processed => write!(f, "Processed"),
+        }
+    }
+}
+
+/// the format of a template, for naming its temporary copies when its filename's
+/// extension doesn't reflect it
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TemplateFormat {
+    Yaml,
+    Json,
+}
+
+impl FromStr for TemplateFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_lowercase().as_str() {
+            "yaml" | "yml" => Ok(TemplateFormat::Yaml),
+            "json" => Ok(TemplateFormat::Json),
+            _ => Err(format!(
+                "invalid template format `{}`. expected yaml or json",
+                s.trim()
+            )),
+        }
+    }
+}
+
+impl TemplateFormat {
+    /// the extension of files in this format
+    pub fn extension(self) -> &'static str {
+        match self {
+            TemplateFormat::Yaml => "yml",
+            TemplateFormat::Json => "json",
+        }
+    }
+}
+
+/// leading bytes of gzip compressed files
+const GZIP_MAGIC: &[u8] = &[0x1f, 0x8b];
+
+fn gzip_extension(filename: &Path) -> bool {
+    filename
+        .extension()
+        .is_some_and(|x| x.eq_ignore_ascii_case("gz"))
+}
+
+/// the suffix of a template's temporary copy, reflecting the format of the template
+/// inside of any `.gz` extension unless a format is provided
+fn suffix(
+    filename: &Path,
+    format: Option<TemplateFormat>,
+) -> String {
+    if let Some(format) = format {
+        return format!(".{}", format.extension());
+    }
+    let inner = if gzip_extension(filename) {
+        filename.file_stem().map(Path::new)
+    } else {
+        Some(filename)
+    };
+    inner
+        .and_then(Path::extension)
+        .map(|x| format!(".{}", x.to_str().unwrap_or_default()))
+        .unwrap_or_default()
+}
+
+fn suffix_tempfile(
+    filename: &Path,
+    format: Option<TemplateFormat>,
+) -> io::Result<tempfile::NamedTempFile> {
+    tempfile::Builder::new()
+        .suffix(&suffix(filename, format))
+        .tempfile()
+}
+
+/// decompresses gzip compressed bytes with `gzip -dc`
+fn gunzip(bytes: Vec<u8>) -> io::Result<Vec<u8>> {
+    let mut child = Command::new("gzip")
+        .arg("-dc")
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|err| match err.kind() {
+            io::ErrorKind::NotFound => io::Error::new(
+                err.kind(),
+                "gzip was not found. install it to read compressed templates",
+            ),
+            _ => err,
+        })?;
+    let mut stdin = child.stdin.take();
+    let writer = thread::spawn(move || match stdin.as_mut() {
+        Some(stdin) => stdin.write_all(&bytes),
+        _ => Ok(()),
+    });
+    let output = child.wait_with_output()?;
+    writer
+        .join()
+        .map_err(|_| io::Error::other("failed to write to gzip"))??;
+    if !output.status.success() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!(
+                "gzip exited with {}: {}",
+                output.status,
+                String::from_utf8_lossy(&output.stderr).trim()
+            ),
+        ));
+    }
+    Ok(output.stdout)
+}
+
+/// expands a differ command into its arguments, substituting `{before}` with the
+/// deployed template and `{after}` with the local template. without placeholders
+/// the deployed and local templates are appended, in that order, as with `diff`.
+/// `{context}` is substituted with the lines of context and `{ignore_whitespace}`
+/// with `-w` when whitespace is ignored. without those placeholders, `-U N` and `-w`
+/// are passed ahead of the templates when set
+fn differ_args(
+    tool: &str,
+    before: &str,
+    after: &str,
+    context_lines: Option<usize>,
+    ignore_whitespace: bool,
+) -> Vec<String> {
+    let files = |arg: &str| arg.contains("{before}") || arg.contains("{after}");
+    let mut flags = Vec::new();
+    if let (false, Some(lines)) = (tool.contains("{context}"), context_lines) {
+        flags.extend(vec!["-U".to_string(), lines.to_string()]);
+    }
+    if ignore_whitespace && !tool.contains("{ignore_whitespace}") {
+        flags.push("-w".to_string());
+    }
+    let context = context_lines.unwrap_or(DEFAULT_CONTEXT_LINES).to_string();
+    let mut args = Vec::new();
+    for arg in tool.split_whitespace() {
+        if files(arg) {
+            args.append(&mut flags);
+        }
+        let arg = arg
+            .replace("{before}", before)
+            .replace("{after}", after)
+            .replace("{context}", &context)
+            .replace(
+                "{ignore_whitespace}",
+                if ignore_whitespace { "-w" } else { "" },
+            );
+        if !arg.is_empty() {
+            args.push(arg);
+        }
+    }
+    args.append(&mut flags);
+    if !files(tool) {
+        args.extend(vec![before.to_string(), after.to_string()]);
+    }
+    args
+}
+
+/// the default differ: a unified diff labelled with the stack and local filename,
+/// or `fc` on windows where `diff` is typically unavailable
+fn default_args(
+    before_label: &str,
+    after_label: &str,
+    before: &str,
+    after: &str,
+    context_lines: usize,
+    ignore_whitespace: bool,
+) -> Vec<String> {
+    if cfg!(windows) {
+        let mut args = vec!["fc".to_string(), "/N".into()];
+        if ignore_whitespace {
+            args.push("/W".into());
+        }
+        args.extend(vec![before.to_string(), after.to_string()]);
+        return args;
+    }
+    let mut args = vec!["diff".to_string(), "-U".into(), context_lines.to_string()];
+    if ignore_whitespace {
+        args.push("-w".into());
+    }
+    args.extend(vec![
+        "--label".into(),
+        format!("a/{}", before_label),
+        "--label".into(),
+        format!("b/{}", after_label),
+        before.into(),
+        after.into(),
+    ]);
+    args
+}
+
+fn write_tempfile(
+    filename: &Path,
+    format: Option<TemplateFormat>,
+    body: &str,
+) -> io::Result<tempfile::NamedTempFile> {
+    let mut tmp = suffix_tempfile(filename, format)?;
+    tmp.write_all(body.as_bytes())?;
+    tmp.flush()?;
+    Ok(tmp)
+}
+
+/// diffs a template body, typically a deployed stack's, against the body of a local
+/// template file using `CLIFF_DIFFER`, defaulting to a `diff -u` with `label` naming
+/// the template body. the local file is diffed in place unless its body was normalized.
+/// temporary copies are suffixed for `format`, defaulting to the format of `filename`
+pub fn diff_template(
+    label: &str,
+    filename: &Path,
+    format: Option<TemplateFormat>,
+    local_body: &str,
+    template_body: String,
+    options: &DiffOptions,
+) -> Result<String, Box<dyn StdError>> {
+    let tmp = write_tempfile(filename, format, &template_body)?;
+    let path = tmp.path().to_str().unwrap_or_default();
+    // a local file named for another format is copied so differs see the right suffix
+    let normalized = match fs::read_to_string(filename) {
+        Ok(ref raw) if raw == local_body && format.is_none() => None,
+        _ => Some(write_tempfile(filename, format, local_body)?),
+    };
+    let name = filename.to_str().unwrap_or_default();
+    let local = normalized
+        .as_ref()
+        .and_then(|tmp| tmp.path().to_str())
+        .unwrap_or(name);
+    let ((before_label, before), (after_label, after)) = if options.reverse {
+        ((name, local), (label, path))
+    } else {
+        ((label, path), (name, local))
+    };
+    let ignore_whitespace = options.ignore_whitespace;
+    let tool = env::var("CLIFF_DIFFER").ok();
+    let elements = match &tool {
+        Some(tool) if tool.trim().is_empty() => return Err(Box::new(Error::Differ(tool.clone()))),
+        Some(tool) => differ_args(
+            tool,
+            before,
+            after,
+            options.context_lines,
+            ignore_whitespace,
+        ),
+        _ => default_args(
+            before_label,
+            after_label,
+            before,
+            after,
+            options.context_lines.unwrap_or(DEFAULT_CONTEXT_LINES),
+            ignore_whitespace,
+        ),
+    };
+    run_differ(
+        &elements,
+        tool.as_deref(),
+        options.max_bytes,
+        options.timeout,
+    )
+}
+
+/// waits for a child to exit until a deadline, killing it once the deadline passes.
+/// yields None when it was killed
+fn wait_until(
+    child: &mut Child,
+    deadline: Option<Instant>,
+) -> io::Result<Option<ExitStatus>> {
+    let deadline = match deadline {
+        Some(deadline) => deadline,
+        _ => return child.wait().map(Some),
+    };
+    loop {
+        if let Some(status) = child.try_wait()? {
+            return Ok(Some(status));
+        }
+        if Instant::now() >= deadline {
+            let _ = child.kill();
+            child.wait()?;
+            return Ok(None);
+        }
+        thread::sleep(WAIT_INTERVAL);
+    }
+}
+
+/// runs a differ, yielding its output. differs like `diff` exit nonzero when inputs
+/// differ, so a nonzero exit is only an error when the differ wrote nothing but errors.
+/// output beyond `max_bytes`, unless 0, is cut off at the last complete line with a
+/// notice, and bytes that aren't UTF-8 are replaced. a differ still running after
+/// `timeout` is killed
+fn run_differ(
+    elements: &[String],
+    tool: Option<&str>,
+    max_bytes: usize,
+    timeout: Option<Duration>,
+) -> Result<String, Box<dyn StdError>> {
+    let (program, args) = match elements.split_first() {
+        Some(pair) => pair,
+        _ => return Err(Box::new(Error::Differ(String::new()))),
+    };
+    let mut child = Command::new(program)
+        .args(args)
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|err| -> Box<dyn StdError> {
+            if err.kind() != io::ErrorKind::NotFound {
+                return err.into();
+            }
+            Box::new(Error::Differ(match tool {
+                Some(tool) => format!(
+                    "`{}` from CLIFF_DIFFER=`{}`: {} was not found",
+                    program, tool, program
+                ),
+                _ => format!(
+                    "`{}`: {} was not found. install it or set CLIFF_DIFFER",
+                    program, program
+                ),
+            }))
+        })?;
+    // stderr is drained alongside stdout so that neither pipe fills and blocks the differ
+    let stderr = child.stderr.take().map(|mut stderr| {
+        thread::spawn(move || {
+            let mut bytes = Vec::new();
+            let _ = stderr.read_to_end(&mut bytes);
+            bytes
+        })
+    });
+    let deadline = timeout.map(|timeout| Instant::now() + timeout);
+    let timed_out = |timeout: Option<Duration>| -> Box<dyn StdError> {
+        Box::new(Error::Differ(format!(
+            "`{}` didn't finish within {}s. differs that wait for input, like pagers, can't be used. raise --diff-tool-timeout for slow differs",
+            program,
+            timeout.unwrap_or_default().as_secs_f64()
+        )))
+    };
+    // stdout is read on its own thread so that a differ that never finishes writing
+    // can't outlast the timeout
+    let (sender, receiver) = mpsc::channel();
+    let pipe = child.stdout.take();
+    thread::spawn(move || {
+        let mut bytes = Vec::new();
+        let limit = match max_bytes {
+            0 => u64::MAX,
+            max => max as u64 + 1,
+        };
+        let read = match pipe {
+            Some(pipe) => pipe.take(limit).read_to_end(&mut bytes).map(|_| bytes),
+            _ => Ok(bytes),
+        };
+        let _ = sender.send(read);
+    });
+    let read = match deadline {
+        Some(deadline) => receiver.recv_timeout(deadline.saturating_duration_since(Instant::now())),
+        _ => receiver.recv().map_err(RecvTimeoutError::from),
+    };
+    let mut stdout = match read {
+        Ok(read) => read?,
+        Err(RecvTimeoutError::Timeout) => {
+            let _ = child.kill();
+            let _ = child.wait();
+            return Err(timed_out(timeout));
+        }
+        Err(RecvTimeoutError::Disconnected) => Vec::new(),
+    };
+    let truncated = max_bytes > 0 && stdout.len() > max_bytes;
+    if truncated {
+        let _ = child.kill();
+        stdout.truncate(max_bytes);
+        let complete = stdout
+            .iter()
+            .rposition(|&b| b == b'\n')
+            .map_or(0, |i| i + 1);
+        stdout.truncate(complete);
+    }
+    let status = match wait_until(&mut child, deadline)? {
+        Some(status) => status,
+        _ => return Err(timed_out(timeout)),
+    };
+    let stderr = stderr
+        .and_then(|reader| reader.join().ok())
+        .unwrap_or_default();
+    if !truncated && !status.success() && stdout.is_empty() && !stderr.is_empty() {
+        return Err(Box::new(Error::Differ(format!(
+            "`{}` exited with {}: {}",
+            program,
+            status,
+            String::from_utf8_lossy(&stderr).trim()
+        ))));
+    }
+    let mut diff = String::from_utf8_lossy(&stdout).into_owned();
+    if is_fc(program) {
+        diff = fc_output(status.code(), diff);
+    }
+    if truncated {
+        diff.push_str(&format!(
+            "... diff truncated at {} bytes (use --max-diff-bytes 0 to show all)\n",
+            max_bytes
+        ));
+    }
+    Ok(diff)
+}
+
+/// true when a differ is windows' `fc`
+fn is_fc(program: &str) -> bool {
+    program.eq_ignore_ascii_case("fc") || program.eq_ignore_ascii_case("fc.exe")
+}
+
+/// `fc` reports identical files in words rather than with empty output, so its output
+/// is only kept when it exits with 1, meaning the files differ
+fn fc_output(
+    code: Option<i32>,
+    output: String,
+) -> String {
+    match code {
+        Some(0) => String::new(),
+        _ => output,
+    }
+}
+
+/// runs a command that builds a template, like `cdk synth`, before it's diffed.
+/// `{template}` and `{stack}` in the command are replaced with the template's filename
+/// and the stack's name. output the command writes to stdout replaces the template's
+/// body. otherwise the command is expected to write the template itself
+pub fn run_template_command(
+    command: &str,
+    filename: &Path,
+    stack_name: &str,
+) -> Result<(), Box<dyn StdError>> {
+    let name = filename.to_str().unwrap_or_default();
+    let elements = command
+        .split_whitespace()
+        .map(|arg| {
+            arg.replace("{template}", name)
+                .replace("{stack}", stack_name)
+        })
+        .collect::<Vec<_>>();
+    let (program, args) = match elements.split_first() {
+        Some(pair) => pair,
+        _ => return Err(Box::new(Error::Template("empty template command".into()))),
+    };
+    let output = Command::new(program)
+        .args(args)
+        .stderr(Stdio::inherit())
+        .output()
+        .map_err(|err| -> Box<dyn StdError> {
+            match err.kind() {
+                io::ErrorKind::NotFound => Box::new(Error::Template(format!(
+                    "`{}` from --template-command: {} was not found",
+                    command, program
+                ))),
+                _ => err.into(),
+            }
+        })?;
+    if !output.status.success() {
+        return Err(Box::new(Error::Template(format!(
+            "template command `{}` exited with {}",
+            command, output.status
+        ))));
+    }
+    if !output.stdout.is_empty() {
+        fs::write(filename, &output.stdout)?;
+    }
+    Ok(())
+}
+
+/// the name of the stack a template declares itself deployed as, in
+/// `Metadata: { Cliff: { StackName: ... } }`
+pub fn metadata_stack_name(template: &serde_yaml::Value) -> Option<String> {
+    template
+        .get("Metadata")?
+        .get("Cliff")?
+        .get("StackName")?
+        .as_str()
+        .map(String::from)
+}
+
+/// reads a template body from disk, decompressing it when gzipped and stripping any
+/// leading UTF-8 byte order mark
+pub fn template_body<P: AsRef<Path>>(filename: P) -> io::Result<String> {
+    let filename = filename.as_ref();
+    let mut bytes = fs::read(filename)?;
+    if gzip_extension(filename) || bytes.starts_with(GZIP_MAGIC) {
+        bytes = gunzip(bytes)?;
+    }
+    let body =
+        String::from_utf8(bytes).map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+    Ok(match body.strip_prefix('\u{feff}') {
+        Some(stripped) => stripped.to_string(),
+        _ => body,
+    })
+}
+
+/// converts CRLF line endings to the LF line endings CloudFormation stores
+pub fn normalize_line_endings(template_body: &str) -> String {
+    template_body.replace("\r\n", "\n")
+}
+
+/// parses a YAML or JSON template body
+pub fn parse(template_body: &str) -> Result<serde_yaml::Value, serde_yaml::Error> {
+    serde_yaml::from_str(template_body)
+}
+
+/// the number of resources a parsed template declares
+pub fn resource_count(template: &serde_yaml::Value) -> usize {
+    template
+        .get("Resources")
+        .and_then(serde_yaml::Value::as_mapping)
+        .map_or(0, serde_yaml::Mapping::len)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    #[test]
+    fn template_body_decompresses_gzipped_templates() -> Result<(), Box<dyn StdError>> {
+        assert_eq!(
+            template_body("tests/data/template-after.yml.gz")?,
+            include_str!("../tests/data/template-after.yml")
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn suffix_strips_gz_extension() {
+        assert_eq!(suffix(Path::new("template.yml.gz"), None), ".yml");
+        assert_eq!(suffix(Path::new("template.json"), None), ".json");
+        assert_eq!(suffix(Path::new("template"), None), "");
+    }
+
+    #[test]
+    fn suffix_prefers_provided_format() {
+        assert_eq!(
+            suffix(Path::new("template.txt"), Some(TemplateFormat::Yaml)),
+            ".yml"
+        );
+        assert_eq!(
+            suffix(Path::new("template.yml"), Some(TemplateFormat::Json)),
+            ".json"
+        );
+        assert_eq!("YAML".parse(), Ok(TemplateFormat::Yaml));
+        assert_eq!("json".parse(), Ok(TemplateFormat::Json));
+        assert!("toml".parse::<TemplateFormat>().is_err());
+    }
+
+    #[test]
+    fn stage_parses_from_str() {
+        assert_eq!("Original".parse(), Ok(Stage::Original));
+        assert_eq!("processed".parse(), Ok(Stage::Processed));
+        assert!("bogus".parse::<Stage>().is_err());
+        assert_eq!(Stage::Processed.to_string(), "Processed");
+    }
+
+    #[test]
+    fn template_body_reads_from_disk() {
+        assert!(template_body("tests/data/template-after.yml").is_ok())
+    }
+
+    #[test]
+    fn template_body_strips_bom() -> Result<(), Box<dyn StdError>> {
+        assert_eq!(
+            template_body("tests/data/template-bom.yml")?,
+            include_str!("../tests/data/template-before.yml")
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn normalize_line_endings_converts_crlf() -> Result<(), Box<dyn StdError>> {
+        assert_eq!(
+            normalize_line_endings(&template_body("tests/data/template-crlf.yml")?),
+            include_str!("../tests/data/template-before.yml")
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn diff_template_diffs_normalized_bodies() -> Result<(), Box<dyn StdError>> {
+        let body = normalize_line_endings(&template_body("tests/data/template-crlf.yml")?);
+        assert_eq!(
+            diff_template(
+                "test",
+                &PathBuf::from("tests/data/template-crlf.yml"),
+                None,
+                &body,
+                include_str!("../tests/data/template-before.yml").into(),
+                &DiffOptions::default(),
+            )?,
+            ""
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn parse_parses_yaml_and_json() -> Result<(), Box<dyn StdError>> {
+        let yaml = parse(include_str!("../tests/data/template-before.yml"))?;
+        let json = parse(
+            r#"{"Resources":{"DynamodbTable":{"Type":"AWS::DynamoDB::Table","Properties":{"TableName":"test"}}}}"#,
+        )?;
+        assert_eq!(yaml, json);
+        Ok(())
+    }
+
+    #[test]
+    fn differ_args_appends_filenames_without_placeholders() {
+        assert_eq!(
+            differ_args("diff -u", "remote.yml", "local.yml", None, false),
+            vec!["diff", "-u", "remote.yml", "local.yml"]
+        );
+        assert_eq!(
+            differ_args(
+                "git diff --no-index",
+                "remote.yml",
+                "local.yml",
+                Some(1),
+                true
+            ),
+            vec![
+                "git",
+                "diff",
+                "--no-index",
+                "-U",
+                "1",
+                "-w",
+                "remote.yml",
+                "local.yml"
+            ]
+        );
+    }
+
+    #[test]
+    fn fc_output_is_empty_for_identical_files() {
+        assert_eq!(
+            fc_output(
+                Some(0),
+                "Comparing files a and b\nFC: no differences encountered\n".into()
+            ),
+            ""
+        );
+        assert_eq!(
+            fc_output(Some(1), "***** a\n     1:  old\n".into()),
+            "***** a\n     1:  old\n"
+        );
+        assert!(is_fc("FC.EXE"));
+        assert!(!is_fc("diff"));
+    }
+
+    #[cfg(windows)]
+    #[test]
+    fn default_args_yield_no_diff_for_identical_templates() -> Result<(), Box<dyn StdError>> {
+        let body = "Resources:\n  Topic:\n    Type: AWS::SNS::Topic\n";
+        let before = write_tempfile(Path::new("template.yml"), None, body)?;
+        let after = write_tempfile(Path::new("template.yml"), None, body)?;
+        let diff = run_differ(
+            &default_args(
+                "test",
+                "template.yml",
+                &before.path().to_string_lossy(),
+                &after.path().to_string_lossy(),
+                3,
+                false,
+            ),
+            None,
+            DEFAULT_MAX_DIFF_BYTES,
+            Some(DEFAULT_DIFF_TIMEOUT),
+        )?;
+        assert_eq!(diff, "");
+        Ok(())
+    }
+
+    #[cfg(not(windows))]
+    #[test]
+    fn default_args_ignore_whitespace_only_changes() -> Result<(), Box<dyn StdError>> {
+        let before = write_tempfile(
+            Path::new("template.yml"),
+            None,
+            "Resources:\n  Topic:\n    Type: AWS::SNS::Topic\n",
+        )?;
+        let after = write_tempfile(
+            Path::new("template.yml"),
+            None,
+            "Resources:\n  Topic:   \n      Type:  AWS::SNS::Topic\n",
+        )?;
+        let (before, after) = (
+            before.path().to_string_lossy().to_string(),
+            after.path().to_string_lossy().to_string(),
+        );
+        let diff = |ignore_whitespace| {
+            run_differ(
+                &default_args(
+                    "test",
+                    "template.yml",
+                    &before,
+                    &after,
+                    3,
+                    ignore_whitespace,
+                ),
+                None,
+                DEFAULT_MAX_DIFF_BYTES,
+                Some(DEFAULT_DIFF_TIMEOUT),
+            )
+        };
+        assert!(!diff(false)?.is_empty());
+        assert_eq!(diff(true)?, "");
+        Ok(())
+    }
+
+    #[test]
+    fn differ_args_substitutes_placeholders() {
+        assert_eq!(
+            differ_args(
+                "delta {before} {after} --side-by-side",
+                "remote.yml",
+                "local.yml",
+                None,
+                false
+            ),
+            vec!["delta", "remote.yml", "local.yml", "--side-by-side"]
+        );
+        assert_eq!(
+            differ_args(
+                "icdiff {before} {after} --no-headers",
+                "remote.yml",
+                "local.yml",
+                Some(5),
+                false
+            ),
+            vec![
+                "icdiff",
+                "-U",
+                "5",
+                "remote.yml",
+                "local.yml",
+                "--no-headers"
+            ]
+        );
+        assert_eq!(
+            differ_args(
+                "delta --context={context} {ignore_whitespace}",
+                "remote.yml",
+                "local.yml",
+                Some(5),
+                false
+            ),
+            vec!["delta", "--context=5", "remote.yml", "local.yml"]
+        );
+    }
+
+    #[test]
+    fn resource_count_counts_declared_resources() -> Result<(), Box<dyn StdError>> {
+        assert_eq!(
+            resource_count(&parse(
+                "Resources:\n  Topic:\n    Type: AWS::SNS::Topic\n  Queue:\n    Type: AWS::SQS::Queue\n"
+            )?),
+            2
+        );
+        assert_eq!(resource_count(&parse("Outputs: {}")?), 0);
+        Ok(())
+    }
+
+    #[test]
+    fn metadata_stack_name_reads_cliff_metadata() -> Result<(), Box<dyn StdError>> {
+        let template = parse(
+            r#"
+Metadata:
+  Cliff:
+    StackName: svc-foo-prod
+Resources: {}
+"#,
+        )?;
+        assert_eq!(
+            metadata_stack_name(&template).as_deref(),
+            Some("svc-foo-prod")
+        );
+        assert_eq!(metadata_stack_name(&parse("Resources: {}")?), None);
+        assert_eq!(
+            metadata_stack_name(&parse("Metadata: { Cliff: { StackName: 1 } }")?),
+            None
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn run_template_command_writes_stdout_to_template() -> Result<(), Box<dyn StdError>> {
+        let dir = tempfile::tempdir()?;
+        let filename = dir.path().join("template.yml");
+        run_template_command("echo Description: {stack}", &filename, "test")?;
+        assert_eq!(fs::read_to_string(&filename)?, "Description: test\n");
+        Ok(())
+    }
+
+    #[test]
+    fn run_template_command_fails_on_nonzero_exit() {
+        let err = run_template_command("false", Path::new("template.yml"), "test")
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains("template command `false` exited with"));
+        assert!(
+            run_template_command("cliff-missing-command", Path::new("template.yml"), "test")
+                .unwrap_err()
+                .to_string()
+                .contains("cliff-missing-command was not found")
+        );
+    }
+
+    #[test]
+    fn run_differ_names_missing_program() {
+        let err = run_differ(
+            &["cliff-missing-differ".to_string(), "a".into(), "b".into()],
+            Some("cliff-missing-differ --side-by-side"),
+            0,
+            None,
+        )
+        .unwrap_err()
+        .to_string();
+        assert!(err.contains("cliff-missing-differ was not found"));
+        assert!(err.contains("CLIFF_DIFFER=`cliff-missing-differ --side-by-side`"));
+    }
+
+    #[test]
+    fn run_differ_allows_nonzero_exit_with_output() -> Result<(), Box<dyn StdError>> {
+        let diff = run_differ(
+            &[
+                "diff".to_string(),
+                "tests/data/template-before.yml".into(),
+                "tests/data/template-after.yml".into(),
+            ],
+            None,
+            DEFAULT_MAX_DIFF_BYTES,
+            Some(DEFAULT_DIFF_TIMEOUT),
+        )?;
+        assert!(!diff.is_empty());
+        Ok(())
+    }
+
+    #[cfg(not(windows))]
+    #[test]
+    fn run_differ_replaces_invalid_utf8() -> Result<(), Box<dyn StdError>> {
+        let diff = run_differ(
+            &["sh".to_string(), "-c".into(), r"printf '+a\377b\n'".into()],
+            None,
+            DEFAULT_MAX_DIFF_BYTES,
+            Some(DEFAULT_DIFF_TIMEOUT),
+        )?;
+        assert_eq!(diff, "+a\u{fffd}b\n");
+        Ok(())
+    }
+
+    #[cfg(not(windows))]
+    #[test]
+    fn run_differ_kills_differs_that_outlast_the_timeout() {
+        let start = Instant::now();
+        let result = run_differ(
+            &["sleep".to_string(), "10".into()],
+            Some("sleep 10"),
+            DEFAULT_MAX_DIFF_BYTES,
+            Some(Duration::from_millis(100)),
+        );
+        assert!(result
+            .err()
+            .is_some_and(|err| err.to_string().contains("didn't finish within 0.1s")));
+        assert!(start.elapsed() < Duration::from_secs(5));
+    }
+
+    #[cfg(not(windows))]
+    #[test]
+    fn run_differ_truncates_huge_output() -> Result<(), Box<dyn StdError>> {
+        let diff = run_differ(&["yes".to_string(), "+line".into()], None, 16, None)?;
+        assert_eq!(
+            diff,
+            "+line\n+line\n... diff truncated at 16 bytes (use --max-diff-bytes 0 to show all)\n"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn run_differ_fails_when_differ_only_errors() {
+        assert!(run_differ(
+            &[
+                "diff".to_string(),
+                "tests/data/missing.yml".into(),
+                "tests/data/template-after.yml".into()
+            ],
+            None,
+            DEFAULT_MAX_DIFF_BYTES,
+            Some(DEFAULT_DIFF_TIMEOUT),
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn diff_template_without_context_yields_minimal_diff() -> Result<(), Box<dyn StdError>> {
+        let diff = diff_template(
+            "test",
+            &PathBuf::from("tests/data/template-after.yml"),
+            None,
+            include_str!("../tests/data/template-after.yml"),
+            include_str!("../tests/data/template-before.yml").into(),
+            &DiffOptions {
+                context_lines: Some(0),
+                ..DiffOptions::default()
+            },
+        )?;
+        assert_eq!(
+            diff,
+            r#"--- a/test
++++ b/tests/data/template-after.yml
+@@ -5 +5 @@
+-      TableName: test
+\ No newline at end of file
++      TableName: test2
+\ No newline at end of file
+"#
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn diff_template_reverses_diff() -> Result<(), Box<dyn StdError>> {
+        let diff = diff_template(
+            "test",
+            &PathBuf::from("tests/data/template-after.yml"),
+            None,
+            include_str!("../tests/data/template-after.yml"),
+            include_str!("../tests/data/template-before.yml").into(),
+            &DiffOptions {
+                context_lines: Some(0),
+                reverse: true,
+                ..DiffOptions::default()
+            },
+        )?;
+        assert_eq!(
+            diff,
+            r#"--- a/tests/data/template-after.yml
++++ b/test
+@@ -5 +5 @@
+-      TableName: test2
+\ No newline at end of file
++      TableName: test
+\ No newline at end of file
+"#
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn diff_template_yields_diff() -> Result<(), Box<dyn StdError>> {
+        let diff = diff_template(
+            "test",
+            &PathBuf::from("tests/data/template-after.yml"),
+            None,
+            include_str!("../tests/data/template-after.yml"),
+            include_str!("../tests/data/template-before.yml").into(),
+            &DiffOptions::default(),
+        )?;
+        assert_eq!(
+            diff,
+            r#"--- a/test
++++ b/tests/data/template-after.yml
+@@ -2,4 +2,4 @@
+   DynamodbTable:
+     Type: AWS::DynamoDB::Table
+     Properties:
+-      TableName: test
+\ No newline at end of file
++      TableName: test2
+\ No newline at end of file
+"#
+        );
+        Ok(())
+    }
+}
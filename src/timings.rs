@@ -0,0 +1,85 @@
+use futures::{future, Future};
+use std::{
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+/// how long each phase of a diff took
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Timings {
+    pub get_template: Duration,
+    pub create_change_set: Duration,
+    pub describe_change_set: Duration,
+    /// number of times the change set was described before it settled
+    pub describe_polls: usize,
+    pub delete_change_set: Duration,
+}
+
+/// a timed phase of a diff
+#[derive(Debug, Clone, Copy)]
+pub(crate) enum Phase {
+    GetTemplate,
+    CreateChangeSet,
+    DescribeChangeSet,
+    DeleteChangeSet,
+}
+
+impl Timings {
+    fn record(
+        &mut self,
+        phase: Phase,
+        elapsed: Duration,
+    ) {
+        match phase {
+            Phase::GetTemplate => self.get_template += elapsed,
+            Phase::CreateChangeSet => self.create_change_set += elapsed,
+            Phase::DescribeChangeSet => self.describe_change_set += elapsed,
+            Phase::DeleteChangeSet => self.delete_change_set += elapsed,
+        }
+    }
+}
+
+/// timings shared between the phases of a single diff
+pub(crate) type Shared = Arc<Mutex<Timings>>;
+
+/// records the time from first poll to completion of the future `f` creates
+pub(crate) fn timed<F, T>(
+    timings: Shared,
+    phase: Phase,
+    f: F,
+) -> impl Future<Item = T::Item, Error = T::Error>
+where
+    F: FnOnce() -> T,
+    T: Future,
+{
+    future::lazy(move || {
+        let start = Instant::now();
+        f().then(move |result| {
+            if let Ok(mut timings) = timings.lock() {
+                timings.record(phase, start.elapsed());
+            }
+            result
+        })
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn timed_records_phases() {
+        let timings = Shared::default();
+        let result = timed(timings.clone(), Phase::GetTemplate, || {
+            future::lazy(|| {
+                std::thread::sleep(Duration::from_millis(5));
+                future::ok::<_, ()>(1)
+            })
+        })
+        .wait();
+        assert_eq!(result, Ok(1));
+        let timings = timings.lock().unwrap();
+        assert!(timings.get_template >= Duration::from_millis(5));
+        assert_eq!(timings.delete_change_set, Duration::default());
+    }
+}
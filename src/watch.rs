@@ -0,0 +1,72 @@
+use futures::{
+    future::{self, Loop},
+    Future,
+};
+use std::{
+    fs, io,
+    path::PathBuf,
+    time::{Duration, Instant, SystemTime},
+};
+use tokio::timer::Delay;
+
+/// interval between checks on watched files
+const POLL_INTERVAL: Duration = Duration::from_millis(250);
+
+/// the last modified time of each file, or None for files that can't be read
+pub fn modified(paths: &[PathBuf]) -> Vec<Option<SystemTime>> {
+    paths
+        .iter()
+        .map(|path| fs::metadata(path).and_then(|meta| meta.modified()).ok())
+        .collect()
+}
+
+/// resolves once any of the files was modified since `since`. editors often write a
+/// file in several steps, so changes are debounced until the files are left untouched
+/// for a poll interval
+pub fn changed(
+    paths: Vec<PathBuf>,
+    since: Vec<Option<SystemTime>>,
+) -> impl Future<Item = (), Error = io::Error> + Send {
+    future::loop_fn((since, false), move |(last, dirty)| {
+        let paths = paths.clone();
+        Delay::new(Instant::now() + POLL_INTERVAL)
+            .map_err(io::Error::other)
+            .map(move |_| {
+                let current = modified(&paths);
+                match (current == last, dirty) {
+                    (true, true) => Loop::Break(()),
+                    (true, false) => Loop::Continue((last, false)),
+                    _ => Loop::Continue((current, true)),
+                }
+            })
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{error::Error as StdError, fs::OpenOptions, thread};
+    use tokio::{runtime::Runtime, timer::Timeout};
+
+    #[test]
+    fn changed_resolves_after_a_file_is_modified() -> Result<(), Box<dyn StdError>> {
+        let file = tempfile::NamedTempFile::new()?;
+        let path = file.path().to_path_buf();
+        let paths = vec![path.clone()];
+        let since = modified(&paths);
+        let writer = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(50));
+            fs::write(&path, "Resources: {}")?;
+            // filesystems with coarse timestamps may not tell the write apart otherwise
+            OpenOptions::new()
+                .write(true)
+                .open(&path)?
+                .set_modified(SystemTime::now() + Duration::from_secs(2))
+        });
+        Runtime::new()?
+            .block_on(Timeout::new(changed(paths, since), Duration::from_secs(10)))
+            .map_err(|err| format!("watched file wasn't seen changing: {}", err))?;
+        writer.join().map_err(|_| "writer panicked")??;
+        Ok(())
+    }
+}
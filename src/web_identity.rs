@@ -0,0 +1,237 @@
+use crate::http;
+use chrono::{DateTime, Utc};
+use futures::{future, Future, Stream};
+use hyper::{header::CONTENT_TYPE, Body, Client, Request};
+use lazy_static::lazy_static;
+use rusoto_core::credential::{AwsCredentials, CredentialsError};
+use serde::Deserialize;
+use std::{env, fs};
+
+lazy_static! {
+    /// the session name when `AWS_ROLE_SESSION_NAME` isn't set, named once per run
+    static ref DEFAULT_SESSION_NAME: String = format!("cliff-{}", Utc::now().timestamp());
+}
+
+/// the web identity settings of the environment, as set by CI providers like GitHub Actions
+#[derive(Debug, PartialEq)]
+struct Settings {
+    token_file: String,
+    role_arn: String,
+    session_name: String,
+    /// the regional STS endpoint, when a region is configured
+    region: Option<String>,
+}
+
+/// reads web identity settings from environment variables
+fn settings<F>(var: F) -> Option<Settings>
+where
+    F: Fn(&str) -> Option<String>,
+{
+    let var = |name: &str| var(name).filter(|value| !value.trim().is_empty());
+    Some(Settings {
+        token_file: var("AWS_WEB_IDENTITY_TOKEN_FILE")?,
+        role_arn: var("AWS_ROLE_ARN")?,
+        session_name: var("AWS_ROLE_SESSION_NAME").unwrap_or_else(|| DEFAULT_SESSION_NAME.clone()),
+        region: var("AWS_REGION").or_else(|| var("AWS_DEFAULT_REGION")),
+    })
+}
+
+/// percent encodes a query parameter value
+pub(crate) fn encode(value: &str) -> String {
+    value
+        .bytes()
+        .map(|b| match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => {
+                (b as char).to_string()
+            }
+            other => format!("%{:02X}", other),
+        })
+        .collect()
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "PascalCase")]
+struct AssumeRoleWithWebIdentityResponse {
+    assume_role_with_web_identity_result: AssumeRoleWithWebIdentityResult,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "PascalCase")]
+struct AssumeRoleWithWebIdentityResult {
+    credentials: Credentials,
+}
+
+/// temporary credentials issued by STS
+#[derive(Deserialize, Debug, PartialEq)]
+#[serde(rename_all = "PascalCase")]
+pub(crate) struct Credentials {
+    access_key_id: String,
+    secret_access_key: String,
+    session_token: String,
+    expiration: String,
+}
+
+impl From<Credentials> for AwsCredentials {
+    fn from(credentials: Credentials) -> Self {
+        AwsCredentials::new(
+            credentials.access_key_id,
+            credentials.secret_access_key,
+            Some(credentials.session_token),
+            DateTime::parse_from_rfc3339(&credentials.expiration)
+                .ok()
+                .map(|at| at.with_timezone(&Utc)),
+        )
+    }
+}
+
+fn parse_credentials(body: &[u8]) -> Result<Credentials, CredentialsError> {
+    serde_xml_rs::from_reader::<_, AssumeRoleWithWebIdentityResponse>(body)
+        .map(|response| response.assume_role_with_web_identity_result.credentials)
+        .map_err(|err| CredentialsError::new(err.to_string()))
+}
+
+/// resolves credentials by assuming `AWS_ROLE_ARN` with the web identity token in
+/// `AWS_WEB_IDENTITY_TOKEN_FILE`. AssumeRoleWithWebIdentity requests aren't signed,
+/// so no other credentials are needed
+pub(crate) fn credentials(
+) -> Box<dyn Future<Item = AwsCredentials, Error = CredentialsError> + Send> {
+    let settings = match settings(|name| env::var(name).ok()) {
+        Some(settings) => settings,
+        _ => {
+            return Box::new(future::err(CredentialsError::new(
+                "AWS_WEB_IDENTITY_TOKEN_FILE and AWS_ROLE_ARN must both be set",
+            )))
+        }
+    };
+    let token = match fs::read_to_string(&settings.token_file) {
+        Ok(token) => token,
+        Err(err) => {
+            return Box::new(future::err(CredentialsError::new(format!(
+                "Failed to read web identity token {}: {}",
+                settings.token_file, err
+            ))))
+        }
+    };
+    let connector = match http::connector() {
+        Ok(connector) => connector,
+        Err(err) => return Box::new(future::err(CredentialsError::new(err.to_string()))),
+    };
+    let endpoint = match &settings.region {
+        Some(region) => format!("https://sts.{}.amazonaws.com/", region),
+        _ => "https://sts.amazonaws.com/".to_string(),
+    };
+    let request = Request::post(endpoint)
+        .header(CONTENT_TYPE, "application/x-www-form-urlencoded")
+        .body(Body::from(format!(
+            "Action=AssumeRoleWithWebIdentity&Version=2011-06-15&RoleArn={}&RoleSessionName={}&WebIdentityToken={}",
+            encode(&settings.role_arn),
+            encode(&settings.session_name),
+            encode(token.trim())
+        )));
+    let request = match request {
+        Ok(request) => request,
+        Err(err) => return Box::new(future::err(CredentialsError::new(err.to_string()))),
+    };
+    Box::new(
+        Client::builder()
+            .build::<_, Body>(connector)
+            .request(request)
+            .map_err(|err| CredentialsError::new(err.to_string()))
+            .and_then(|response| {
+                let status = response.status();
+                response
+                    .into_body()
+                    .concat2()
+                    .map_err(|err| CredentialsError::new(err.to_string()))
+                    .and_then(move |body| {
+                        if !status.is_success() {
+                            return Err(CredentialsError::new(format!(
+                                "Failed to assume role with web identity: {} {}",
+                                status,
+                                String::from_utf8_lossy(&body)
+                            )));
+                        }
+                        Ok(parse_credentials(&body)?.into())
+                    })
+            }),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn settings_requires_token_file_and_role() {
+        let vars = |name: &str| match name {
+            "AWS_WEB_IDENTITY_TOKEN_FILE" => Some("/tmp/token".to_string()),
+            "AWS_ROLE_ARN" => Some("arn:aws:iam::123456789012:role/ci".to_string()),
+            "AWS_ROLE_SESSION_NAME" => Some("build".to_string()),
+            "AWS_REGION" => Some("eu-west-1".to_string()),
+            _ => None,
+        };
+        assert_eq!(
+            settings(vars),
+            Some(Settings {
+                token_file: "/tmp/token".into(),
+                role_arn: "arn:aws:iam::123456789012:role/ci".into(),
+                session_name: "build".into(),
+                region: Some("eu-west-1".into()),
+            })
+        );
+        assert_eq!(
+            settings(|name| vars(name).filter(|_| name != "AWS_ROLE_ARN")),
+            None
+        );
+    }
+
+    #[test]
+    fn settings_names_one_default_session_per_run() {
+        let vars = |name: &str| match name {
+            "AWS_WEB_IDENTITY_TOKEN_FILE" => Some("/tmp/token".to_string()),
+            "AWS_ROLE_ARN" => Some("arn:aws:iam::123456789012:role/ci".to_string()),
+            _ => None,
+        };
+        let session_name = settings(vars).map(|settings| settings.session_name);
+        assert!(session_name
+            .as_deref()
+            .is_some_and(|name| name.starts_with("cliff-")));
+        assert_eq!(
+            settings(vars).map(|settings| settings.session_name),
+            session_name
+        );
+    }
+
+    #[test]
+    fn encode_escapes_reserved_characters() {
+        assert_eq!(
+            encode("arn:aws:iam::123456789012:role/ci"),
+            "arn%3Aaws%3Aiam%3A%3A123456789012%3Arole%2Fci"
+        );
+        assert_eq!(encode("eyJ.abc-_~"), "eyJ.abc-_~");
+    }
+
+    #[test]
+    fn parse_credentials_reads_sts_response() {
+        let body = r#"<AssumeRoleWithWebIdentityResponse xmlns="https://sts.amazonaws.com/doc/2011-06-15/">
+  <AssumeRoleWithWebIdentityResult>
+    <SubjectFromWebIdentityToken>repo:meetup/cliff:ref:refs/heads/master</SubjectFromWebIdentityToken>
+    <Credentials>
+      <AccessKeyId>ASIAEXAMPLE</AccessKeyId>
+      <SecretAccessKey>secret</SecretAccessKey>
+      <SessionToken>token</SessionToken>
+      <Expiration>2030-01-01T00:00:00Z</Expiration>
+    </Credentials>
+  </AssumeRoleWithWebIdentityResult>
+</AssumeRoleWithWebIdentityResponse>"#;
+        assert_eq!(
+            parse_credentials(body.as_bytes()).ok(),
+            Some(Credentials {
+                access_key_id: "ASIAEXAMPLE".into(),
+                secret_access_key: "secret".into(),
+                session_token: "token".into(),
+                expiration: "2030-01-01T00:00:00Z".into(),
+            })
+        );
+    }
+}
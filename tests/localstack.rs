@@ -0,0 +1,137 @@
+//! End to end diffs against [LocalStack](https://github.com/localstack/localstack)
+//!
+//! Run with `LOCALSTACK_ENDPOINT=http://localhost:4566 cargo test --features localstack`.
+//! Tests are skipped when no endpoint is configured
+#![cfg(feature = "localstack")]
+use cliff::{changeset::Status, http, Differ};
+use rusoto_cloudformation::{
+    CloudFormation, CloudFormationClient, CreateStackInput, DeleteStackInput, DescribeStacksInput,
+};
+use rusoto_core::{credential::StaticProvider, Region};
+use rusoto_s3::S3Client;
+use rusoto_ssm::SsmClient;
+use std::{env, error::Error as StdError, fs, thread::sleep, time::Duration};
+use tokio::runtime::Runtime;
+
+const BEFORE: &str = r#"
+Resources:
+  Parameter:
+    Type: AWS::SSM::Parameter
+    Properties:
+      Type: String
+      Value: before
+"#;
+
+const AFTER: &str = r#"
+Resources:
+  Parameter:
+    Type: AWS::SSM::Parameter
+    Properties:
+      Type: String
+      Value: after
+  Topic:
+    Type: AWS::SNS::Topic
+"#;
+
+fn region() -> Option<Region> {
+    env::var("LOCALSTACK_ENDPOINT")
+        .ok()
+        .filter(|endpoint| !endpoint.trim().is_empty())
+        .map(|endpoint| Region::Custom {
+            name: "us-east-1".into(),
+            endpoint,
+        })
+}
+
+fn credentials() -> StaticProvider {
+    StaticProvider::new_minimal("test".into(), "test".into())
+}
+
+/// waits for a stack operation to finish, yielding the stack's final status
+fn wait_for_stack(
+    cf: &CloudFormationClient,
+    stack_name: &str,
+) -> Result<String, Box<dyn StdError>> {
+    loop {
+        let status = cf
+            .describe_stacks(DescribeStacksInput {
+                stack_name: Some(stack_name.into()),
+                ..DescribeStacksInput::default()
+            })
+            .sync()?
+            .stacks
+            .unwrap_or_default()
+            .into_iter()
+            .next()
+            .map(|stack| stack.stack_status)
+            .unwrap_or_default();
+        if !status.ends_with("_IN_PROGRESS") {
+            return Ok(status);
+        }
+        sleep(Duration::from_secs(1));
+    }
+}
+
+#[test]
+fn diff_reports_changes_to_a_live_stack() -> Result<(), Box<dyn StdError>> {
+    let region = match region() {
+        Some(region) => region,
+        _ => {
+            eprintln!("skipping: LOCALSTACK_ENDPOINT is not set");
+            return Ok(());
+        }
+    };
+    let cf = CloudFormationClient::new_with(http::dispatcher()?, credentials(), region.clone());
+    let stack_name = format!("cliff-{}", std::process::id());
+    cf.create_stack(CreateStackInput {
+        stack_name: stack_name.clone(),
+        template_body: Some(BEFORE.into()),
+        ..CreateStackInput::default()
+    })
+    .sync()?;
+    assert_eq!(wait_for_stack(&cf, &stack_name)?, "CREATE_COMPLETE");
+
+    let dir = tempfile::tempdir()?;
+    let filename = dir.path().join("template.yml");
+    fs::write(&filename, AFTER)?;
+    let result = Differ::builder()
+        .client(cf.clone())
+        .ssm_client(SsmClient::new_with(
+            http::dispatcher()?,
+            credentials(),
+            region.clone(),
+        ))
+        .s3_client(S3Client::new_with(
+            http::dispatcher()?,
+            credentials(),
+            region.clone(),
+        ))
+        .region(region)
+        .stack_name(stack_name.as_str())
+        .template(filename)
+        .build()
+        .map_err(Box::<dyn StdError>::from)
+        .and_then(|differ| Ok(Runtime::new()?.block_on(differ.diff())?));
+
+    cf.delete_stack(DeleteStackInput {
+        stack_name: stack_name.clone(),
+        ..DeleteStackInput::default()
+    })
+    .sync()?;
+
+    let diff = result?;
+    assert_eq!(diff.result.status, Status::Complete);
+    let mut changes = diff
+        .result
+        .changes
+        .iter()
+        .map(|change| (change.action.as_str(), change.logical_resource_id.as_str()))
+        .collect::<Vec<_>>();
+    changes.sort();
+    assert_eq!(changes, vec![("Add", "Topic"), ("Modify", "Parameter")]);
+    assert!(diff
+        .template
+        .unwrap_or_default()
+        .contains("+      Value: after"));
+    Ok(())
+}